@@ -0,0 +1,477 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use owo_colors::OwoColorize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::AppError,
+    store::Store,
+    types::{
+        backup::{Backup, BackupBlog, BackupSource, BackupUser},
+        blog::Text,
+        comment::Comment,
+    },
+};
+
+// bumped whenever the layout changes, a restore refuses archives newer than it knows.
+// 2: a comment's approved is null while it's pending, in 1 false meant pending
+// 3: users.jsonl, the earlier formats have no users
+// 4: markdown_sources.jsonl, which file each imported post came from
+const FORMAT: u32 = 4;
+
+const MANIFEST: &str = "manifest.json";
+const BLOGS: &str = "blogs.jsonl";
+const TEXTS: &str = "texts.jsonl";
+const COMMENTS: &str = "comments.jsonl";
+const USERS: &str = "users.jsonl";
+const SOURCES: &str = "markdown_sources.jsonl";
+const MEDIA: &str = "media";
+
+// the first file in the archive, everything else is checked against it
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    version: String,
+    created_at: DateTime<Utc>,
+    counts: Counts,
+    // archive path -> sha256
+    files: BTreeMap<String, String>,
+    // blog image -> archive path, only for images that were files in the media directory
+    media: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Counts {
+    blogs: usize,
+    texts: usize,
+    comments: usize,
+    tags: usize,
+    #[serde(default)]
+    users: usize,
+    #[serde(default)]
+    sources: usize,
+}
+
+impl Counts {
+    fn of(backup: &Backup) -> Self {
+        Counts {
+            blogs: backup.blogs.len(),
+            texts: backup.texts.len(),
+            comments: backup.comments.len(),
+            tags: backup.blogs.iter().map(|blog| blog.tags.len()).sum(),
+            users: backup.users.len(),
+            sources: backup.sources.len(),
+        }
+    }
+}
+
+// relative path in the media directory -> contents
+type Media = Vec<(PathBuf, Vec<u8>)>;
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn json_lines<T: Serialize>(rows: &[T]) -> Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    for row in rows {
+        serde_json::to_writer(&mut lines, row)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+fn parse_lines<T: DeserializeOwned>(name: &str, bytes: &[u8]) -> Result<Vec<T>> {
+    bytes
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(number, line)| {
+            serde_json::from_str(&line?).with_context(|| format!("{name} line {}", number + 1))
+        })
+        .collect()
+}
+
+// images are either urls, kept as they are, or paths served from the media directory
+pub fn media_path(image: &str) -> Option<PathBuf> {
+    if image.contains("://") {
+        return None;
+    }
+    let path = Path::new(image.trim_start_matches('/'));
+    // nothing outside the media directory gets read or written
+    match path.components().all(|part| matches!(part, Component::Normal(_))) {
+        true => Some(path.to_path_buf()),
+        false => None,
+    }
+}
+
+pub async fn backup(store: &Store, output: Option<&str>, media_dir: &str) -> Result<(), AppError> {
+    let backup = store.backup().await?;
+    let output = output.map(String::from).unwrap_or_else(|| {
+        format!("backup-{}.tar.gz", Utc::now().format("%Y%m%d-%H%M%S"))
+    });
+    let manifest = write_archive(&backup, &output, Path::new(media_dir))
+        .map_err(|e| AppError::command_failed(format!("couldn't write the backup: {e:#}")))?;
+
+    println!(
+        "{} {} ({} posts, {} texts, {} comments, {} tags, {} users, {} markdown sources, {} media files)",
+        "Backed up to".bright_green(),
+        output,
+        manifest.counts.blogs,
+        manifest.counts.texts,
+        manifest.counts.comments,
+        manifest.counts.tags,
+        manifest.counts.users,
+        manifest.counts.sources,
+        manifest.media.len()
+    );
+    Ok(())
+}
+
+fn write_archive(backup: &Backup, output: &str, media_dir: &Path) -> Result<Manifest> {
+    let mut files = vec![
+        (BLOGS.to_string(), json_lines(&backup.blogs)?),
+        (TEXTS.to_string(), json_lines(&backup.texts)?),
+        (COMMENTS.to_string(), json_lines(&backup.comments)?),
+        (USERS.to_string(), json_lines(&backup.users)?),
+        (SOURCES.to_string(), json_lines(&backup.sources)?),
+    ];
+
+    let mut media = BTreeMap::new();
+    for image in backup.blogs.iter().filter_map(|blog| blog.image.as_deref()) {
+        let Some(path) = media_path(image) else {
+            continue;
+        };
+        if media.contains_key(image) {
+            continue;
+        }
+        let file = media_dir.join(&path);
+        if !file.is_file() {
+            // still restorable, the post just keeps pointing at a missing image like it did here
+            println!("{} {}", "Not in the media directory:".bright_yellow(), image);
+            continue;
+        }
+        let bytes = fs::read(&file).with_context(|| format!("couldn't read {}", file.display()))?;
+        let archived = format!("{MEDIA}/{}", path.to_string_lossy());
+        media.insert(image.to_string(), archived.clone());
+        if !files.iter().any(|(name, _)| *name == archived) {
+            files.push((archived, bytes));
+        }
+    }
+
+    let manifest = Manifest {
+        format: FORMAT,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Utc::now(),
+        counts: Counts::of(backup),
+        files: files
+            .iter()
+            .map(|(name, bytes)| (name.clone(), sha256(bytes)))
+            .collect(),
+        media,
+    };
+
+    // it holds password hashes, only the owner gets to read it
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let archive = options
+        .open(output)
+        .with_context(|| format!("couldn't create {output}"))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(archive, Compression::default()));
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+    for (name, bytes) in std::iter::once((MANIFEST, &manifest_json))
+        .chain(files.iter().map(|(name, bytes)| (name.as_str(), bytes)))
+    {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(manifest.created_at.timestamp().max(0) as u64);
+        tar.append_data(&mut header, name, bytes.as_slice())?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(manifest)
+}
+
+pub async fn restore(store: &Store, input: &str, media_dir: &str) -> Result<(), AppError> {
+    let failed = |e: anyhow::Error| AppError::command_failed(format!("couldn't restore {input}: {e:#}"));
+    let (manifest, backup, media) = read_archive(input).map_err(failed)?;
+
+    let report = store.restore(backup).await?;
+    // read back what landed, a restore that lost rows shouldn't look like a success
+    let restored = Counts::of(&store.backup().await?);
+    if restored != manifest.counts {
+        return Err(AppError::command_failed(format!(
+            "the database has {restored:?} after the restore, the backup has {:?}",
+            manifest.counts
+        )));
+    }
+    let written = write_media(&media, Path::new(media_dir)).map_err(failed)?;
+
+    println!(
+        "{} {} posts, {} texts, {} comments, {} tags, {} users, {} markdown sources and {} media files from {}",
+        "Restored".bright_green(),
+        report.blogs,
+        report.texts,
+        report.comments,
+        report.tags,
+        report.users,
+        report.sources,
+        written,
+        input
+    );
+    let moved = report.blog_ids.iter().filter(|(old, new)| old != new).count();
+    if moved > 0 {
+        println!("{moved} posts have a new id");
+    }
+    Ok(())
+}
+
+// every check that doesn't need the database happens here, before anything is written
+fn read_archive(input: &str) -> Result<(Manifest, Backup, Media)> {
+    let archive = File::open(input).with_context(|| format!("couldn't open {input}"))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(archive));
+    let mut entries = BTreeMap::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        entries.insert(name, bytes);
+    }
+
+    let manifest: Manifest = serde_json::from_slice(
+        &entries
+            .remove(MANIFEST)
+            .ok_or_else(|| anyhow!("there's no {MANIFEST}, this isn't a backup"))?,
+    )
+    .context("the manifest is unreadable")?;
+    if manifest.format > FORMAT {
+        bail!(
+            "it was made by version {} in format {}, this version only reads up to {FORMAT}",
+            manifest.version,
+            manifest.format
+        );
+    }
+
+    for (name, checksum) in &manifest.files {
+        let bytes = entries
+            .get(name)
+            .ok_or_else(|| anyhow!("{name} is missing from the archive"))?;
+        if sha256(bytes) != *checksum {
+            bail!("{name} doesn't match its checksum");
+        }
+    }
+    if let Some(extra) = entries.keys().find(|name| !manifest.files.contains_key(*name)) {
+        bail!("{extra} isn't in the manifest");
+    }
+
+    let mut backup = Backup {
+        blogs: parse_lines::<BackupBlog>(BLOGS, &entries[BLOGS])?,
+        texts: parse_lines::<Text>(TEXTS, &entries[TEXTS])?,
+        comments: parse_lines::<Comment>(COMMENTS, &entries[COMMENTS])?,
+        users: match entries.get(USERS) {
+            Some(bytes) => parse_lines::<BackupUser>(USERS, bytes)?,
+            None if manifest.format < 3 => Vec::new(),
+            None => bail!("{USERS} is missing from the archive"),
+        },
+        sources: match entries.get(SOURCES) {
+            Some(bytes) => parse_lines::<BackupSource>(SOURCES, bytes)?,
+            None if manifest.format < 4 => Vec::new(),
+            None => bail!("{SOURCES} is missing from the archive"),
+        },
+    };
+    if manifest.format < 2 {
+        for comment in &mut backup.comments {
+            comment.approved = comment.approved.filter(|approved| *approved);
+        }
+    }
+    if Counts::of(&backup) != manifest.counts {
+        bail!(
+            "it holds {:?} but the manifest says {:?}",
+            Counts::of(&backup),
+            manifest.counts
+        );
+    }
+
+    let mut blog_ids = HashSet::new();
+    for blog in &backup.blogs {
+        if !blog_ids.insert(blog.id) {
+            bail!("post {} is in it twice", blog.id);
+        }
+    }
+    let mut texts = HashSet::new();
+    for text in &backup.texts {
+        if !blog_ids.contains(&text.blog_id) {
+            bail!("there's a text for post {}, which isn't in it", text.blog_id);
+        }
+        if !texts.insert(text.blog_id) {
+            bail!("post {} has two texts", text.blog_id);
+        }
+    }
+    if let Some(comment) = backup
+        .comments
+        .iter()
+        .find(|comment| !blog_ids.contains(&comment.blog_id))
+    {
+        bail!(
+            "comment {} belongs to post {}, which isn't in it",
+            comment.id,
+            comment.blog_id
+        );
+    }
+
+    let mut sources = HashSet::new();
+    let mut slugs = HashSet::new();
+    for source in &backup.sources {
+        if !blog_ids.contains(&source.blog_id) {
+            bail!("{} was imported as post {}, which isn't in it", source.source, source.blog_id);
+        }
+        if !sources.insert(&source.source) || !slugs.insert(&source.slug) {
+            bail!("{} is in it twice", source.source);
+        }
+    }
+
+    let mut usernames = HashSet::new();
+    if let Some(user) = backup.users.iter().find(|user| !usernames.insert(&user.username)) {
+        bail!("the username {:?} is in it twice", user.username);
+    }
+
+    let mut media = Vec::new();
+    for (image, archived) in &manifest.media {
+        let path = media_path(image).ok_or_else(|| anyhow!("\"{image}\" isn't a media path"))?;
+        let bytes = entries
+            .get(archived)
+            .ok_or_else(|| anyhow!("{archived} is missing from the archive"))?;
+        media.push((path, bytes.clone()));
+    }
+    Ok((manifest, backup, media))
+}
+
+// files that are already there stay untouched, the media directory is shared with the frontend
+fn write_media(media: &Media, media_dir: &Path) -> Result<usize> {
+    let mut written = 0;
+    for (path, bytes) in media {
+        let file = media_dir.join(path);
+        if file.exists() {
+            if fs::read(&file).map(|existing| existing != *bytes).unwrap_or(true) {
+                println!("{} {}", "Kept the existing".bright_yellow(), file.display());
+            }
+            continue;
+        }
+        if let Some(folder) = file.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(&file, bytes).with_context(|| format!("couldn't write {}", file.display()))?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::memory::MemoryStore,
+        types::{blog::NewBlog, markdown::MarkdownPost, user::NewUser},
+    };
+
+    #[tokio::test]
+    async fn users_survive_a_round_trip() {
+        let source = Store::from(MemoryStore::new());
+        source
+            .post_blog(NewBlog {
+                image: None,
+                author: "ada".to_string(),
+                text: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+        source
+            .create_user(NewUser {
+                username: "ada".to_string(),
+                password_hash: "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA".to_string(),
+                role: "editor".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let output = std::env::temp_dir().join(format!("backup-test-{}.tar.gz", std::process::id()));
+        let output = output.to_string_lossy().into_owned();
+        let media_dir = std::env::temp_dir();
+        let written = write_archive(&source.backup().await.unwrap(), &output, &media_dir).unwrap();
+        assert_eq!(written.counts.users, 1);
+
+        let (manifest, backup, _) = read_archive(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(manifest.format, FORMAT);
+
+        let target = Store::from(MemoryStore::new());
+        let report = target.restore(backup).await.unwrap();
+        assert_eq!(report.users, 1);
+        let restored = target.backup().await.unwrap().users;
+        let original = source.backup().await.unwrap().users;
+        assert_eq!(restored, original);
+
+        // a database with users in it isn't empty
+        let again = target.backup().await.unwrap();
+        assert!(target.restore(again).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn markdown_sources_follow_their_posts() {
+        let source = Store::from(MemoryStore::new());
+        // blog 1 is gone before the import, so the imported post moves to a new id on restore
+        let first = source
+            .post_blog(NewBlog {
+                image: None,
+                author: "ada".to_string(),
+                text: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+        source.delete_blog(first.id.0).await.unwrap();
+        source
+            .import_markdown(vec![MarkdownPost {
+                source: "posts/hello.md".to_string(),
+                slug: "hello".to_string(),
+                image: None,
+                author: "ada".to_string(),
+                date: None,
+                archived: false,
+                tags: Vec::new(),
+                text: "hello".to_string(),
+            }])
+            .await
+            .unwrap();
+
+        let output = std::env::temp_dir().join(format!("backup-sources-{}.tar.gz", std::process::id()));
+        let output = output.to_string_lossy().into_owned();
+        write_archive(&source.backup().await.unwrap(), &output, &std::env::temp_dir()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&output).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let (_, backup, _) = read_archive(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        let target = Store::from(MemoryStore::new());
+        let report = target.restore(backup).await.unwrap();
+        assert_eq!(report.sources, 1);
+        let restored = target.backup().await.unwrap().sources;
+        assert_eq!(restored[0].source, "posts/hello.md");
+        assert_eq!(restored[0].blog_id, report.blog_ids[&2]);
+        assert_eq!(restored[0].blog_id, 1);
+    }
+}
@@ -0,0 +1,31 @@
+use clap::ArgMatches;
+use owo_colors::OwoColorize;
+
+use crate::{
+    error::AppError,
+    store::Store,
+    types::{
+        bulk::{CommentAction, CommentFilter, Selection},
+        comment::Moderation,
+    },
+};
+
+// spam is what a moderator rejected, narrowed down by the optional --blog, --author and --contains.
+// pending comments are never touched, they haven't been looked at yet
+pub async fn purge_spam(store: &Store, purge: &ArgMatches) -> Result<(), AppError> {
+    let filter = CommentFilter {
+        blog_id: purge.get_one::<i64>("blog").copied(),
+        author: purge.get_one::<String>("author").cloned(),
+        contains: purge.get_one::<String>("contains").cloned(),
+        moderation: Some(Moderation::rejected),
+    };
+    let report = store
+        .bulk_comments(CommentAction::delete, Selection::filter(filter))
+        .await?;
+    println!(
+        "{} {}",
+        "Deleted rejected comments:".bright_green(),
+        report.succeeded
+    );
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use crate::{
+    error::AppError,
+    utils::setting::{check_config, redacted, save_config, ServerConfig, DEFAULT_CONFIG_FILE},
+};
+use owo_colors::OwoColorize;
+use std::path::Path;
+
+// config init, runs before the config is loaded so a missing --config-file isn't an error
+pub fn init(config_file: Option<&str>, force: bool) -> Result<(), AppError> {
+    let config_path = config_file.unwrap_or(DEFAULT_CONFIG_FILE);
+    if Path::new(config_path).exists() && !force {
+        return Err(AppError::command_failed(format!(
+            "there's already a config file at {config_path}, --force overwrites it"
+        )));
+    }
+    save_config(config_file, &ServerConfig::default())
+        .map_err(|e| AppError::command_failed(format!("couldn't write the config: {e:#}")))
+}
+
+pub fn show(config: &ServerConfig) -> Result<(), AppError> {
+    match toml::to_string_pretty(&redacted(config)) {
+        Ok(effective) => {
+            println!("{}\n{effective}", "# effective config".bright_black());
+            Ok(())
+        }
+        Err(e) => Err(AppError::command_failed(format!("couldn't print the config: {e}"))),
+    }
+}
+
+// check_config prints the problems itself
+pub fn check(config: &ServerConfig) -> Result<(), AppError> {
+    match check_config(config) {
+        true => Ok(()),
+        false => Err(AppError::invalid_config("the config has problems".to_string())),
+    }
+}
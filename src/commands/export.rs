@@ -0,0 +1,459 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs,
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use owo_colors::OwoColorize;
+use pulldown_cmark::{html, Options, Parser};
+use serde::Serialize;
+
+use super::{backup::media_path, import::slugify};
+use crate::{error::AppError, store::Store};
+
+const MEDIA: &str = "media";
+
+// inlined into every page so the directory works from any host, or straight from the disk
+const STYLE: &str = "body{max-width:46rem;margin:2rem auto;padding:0 1rem;font:17px/1.6 system-ui,sans-serif;color:#222}\
+a{color:#0b5cad}header,footer{color:#666;font-size:.9rem}nav a{margin-right:1rem}\
+.meta{color:#666;font-size:.9rem}.tags a{margin-right:.5rem}img{max-width:100%}\
+pre{overflow-x:auto;background:#f5f5f5;padding:.75rem}ul.posts{list-style:none;padding:0}ul.posts li{margin:1rem 0}";
+
+// one published post, ready to be written out
+struct Page {
+    id: i64,
+    // file name without the extension, the id keeps it unique and stable across exports
+    name: String,
+    title: String,
+    author: String,
+    date: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    tags: Vec<String>,
+    image: Option<String>,
+    // the text without its title heading
+    markdown: String,
+    html: String,
+}
+
+// the same fields `import markdown` reads
+#[derive(Serialize)]
+struct FrontMatter<'p> {
+    title: &'p str,
+    date: String,
+    author: &'p str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tags: &'p [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover: Option<&'p str>,
+}
+
+pub struct ExportOptions<'o> {
+    pub output: &'o str,
+    pub title: &'o str,
+    // without it every link is relative, the feed included
+    pub base_url: Option<&'o str>,
+    pub media_dir: &'o str,
+    pub force: bool,
+}
+
+pub async fn export(store: &Store, options: ExportOptions<'_>) -> Result<(), AppError> {
+    if let Some(url) = options.base_url {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(AppError::command_failed(format!(
+                "\"{url}\" isn't a url like https://blog.example.com"
+            )));
+        }
+    }
+    // archived posts and comments stay out, the export is what readers can see
+    let mut pages = store
+        .published_posts()
+        .await?
+        .into_iter()
+        .map(|post| {
+            let (title, markdown) = split_title(&post.text);
+            let title = title.unwrap_or_else(|| format!("Post {}", post.id));
+            let name = match slugify(&title) {
+                slug if slug.is_empty() => post.id.to_string(),
+                slug => format!("{}-{slug}", post.id),
+            };
+            Page {
+                id: post.id,
+                name,
+                title,
+                author: post.author,
+                date: post.date,
+                updated_at: post.updated_at,
+                tags: post.tags,
+                image: post.image,
+                html: render(&markdown),
+                markdown,
+            }
+        })
+        .collect::<Vec<_>>();
+    // newest first everywhere
+    pages.sort_by(|a, b| b.date.cmp(&a.date).then(b.id.cmp(&a.id)));
+
+    let (tags, media) = write_site(&pages, &options).map_err(|e| {
+        AppError::command_failed(format!("couldn't export to {}: {e:#}", options.output))
+    })?;
+    println!(
+        "{} {} posts, {} tags and {} media files to {}",
+        "Exported".bright_green(),
+        pages.len(),
+        tags,
+        media,
+        options.output
+    );
+    Ok(())
+}
+
+// "# Title\n\nbody" -> (Some("Title"), "body"), the import puts the title there
+fn split_title(text: &str) -> (Option<String>, String) {
+    let text = text.trim_start();
+    match text.strip_prefix("# ") {
+        Some(rest) => {
+            let (title, body) = rest.split_once('\n').unwrap_or((rest, ""));
+            (Some(title.trim().to_string()), body.trim().to_string())
+        }
+        None => (None, text.trim().to_string()),
+    }
+}
+
+fn render(markdown: &str) -> String {
+    let mut rendered = String::new();
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    html::push_html(&mut rendered, Parser::new_ext(markdown, options));
+    rendered
+}
+
+// returns how many tag pages and media files were written
+fn write_site(pages: &[Page], options: &ExportOptions) -> Result<(usize, usize)> {
+    let output = Path::new(options.output);
+    let occupied = fs::read_dir(output).is_ok_and(|mut entries| entries.next().is_some());
+    if occupied && !options.force {
+        bail!("the directory isn't empty, --force writes into it anyway");
+    }
+    for dir in ["posts", "tags", MEDIA] {
+        fs::create_dir_all(output.join(dir))?;
+    }
+    let write = |path: &str, content: &str| {
+        fs::write(output.join(path), content).with_context(|| format!("couldn't write {path}"))
+    };
+
+    // cover images from the media directory are copied, urls are left as they are
+    let mut images = HashMap::new();
+    for image in pages.iter().filter_map(|page| page.image.as_deref()) {
+        let Some(path) = media_path(image) else {
+            continue;
+        };
+        if images.contains_key(image) {
+            continue;
+        }
+        let file = Path::new(options.media_dir).join(&path);
+        if !file.is_file() {
+            println!("{} {}", "Not in the media directory:".bright_yellow(), image);
+            continue;
+        }
+        let copy = output.join(MEDIA).join(&path);
+        if let Some(folder) = copy.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::copy(&file, &copy).with_context(|| format!("couldn't copy {}", file.display()))?;
+        images.insert(image, format!("{MEDIA}/{}", path.to_string_lossy()));
+    }
+    // a copied image is linked relative to the page, anything else as it was
+    let cover = |page: &Page, root: &str| {
+        page.image.as_deref().map(|image| match images.get(image) {
+            Some(copied) => format!("{root}{copied}"),
+            None => image.to_string(),
+        })
+    };
+
+    // tags that only differ in case or punctuation share a page
+    let mut tags = BTreeMap::<String, (String, Vec<&Page>)>::new();
+    for page in pages {
+        for tag in &page.tags {
+            tags.entry(slugify(tag))
+                .or_insert_with(|| (tag.clone(), Vec::new()))
+                .1
+                .push(page);
+        }
+    }
+    tags.retain(|slug, _| !slug.is_empty());
+
+    for page in pages {
+        let front = FrontMatter {
+            title: &page.title,
+            date: page.date.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            author: &page.author,
+            tags: &page.tags,
+            cover: page.image.as_deref(),
+        };
+        write(
+            &format!("posts/{}.md", page.name),
+            &format!("---\n{}---\n\n{}\n", serde_yaml::to_string(&front)?, page.markdown),
+        )?;
+
+        let mut body = String::new();
+        writeln!(body, "<article>\n<h1>{}</h1>", escape(&page.title))?;
+        writeln!(body, "{}", meta(page, "../"))?;
+        if let Some(image) = cover(page, "../") {
+            writeln!(body, "<img src=\"{}\" alt=\"\">", escape(&image))?;
+        }
+        writeln!(body, "{}</article>", page.html)?;
+        writeln!(
+            body,
+            "<p><a href=\"{}.md\">Markdown source</a></p>",
+            escape(&page.name)
+        )?;
+        write(
+            &format!("posts/{}.html", page.name),
+            &layout(&page.title, options.title, "../", &body),
+        )?;
+    }
+
+    for (slug, (tag, tagged)) in &tags {
+        let body = format!(
+            "<h1>Tagged “{}”</h1>\n{}",
+            escape(tag),
+            post_list(tagged, "../")
+        );
+        write(&format!("tags/{slug}.html"), &layout(tag, options.title, "../", &body))?;
+    }
+
+    let mut index = format!("<h1>{}</h1>\n", escape(options.title));
+    if !tags.is_empty() {
+        index.push_str("<p class=\"tags\">");
+        for (slug, (tag, tagged)) in &tags {
+            write!(
+                index,
+                "<a href=\"tags/{slug}.html\">{}</a>({}) ",
+                escape(tag),
+                tagged.len()
+            )?;
+        }
+        index.push_str("</p>\n");
+    }
+    index.push_str(&post_list(&pages.iter().collect::<Vec<_>>(), ""));
+    write("index.html", &layout(options.title, options.title, "", &index))?;
+
+    write("feed.xml", &feed(pages, options)?)?;
+    Ok((tags.len(), images.len()))
+}
+
+fn layout(title: &str, site: &str, root: &str, body: &str) -> String {
+    let title = match title == site {
+        true => escape(site),
+        false => format!("{} · {}", escape(title), escape(site)),
+    };
+    format!(
+        "<!doctype html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+        <title>{title}</title>\n\
+        <link rel=\"alternate\" type=\"application/atom+xml\" href=\"{root}feed.xml\">\n\
+        <style>{STYLE}</style>\n</head>\n<body>\n\
+        <header><nav><a href=\"{root}index.html\">{}</a><a href=\"{root}feed.xml\">Feed</a></nav></header>\n\
+        <main>\n{body}</main>\n\
+        <footer><p>Exported {}</p></footer>\n</body>\n</html>\n",
+        escape(site),
+        chrono::Utc::now().format("%Y-%m-%d")
+    )
+}
+
+fn meta(page: &Page, root: &str) -> String {
+    let mut meta = format!(
+        "<p class=\"meta\">{} · {}",
+        page.date.format("%Y-%m-%d"),
+        escape(&page.author)
+    );
+    let links = page
+        .tags
+        .iter()
+        .map(|tag| (slugify(tag), tag))
+        .filter(|(slug, _)| !slug.is_empty())
+        .map(|(slug, tag)| format!("<a href=\"{root}tags/{slug}.html\">#{}</a>", escape(tag)))
+        .collect::<Vec<_>>();
+    if !links.is_empty() {
+        meta.push_str(&format!(" <span class=\"tags\">{}</span>", links.join(" ")));
+    }
+    meta.push_str("</p>");
+    meta
+}
+
+fn post_list(pages: &[&Page], root: &str) -> String {
+    if pages.is_empty() {
+        return "<p>There are no posts yet.</p>\n".to_string();
+    }
+    let mut list = String::from("<ul class=\"posts\">\n");
+    for page in pages {
+        list.push_str(&format!(
+            "<li><a href=\"{root}posts/{}.html\">{}</a>\n{}</li>\n",
+            escape(&page.name),
+            escape(&page.title),
+            meta(page, root)
+        ));
+    }
+    list.push_str("</ul>\n");
+    list
+}
+
+// atom, links are relative unless there's a --base-url
+fn feed(pages: &[Page], options: &ExportOptions) -> Result<String> {
+    let base = options
+        .base_url
+        .map(|url| format!("{}/", url.trim_end_matches('/')))
+        .unwrap_or_default();
+    let time = |date: &NaiveDateTime| date.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let updated = pages
+        .iter()
+        .map(|page| page.updated_at)
+        .max()
+        .unwrap_or_else(|| chrono::Utc::now().naive_utc());
+
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <title>{}</title>\n<id>{}</id>\n<updated>{}</updated>\n\
+        <link rel=\"self\" href=\"{base}feed.xml\"/>\n<link href=\"{base}index.html\"/>\n",
+        escape(options.title),
+        match options.base_url {
+            Some(_) => escape(&base),
+            None => format!("urn:blog-webserver:{}", slugify(options.title)),
+        },
+        time(&updated)
+    );
+    for page in pages {
+        let link = format!("{base}posts/{}.html", page.name);
+        write!(
+            feed,
+            "<entry>\n<title>{}</title>\n<id>{}</id>\n<link href=\"{}\"/>\n\
+            <published>{}</published>\n<updated>{}</updated>\n<author><name>{}</name></author>\n",
+            escape(&page.title),
+            match options.base_url {
+                Some(_) => escape(&link),
+                None => format!("urn:blog-webserver:{}:post:{}", slugify(options.title), page.id),
+            },
+            escape(&link),
+            time(&page.date),
+            time(&page.updated_at),
+            escape(&page.author)
+        )?;
+        for tag in &page.tags {
+            writeln!(feed, "<category term=\"{}\"/>", escape(tag))?;
+        }
+        writeln!(
+            feed,
+            "<content type=\"html\">{}</content>\n</entry>",
+            escape(&page.html)
+        )?;
+    }
+    feed.push_str("</feed>\n");
+    Ok(feed)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(id: i64, title: &str, tags: &[&str]) -> Page {
+        let date = NaiveDateTime::parse_from_str("2021-03-04 10:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        Page {
+            id,
+            name: format!("{id}-{}", slugify(title)),
+            title: title.to_string(),
+            author: "ada".to_string(),
+            date,
+            updated_at: date,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            image: None,
+            markdown: "Some *text*".to_string(),
+            html: render("Some *text*"),
+        }
+    }
+
+    fn options<'o>(output: &'o str, base_url: Option<&'o str>) -> ExportOptions<'o> {
+        ExportOptions {
+            output,
+            title: "Ada's <blog>",
+            base_url,
+            media_dir: "./there/is/no/media",
+            force: false,
+        }
+    }
+
+    #[test]
+    fn the_title_heading_is_split_off() {
+        assert_eq!(
+            split_title("\n# Hello\n\nSome text\n"),
+            (Some("Hello".to_string()), "Some text".to_string())
+        );
+        assert_eq!(split_title("# Only a title"), (Some("Only a title".to_string()), String::new()));
+        assert_eq!(split_title("## Not a title\n"), (None, "## Not a title".to_string()));
+    }
+
+    #[test]
+    fn html_special_characters_are_escaped() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(escape("plain"), "plain");
+    }
+
+    #[test]
+    fn feed_links_are_only_absolute_with_a_base_url() {
+        let pages = [page(3, "Hello & bye", &["rust"])];
+
+        let absolute = feed(&pages, &options("", Some("https://blog.example/"))).unwrap();
+        assert!(absolute.contains("<title>Ada&#39;s &lt;blog&gt;</title>"));
+        assert!(absolute.contains("<id>https://blog.example/posts/3-hello-bye.html</id>"));
+        assert!(absolute.contains("<link href=\"https://blog.example/posts/3-hello-bye.html\"/>"));
+        assert!(absolute.contains("<updated>2021-03-04T10:00:00Z</updated>"));
+        assert!(absolute.contains("<category term=\"rust\"/>"));
+        assert!(absolute.contains("<content type=\"html\">&lt;p&gt;Some &lt;em&gt;text&lt;/em&gt;&lt;/p&gt;"));
+
+        let relative = feed(&pages, &options("", None)).unwrap();
+        assert!(relative.contains("<id>urn:blog-webserver:ada-s-blog:post:3</id>"));
+        assert!(relative.contains("<link href=\"posts/3-hello-bye.html\"/>"));
+    }
+
+    #[test]
+    fn the_site_has_posts_tags_and_a_feed() {
+        let folder = std::env::temp_dir().join(format!("blog-export-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        let output = folder.to_str().unwrap();
+        let pages = [page(1, "First", &["Rust", "rust!"]), page(2, "Second", &[])];
+
+        assert_eq!(write_site(&pages, &options(output, None)).unwrap(), (1, 0));
+        for file in ["index.html", "feed.xml", "posts/1-first.md", "posts/2-second.html", "tags/rust.html"] {
+            assert!(folder.join(file).is_file(), "{file}");
+        }
+        let markdown = fs::read_to_string(folder.join("posts/1-first.md")).unwrap();
+        assert!(markdown.starts_with("---\ntitle: First\ndate: 2021-03-04T10:00:00\nauthor: ada\n"));
+        assert!(markdown.ends_with("---\n\nSome *text*\n"));
+
+        // a second export doesn't overwrite the first one by accident
+        assert!(write_site(&pages, &options(output, None)).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use owo_colors::OwoColorize;
+use serde::Deserialize;
+
+use crate::{
+    error::AppError,
+    store::Store,
+    types::markdown::{MarkdownPost, MarkdownStatus},
+};
+
+// the fields hugo and jekyll posts commonly have, everything else in the front matter is ignored
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FrontMatter {
+    title: Option<String>,
+    slug: Option<String>,
+    date: Option<String>,
+    #[serde(alias = "authors")]
+    author: Option<OneOrMany>,
+    tags: Option<OneOrMany>,
+    #[serde(alias = "cover_image", alias = "image", alias = "featured_image")]
+    cover: Option<Cover>,
+    draft: bool,
+    // jekyll's way of saying draft
+    published: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+#[allow(non_camel_case_types)]
+enum OneOrMany {
+    one(String),
+    many(Vec<String>),
+}
+
+// `cover: img/a.png` or hugo's `cover: {image: img/a.png}`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+#[allow(non_camel_case_types)]
+enum Cover {
+    path(String),
+    nested { image: String },
+}
+
+pub async fn markdown(store: &Store, dir: &str, author: Option<&str>) -> Result<(), AppError> {
+    let failed = |e: anyhow::Error| AppError::command_failed(format!("couldn't import {dir}: {e:#}"));
+    let posts = read_posts(Path::new(dir), author).map_err(failed)?;
+    if posts.is_empty() {
+        println!("There are no markdown files in {dir}");
+        return Ok(());
+    }
+
+    let imported = store.import_markdown(posts).await?;
+    for post in &imported {
+        let status = match post.status {
+            MarkdownStatus::created => format!("{:<9}", "created").bright_green().to_string(),
+            MarkdownStatus::updated => format!("{:<9}", "updated").bright_yellow().to_string(),
+            MarkdownStatus::unchanged => "unchanged".bright_black().to_string(),
+        };
+        println!("{:>6}  {}  {}", post.blog_id.cyan(), status, post.source);
+    }
+    let count = |status| imported.iter().filter(|post| post.status == status).count();
+    println!(
+        "{} {} created, {} updated, {} unchanged",
+        "Imported".bright_green(),
+        count(MarkdownStatus::created),
+        count(MarkdownStatus::updated),
+        count(MarkdownStatus::unchanged)
+    );
+    Ok(())
+}
+
+// every file is parsed before anything is written, one broken file stops the whole import
+fn read_posts(dir: &Path, author: Option<&str>) -> Result<Vec<MarkdownPost>> {
+    if !dir.is_dir() {
+        bail!("it isn't a directory");
+    }
+    let mut files = Vec::new();
+    find_markdown(dir, &mut files)?;
+    files.sort();
+
+    let mut posts = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        let source = file
+            .strip_prefix(dir)
+            .unwrap_or(&file)
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        match fs::read_to_string(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| parse_post(&source, &content, author))
+        {
+            Ok(post) => posts.push(post),
+            Err(e) => errors.push(format!("{source}: {e:#}")),
+        }
+    }
+
+    let mut slugs = HashMap::new();
+    for post in &posts {
+        if let Some(other) = slugs.insert(post.slug.as_str(), post.source.as_str()) {
+            errors.push(format!(
+                "{} and {} both have the slug \"{}\"",
+                other, post.source, post.slug
+            ));
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(posts),
+        false => Err(anyhow!("\n  {}", errors.join("\n  "))),
+    }
+}
+
+// hidden directories (.git) and hugo's _index.md list pages aren't posts
+fn find_markdown(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("couldn't read {}", dir.display()))? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') || name.starts_with("_index.") {
+            continue;
+        }
+        if path.is_dir() {
+            find_markdown(&path, files)?;
+        } else if matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("md" | "markdown")
+        ) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn parse_post(source: &str, content: &str, author: Option<&str>) -> Result<MarkdownPost> {
+    let (front, body) = split_front_matter(content)?;
+    let front: FrontMatter =
+        serde_json::from_value(front).context("the front matter has an unexpected field type")?;
+
+    // jekyll puts the date in the name: 2021-03-04-hello-world.md
+    let path = Path::new(source);
+    let mut stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    if stem == "index" {
+        // a hugo page bundle, the directory is the post
+        if let Some(parent) = path.parent().and_then(|parent| parent.file_name()) {
+            stem = parent.to_string_lossy().into_owned();
+        }
+    }
+    let named_date = stem
+        .get(..10)
+        .and_then(|prefix| NaiveDate::parse_from_str(prefix, "%Y-%m-%d").ok())
+        .filter(|_| stem[10..].starts_with('-'));
+    if named_date.is_some() {
+        stem = stem[11..].to_string();
+    }
+
+    let slug = slugify(front.slug.as_deref().unwrap_or(&stem));
+    if slug.is_empty() {
+        bail!("there's nothing to make a slug from");
+    }
+    let date = match &front.date {
+        Some(date) => Some(parse_date(date)?),
+        None => named_date.and_then(|date| date.and_hms_opt(0, 0, 0)),
+    };
+    let author = match front.author {
+        Some(OneOrMany::one(author)) => Some(author),
+        Some(OneOrMany::many(authors)) => authors.into_iter().next(),
+        None => None,
+    }
+    .map(|author| author.trim().to_string())
+    .filter(|author| !author.is_empty())
+    .or_else(|| author.map(String::from))
+    .ok_or_else(|| anyhow!("there's no author in the front matter, pass --author"))?;
+
+    let mut tags = match front.tags {
+        // a string is space or comma separated like in jekyll
+        Some(OneOrMany::one(tags)) => tags
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .map(String::from)
+            .collect(),
+        Some(OneOrMany::many(tags)) => tags,
+        None => Vec::new(),
+    }
+    .into_iter()
+    .map(|tag| tag.trim().to_string())
+    .filter(|tag| !tag.is_empty())
+    .collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+
+    let body = body.trim();
+    // the title goes in front of the text unless the text already starts with a heading
+    let text = match front.title.as_deref().map(str::trim) {
+        Some(title) if !title.is_empty() && !body.starts_with("# ") => {
+            format!("# {title}\n\n{body}\n")
+        }
+        _ => format!("{body}\n"),
+    };
+
+    Ok(MarkdownPost {
+        source: source.to_string(),
+        slug,
+        image: front.cover.map(|cover| match cover {
+            Cover::path(image) | Cover::nested { image } => image,
+        }),
+        author,
+        date,
+        archived: front.draft || front.published == Some(false),
+        tags,
+        text,
+    })
+}
+
+// yaml between --- lines or toml between +++ lines, a file without either is all text
+fn split_front_matter(content: &str) -> Result<(serde_json::Value, &str)> {
+    let content = content.trim_start_matches('\u{feff}');
+    let empty = || serde_json::Value::Object(Default::default());
+    let first = content.lines().next().unwrap_or_default().trim_end();
+    let fence = match first {
+        "---" | "+++" => first,
+        _ => return Ok((empty(), content)),
+    };
+
+    let rest = &content[content.find('\n').map(|at| at + 1).unwrap_or(content.len())..];
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        // yaml documents may also end with ...
+        if line.trim_end() == fence || (fence == "---" && line.trim_end() == "...") {
+            let front = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+            let front = match fence {
+                "---" => serde_yaml::from_str::<Option<serde_json::Value>>(front)
+                    .context("the yaml front matter is invalid")?
+                    .unwrap_or_else(empty),
+                _ => toml_to_json(
+                    front
+                        .parse::<toml::Value>()
+                        .context("the toml front matter is invalid")?,
+                ),
+            };
+            return Ok((front, body));
+        }
+        offset += line.len();
+    }
+    bail!("the front matter starting with {fence} is never closed")
+}
+
+// toml dates would otherwise come out as a private wrapper struct
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(string) => serde_json::Value::String(string),
+        toml::Value::Integer(integer) => integer.into(),
+        toml::Value::Float(float) => float.into(),
+        toml::Value::Boolean(boolean) => boolean.into(),
+        toml::Value::Datetime(datetime) => serde_json::Value::String(datetime.to_string()),
+        toml::Value::Array(array) => array.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect(),
+    }
+}
+
+// dates with an offset are stored in utc like everything else, the rest are taken as they are
+fn parse_date(date: &str) -> Result<NaiveDateTime> {
+    let date = date.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.naive_utc());
+    }
+    if let Ok(date) = DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z") {
+        return Ok(date.naive_utc());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(date, format) {
+            return Ok(date);
+        }
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .ok_or_else(|| anyhow!("\"{date}\" isn't a date like 2021-03-04 or 2021-03-04T10:00:00Z"))
+}
+
+// "Hello, World!" -> "hello-world"
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml_toml_or_no_front_matter() {
+        let (front, body) = split_front_matter("---\ntitle: Hello\ntags: [a, b]\n---\ntext\n").unwrap();
+        assert_eq!(front["title"], "Hello");
+        assert_eq!(body, "text\n");
+
+        let (front, body) =
+            split_front_matter("+++\ntitle = \"Hello\"\ndate = 2021-03-04T10:00:00Z\n+++\ntext").unwrap();
+        assert_eq!(front["date"], "2021-03-04T10:00:00Z");
+        assert_eq!(body, "text");
+
+        let (front, body) = split_front_matter("just text\n---\n").unwrap();
+        assert_eq!(front, serde_json::json!({}));
+        assert_eq!(body, "just text\n---\n");
+
+        assert!(split_front_matter("---\ntitle: Hello\n").is_err());
+    }
+
+    #[test]
+    fn jekyll_posts_take_the_date_from_their_name() {
+        let post = parse_post(
+            "2021/2021-03-04-hello-world.md",
+            "---\ntitle: Hello World\ntags: rust web, rust\npublished: false\n---\nSome text\n",
+            Some("ada"),
+        )
+        .unwrap();
+        assert_eq!(post.slug, "hello-world");
+        assert_eq!(post.date, parse_date("2021-03-04").ok());
+        assert_eq!(post.author, "ada");
+        assert_eq!(post.tags, ["rust", "web"]);
+        assert!(post.archived);
+        assert_eq!(post.text, "# Hello World\n\nSome text\n");
+    }
+
+    #[test]
+    fn hugo_bundles_are_named_after_their_directory() {
+        let post = parse_post(
+            "posts/First Post/index.md",
+            "---\nauthors: [grace, ada]\ncover:\n  image: cover.png\n---\n# Already a heading\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(post.slug, "first-post");
+        assert_eq!(post.author, "grace");
+        assert_eq!(post.image.as_deref(), Some("cover.png"));
+        assert_eq!(post.date, None);
+        assert_eq!(post.text, "# Already a heading\n");
+
+        // without an author anywhere there's nobody to import it as
+        assert!(parse_post("a.md", "text", None).is_err());
+    }
+
+    #[test]
+    fn dates_with_an_offset_end_up_in_utc() {
+        let date = |date| parse_date(date).unwrap().to_string();
+        assert_eq!(date("2021-03-04T10:00:00+02:00"), "2021-03-04 08:00:00");
+        assert_eq!(date("2021-03-04 10:00:00 +0100"), "2021-03-04 09:00:00");
+        assert_eq!(date("2021-03-04 10:00"), "2021-03-04 10:00:00");
+        assert_eq!(date("2021-03-04"), "2021-03-04 00:00:00");
+        assert!(parse_date("March 4th").is_err());
+    }
+
+    #[test]
+    fn slugs_are_lowercase_words() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  rust -- 2021 "), "rust-2021");
+        assert_eq!(slugify("Grüße"), "grüße");
+        assert_eq!(slugify("!!!"), "");
+    }
+}
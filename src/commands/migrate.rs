@@ -0,0 +1,91 @@
+use owo_colors::OwoColorize;
+
+use crate::{
+    error::AppError,
+    store::Store,
+    utils::migration::{migrate, mismatches, MigrationState},
+};
+
+pub async fn up(store: &Store) -> Result<(), AppError> {
+    match migrate(store).await {
+        Ok(_) => {
+            println!("{}", "Migration successfully executed".on_truecolor(250, 156, 28));
+            Ok(())
+        }
+        Err(e) => Err(AppError::migration_failed(e.to_string())),
+    }
+}
+
+// without --to only the latest applied migration is reverted
+pub async fn down(store: &Store, to: Option<i64>) -> Result<(), AppError> {
+    let status = store
+        .migration_status()
+        .await
+        .map_err(|e| AppError::migration_failed(e.to_string()))?;
+    let applied = status
+        .iter()
+        .filter(|migration| migration.state != MigrationState::pending)
+        .map(|migration| migration.version)
+        .collect::<Vec<_>>();
+    let target = match to {
+        Some(to) => to,
+        None if applied.len() > 1 => applied[applied.len() - 2],
+        None => 0,
+    };
+    let reverted = applied
+        .iter()
+        .filter(|version| **version > target)
+        .map(|version| version.to_string())
+        .collect::<Vec<_>>();
+    if reverted.is_empty() {
+        println!("Nothing to revert");
+        return Ok(());
+    }
+
+    store
+        .revert(target)
+        .await
+        .map_err(|e| AppError::migration_failed(e.to_string()))?;
+    println!(
+        "{} {}",
+        "Reverted:".on_truecolor(250, 156, 28),
+        reverted.join(", ")
+    );
+    Ok(())
+}
+
+// exits with an error when the schema doesn't match, so it can gate a deployment
+pub async fn status(store: &Store) -> Result<(), AppError> {
+    let status = store
+        .migration_status()
+        .await
+        .map_err(|e| AppError::migration_failed(e.to_string()))?;
+    if status.is_empty() {
+        println!("This store has no migrations");
+        return Ok(());
+    }
+
+    for migration in &status {
+        let state = match migration.state {
+            MigrationState::applied => "applied".bright_green().to_string(),
+            MigrationState::pending => "pending".bright_yellow().to_string(),
+            MigrationState::drifted => "changed since it was applied".bright_red().to_string(),
+            MigrationState::unknown => "unknown to this version".bright_red().to_string(),
+        };
+        println!(
+            "{:>4}  {:<16} {}",
+            migration.version.cyan(),
+            migration.description,
+            state
+        );
+    }
+
+    let mismatches = mismatches(&status);
+    match mismatches.is_empty() {
+        true => {
+            println!("{}", "The schema is up to date".bright_green());
+            Ok(())
+        }
+        false => Err(AppError::schema_mismatch(mismatches.join("; "))),
+    }
+}
@@ -0,0 +1,130 @@
+pub mod backup;
+pub mod comments;
+pub mod config;
+pub mod export;
+pub mod import;
+pub mod migrate;
+pub mod post;
+pub mod user;
+
+use clap::ArgMatches;
+
+use crate::{
+    error::AppError,
+    store::{events::EventBus, jobs::Jobs, webhooks::Webhooks, Backend, Store},
+    utils::setting::ServerConfig,
+};
+
+// where images that aren't urls are served from, the same directory the server falls back to
+const MEDIA_DIR: &str = "static/dist";
+
+// everything except `serve`, they run against the configured database and exit
+pub async fn run(
+    name: &str,
+    command: &ArgMatches,
+    config: &ServerConfig,
+    interactive: bool,
+) -> Result<(), AppError> {
+    match (name, command.subcommand()) {
+        ("config", Some(("check", _))) => config::check(config),
+        ("config", Some(("show", _))) => config::show(config),
+        ("migrate", Some(("up", _))) => migrate::up(&open_store(config, interactive).await?).await,
+        ("migrate", Some(("down", down))) => {
+            let to = down.get_one::<i64>("to").copied();
+            migrate::down(&open_store(config, interactive).await?, to).await
+        }
+        ("migrate", Some(("status", _))) => {
+            migrate::status(&open_store(config, interactive).await?).await
+        }
+        ("post", Some(("list", list))) => {
+            let page = list.get_one::<i64>("page").copied();
+            post::list(&open_store(config, interactive).await?, page).await
+        }
+        ("post", Some((action @ ("publish" | "unpublish" | "delete"), posts))) => {
+            let ids = posts.get_many::<i64>("ids").unwrap_or_default().copied().collect();
+            let store = open_evented_store(config, interactive).await?;
+            match action {
+                "publish" => post::publish(&store, ids).await,
+                "unpublish" => post::unpublish(&store, ids).await,
+                _ => post::delete(&store, ids).await,
+            }
+        }
+        ("user", Some((action @ ("create" | "reset-password" | "grant"), account))) => {
+            let username = account.get_one::<String>("username").map(String::as_str);
+            let username = username.unwrap_or_default();
+            let store = open_store(config, interactive).await?;
+            match action {
+                "create" => {
+                    let role = account.get_one::<String>("role").cloned();
+                    user::create(&store, username, role, interactive).await
+                }
+                "reset-password" => user::reset_password(&store, username, interactive).await,
+                _ => {
+                    let role = account.get_one::<String>("role").cloned();
+                    user::grant(&store, username, role).await
+                }
+            }
+        }
+        ("comments", Some(("purge-spam", purge))) => {
+            let store = open_evented_store(config, interactive).await?;
+            comments::purge_spam(&store, purge).await
+        }
+        ("backup", _) => {
+            let output = command.get_one::<String>("output").map(String::as_str);
+            let media_dir = command.get_one::<String>("media dir").map(String::as_str);
+            let store = open_store(config, interactive).await?;
+            backup::backup(&store, output, media_dir.unwrap_or(MEDIA_DIR)).await
+        }
+        ("restore", _) => {
+            let input = command.get_one::<String>("archive").map(String::as_str);
+            let media_dir = command.get_one::<String>("media dir").map(String::as_str);
+            let store = open_evented_store(config, interactive).await?;
+            backup::restore(&store, input.unwrap_or_default(), media_dir.unwrap_or(MEDIA_DIR)).await
+        }
+        ("export", _) => {
+            let media_dir = command.get_one::<String>("media dir").map(String::as_str);
+            let options = export::ExportOptions {
+                output: command.get_one::<String>("dir").map(String::as_str).unwrap_or_default(),
+                title: command.get_one::<String>("title").map(String::as_str).unwrap_or("Blog"),
+                base_url: command.get_one::<String>("base url").map(String::as_str),
+                media_dir: media_dir.unwrap_or(MEDIA_DIR),
+                force: command.get_flag("force"),
+            };
+            let store = open_store(config, interactive).await?;
+            export::export(&store, options).await
+        }
+        ("import", Some(("markdown", markdown))) => {
+            let dir = markdown.get_one::<String>("dir").map(String::as_str);
+            let author = markdown.get_one::<String>("author").map(String::as_str);
+            let store = open_evented_store(config, interactive).await?;
+            import::markdown(&store, dir.unwrap_or_default(), author).await
+        }
+        _ => Err(AppError::command_failed(format!("there's no command {name}"))),
+    }
+}
+
+// commands that don't change posts or comments have nothing to announce, a single pool does
+async fn open_store(config: &ServerConfig, interactive: bool) -> Result<Store, AppError> {
+    Store::new(&config.db_url, &config.connect, interactive).await
+}
+
+// the same layers the api writes through (minus the cache), so running instances and webhooks hear about it
+async fn open_evented_store(config: &ServerConfig, interactive: bool) -> Result<Store, AppError> {
+    let store = open_store(config, interactive).await?;
+    if !matches!(Backend::from_url(&config.db_url), Ok(Backend::Postgres)) {
+        return Ok(store);
+    }
+    let bus = match EventBus::postgres(&config.db_url).await {
+        Ok(bus) => bus,
+        Err(e) => {
+            eprintln!("running instances won't hear about this: {e}");
+            EventBus::local()
+        }
+    };
+    // deliveries are only queued here, a running instance's job worker sends them
+    let webhooks = match Jobs::connect(&config.db_url).await {
+        Ok(jobs) => Webhooks::connect(&config.db_url, jobs).await.ok(),
+        Err(_) => None,
+    };
+    Ok(store.with_events(bus, webhooks))
+}
@@ -0,0 +1,76 @@
+use owo_colors::OwoColorize;
+
+use crate::{
+    error::AppError,
+    store::Store,
+    types::{
+        blog::Pagination,
+        bulk::{BlogOperation, BulkReport, Selection},
+    },
+};
+
+// without a page every published post is listed
+pub async fn list(store: &Store, page: Option<i64>) -> Result<(), AppError> {
+    let blogs = store.blogs(Pagination { page }).await?;
+    if blogs.is_empty() {
+        println!("There are no published posts");
+        return Ok(());
+    }
+    for blog in blogs {
+        let tags = store.blog_tags(blog.id.0).await?;
+        println!(
+            "{:>6}  {}  {}  {}{}",
+            blog.id.0.cyan(),
+            blog.date.format("%Y-%m-%d %H:%M").bright_black(),
+            blog.author,
+            format!("{} likes", blog.likes).bright_black(),
+            match tags.is_empty() {
+                true => String::new(),
+                false => format!("  [{}]", tags.join(", ")),
+            }
+        );
+    }
+    Ok(())
+}
+
+pub async fn publish(store: &Store, ids: Vec<i64>) -> Result<(), AppError> {
+    let report = store
+        .bulk_blogs(BlogOperation::unarchive, Selection::ids(ids))
+        .await?;
+    print_report(&report)
+}
+
+pub async fn unpublish(store: &Store, ids: Vec<i64>) -> Result<(), AppError> {
+    let report = store
+        .bulk_blogs(BlogOperation::archive, Selection::ids(ids))
+        .await?;
+    print_report(&report)
+}
+
+pub async fn delete(store: &Store, ids: Vec<i64>) -> Result<(), AppError> {
+    let mut missing = 0;
+    for id in ids {
+        match store.delete_blog(id).await? {
+            true => println!("{:>6}  {}", id.cyan(), "deleted".bright_green()),
+            false => {
+                missing += 1;
+                println!("{:>6}  {}", id.cyan(), "not found".bright_red());
+            }
+        }
+    }
+    match missing {
+        0 => Ok(()),
+        _ => Err(AppError::command_failed(format!("{missing} of the posts weren't found"))),
+    }
+}
+
+// a post that's already in the wanted state is "unchanged", only missing ones fail the command
+fn print_report(report: &BulkReport) -> Result<(), AppError> {
+    for item in &report.results {
+        println!("{:>6}  {:?}", item.id.cyan(), item.status);
+    }
+    match report.failed {
+        0 => Ok(()),
+        failed => Err(AppError::command_failed(format!("{failed} of the posts weren't found"))),
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::BufRead;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+use dialoguer::Password;
+use owo_colors::OwoColorize;
+
+use crate::{error::AppError, store::Store, types::user::NewUser};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+pub async fn create(
+    store: &Store,
+    username: &str,
+    role: Option<String>,
+    interactive: bool,
+) -> Result<(), AppError> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(AppError::command_failed("the username can't be empty".to_string()));
+    }
+    let password_hash = hash(&password(interactive)?)?;
+    let created = store
+        .create_user(NewUser {
+            username: username.to_string(),
+            password_hash,
+            role: role.unwrap_or_else(|| "author".to_string()),
+        })
+        .await?;
+    println!(
+        "{} {} {}",
+        "Created user".bright_green(),
+        created.username,
+        format!("(id {}, {})", created.id, created.role).bright_black()
+    );
+    Ok(())
+}
+
+pub async fn reset_password(store: &Store, username: &str, interactive: bool) -> Result<(), AppError> {
+    let password_hash = hash(&password(interactive)?)?;
+    match store.set_password(username, password_hash).await? {
+        true => {
+            println!("{} {}", "Changed the password of".bright_green(), username);
+            Ok(())
+        }
+        false => Err(AppError::command_failed(format!("there's no user {username:?}"))),
+    }
+}
+
+pub async fn grant(store: &Store, username: &str, role: Option<String>) -> Result<(), AppError> {
+    let role = role.unwrap_or_default();
+    match store.set_role(username, role.clone()).await? {
+        true => {
+            println!("{} {} {}", username, "is now".bright_green(), role);
+            Ok(())
+        }
+        false => Err(AppError::command_failed(format!("there's no user {username:?}"))),
+    }
+}
+
+// asked twice at a terminal, otherwise it's the first line of stdin so scripts can pipe it in
+fn password(interactive: bool) -> Result<String, AppError> {
+    let password = match interactive {
+        true => Password::new()
+            .with_prompt("Password")
+            .with_confirmation("Repeat the password", "The passwords don't match")
+            .interact()
+            .map_err(|e| AppError::command_failed(format!("couldn't read the password: {e}")))?,
+        false => {
+            let mut line = String::new();
+            std::io::stdin()
+                .lock()
+                .read_line(&mut line)
+                .map_err(|e| AppError::command_failed(format!("couldn't read the password: {e}")))?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    match password.chars().count() >= MIN_PASSWORD_LENGTH {
+        true => Ok(password),
+        false => Err(AppError::command_failed(format!(
+            "the password needs at least {MIN_PASSWORD_LENGTH} characters"
+        ))),
+    }
+}
+
+// argon2id with a random salt, the PHC string it returns is all that's needed to verify it later
+fn hash(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => Err(AppError::command_failed(format!("couldn't hash the password: {e}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{PasswordHash, PasswordVerifier};
+
+    use super::*;
+    use crate::{error::Error, store::memory::MemoryStore};
+
+    #[test]
+    fn hashes_are_salted_and_verify() {
+        let first = hash("correct horse").unwrap();
+        let second = hash("correct horse").unwrap();
+        assert_ne!(first, second);
+        assert!(first.starts_with("$argon2id$"));
+
+        let parsed = PasswordHash::new(&first).unwrap();
+        assert!(Argon2::default().verify_password(b"correct horse", &parsed).is_ok());
+        assert!(Argon2::default().verify_password(b"wrong horse", &parsed).is_err());
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique_and_missing_users_are_reported() {
+        let store = Store::from(MemoryStore::new());
+        let user = NewUser {
+            username: "ada".to_string(),
+            password_hash: hash("correct horse").unwrap(),
+            role: "author".to_string(),
+        };
+        store.create_user(user.clone()).await.unwrap();
+        assert!(matches!(store.create_user(user).await, Err(Error::conflict(_))));
+
+        assert!(store.set_role("ada", "admin".to_string()).await.unwrap());
+        assert!(!store.set_role("grace", "admin".to_string()).await.unwrap());
+        assert!(!store.set_password("grace", String::new()).await.unwrap());
+    }
+}
@@ -2,28 +2,32 @@ use std::fmt;
 
 use axum::{
     // extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::{header::WWW_AUTHENTICATE, HeaderValue, StatusCode},
     response::IntoResponse,
     Json,
 };
 use serde::Serialize;
 
+#[derive(Debug)]
 #[allow(non_camel_case_types)]
 pub enum Error {
     // reject_json(JsonRejection),
     db_query_error(sqlx::Error),
     out_of_range_offset,
     invalid_offset,
+    conflict(String),
+    precondition_failed,
+    invalid_patch(String),
+    invalid_request(String),
+    unavailable(String),
+    unauthorized,
+    forbidden,
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        #[derive(Serialize)]
-        struct ErrorResponse {
-            message: String,
-        }
-
-        let (status, message) = match self {
+impl Error {
+    // shared by the http responses and the admin commands
+    fn status_and_message(&self) -> (StatusCode, String) {
+        match self {
             // Error::reject_json(rejection) => (rejection.status(), rejection.body_text()),
             Error::db_query_error(error) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -37,18 +41,90 @@ impl IntoResponse for Error {
                 StatusCode::UNPROCESSABLE_ENTITY,
                 "The offset is invalid".to_string(),
             ),
-        };
+            Error::conflict(message) => (StatusCode::CONFLICT, message.clone()),
+            Error::precondition_failed => (
+                StatusCode::PRECONDITION_FAILED,
+                "The resource changed since you last fetched it".to_string(),
+            ),
+            Error::invalid_patch(message) => (StatusCode::UNPROCESSABLE_ENTITY, message.clone()),
+            Error::invalid_request(message) => (StatusCode::UNPROCESSABLE_ENTITY, message.clone()),
+            Error::unavailable(message) => (StatusCode::SERVICE_UNAVAILABLE, message.clone()),
+            Error::unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "Sign in with the username and password of an admin".to_string(),
+            ),
+            Error::forbidden => (StatusCode::FORBIDDEN, "Only admins can do this".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.status_and_message().1)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        #[derive(Serialize)]
+        struct ErrorResponse {
+            message: String,
+        }
 
-        (status, Json(ErrorResponse { message })).into_response()
+        let (status, message) = self.status_and_message();
+        let mut response = (status, Json(ErrorResponse { message })).into_response();
+        // tells clients (and browsers) to come back with basic auth
+        if let Error::unauthorized = self {
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"admin\", charset=\"UTF-8\""),
+            );
+        }
+        response
     }
 }
 
 
+// everything that keeps the server from starting, each one ends the process with its own exit code
+#[derive(Debug)]
 #[allow(non_camel_case_types)]
 pub enum AppError {
     no_db_url,
     invalid_db_url,
-    db_connection_failed
+    db_connection_failed,
+    invalid_config(String),
+    log_setup(String),
+    metrics_setup(String),
+    invalid_fixture(String),
+    invalid_cors(Vec<String>),
+    tls_setup(String),
+    bind_failed(String),
+    command_failed(String),
+    migration_failed(String),
+    schema_mismatch(String),
+    server_stopped(String),
+}
+
+impl AppError {
+    // 1 is left to panics, so a script can tell a bug from a deployment problem
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::no_db_url => 10,
+            AppError::invalid_db_url => 11,
+            AppError::db_connection_failed => 12,
+            AppError::invalid_config(_) => 13,
+            AppError::log_setup(_) => 14,
+            AppError::metrics_setup(_) => 15,
+            AppError::invalid_fixture(_) => 16,
+            AppError::invalid_cors(_) => 17,
+            AppError::tls_setup(_) => 18,
+            AppError::bind_failed(_) => 19,
+            AppError::command_failed(_) => 20,
+            AppError::migration_failed(_) => 21,
+            AppError::schema_mismatch(_) => 22,
+            AppError::server_stopped(_) => 23,
+        }
+    }
 }
 
 impl fmt::Display for AppError {
@@ -58,6 +134,58 @@ impl fmt::Display for AppError {
             AppError::no_db_url => write!(f, "You didn't enter any database URL"),
             AppError::invalid_db_url => write!(f, "The database URL you entered is invalid"),
             AppError::db_connection_failed => write!(f, "Failed to connect to the database"),
+            AppError::invalid_config(e) => write!(f, "Couldn't construct the config: {e}"),
+            AppError::log_setup(e) => write!(f, "Couldn't open the log file: {e}"),
+            AppError::metrics_setup(e) => write!(f, "Couldn't set up the metrics: {e}"),
+            AppError::invalid_fixture(e) => write!(f, "Couldn't load the fixture file: {e}"),
+            AppError::invalid_cors(errors) => {
+                write!(f, "The cors section has mistakes:\n  {}", errors.join("\n  "))
+            }
+            AppError::tls_setup(e) => write!(f, "Couldn't load the TLS certificate: {e}"),
+            AppError::bind_failed(e) => write!(f, "Couldn't listen on the server port: {e}"),
+            AppError::command_failed(e) => write!(f, "{e}"),
+            AppError::migration_failed(e) => write!(f, "Migration failed: {e}"),
+            AppError::schema_mismatch(e) => {
+                write!(f, "The database schema doesn't match this version: {e}")
+            }
+            AppError::server_stopped(e) => write!(f, "The server stopped on its own: {e}"),
         }
     }
-}
\ No newline at end of file
+}
+
+// lets the admin commands use ? on the store
+impl From<Error> for AppError {
+    fn from(error: Error) -> Self {
+        AppError::command_failed(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_startup_failure_has_its_own_exit_code() {
+        let errors = [
+            AppError::no_db_url,
+            AppError::invalid_db_url,
+            AppError::db_connection_failed,
+            AppError::invalid_config(String::new()),
+            AppError::log_setup(String::new()),
+            AppError::metrics_setup(String::new()),
+            AppError::invalid_fixture(String::new()),
+            AppError::invalid_cors(Vec::new()),
+            AppError::tls_setup(String::new()),
+            AppError::bind_failed(String::new()),
+            AppError::command_failed(String::new()),
+            AppError::migration_failed(String::new()),
+            AppError::schema_mismatch(String::new()),
+        ];
+        let mut codes = errors.iter().map(AppError::exit_code).collect::<Vec<_>>();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        // 0 is success and 1 a panic
+        assert!(codes.iter().all(|code| *code > 1));
+    }
+}
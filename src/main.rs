@@ -1,40 +1,88 @@
+mod commands;
 mod error;
 mod routes;
 mod store;
 mod types;
 mod utils;
-use std::fs::{create_dir_all, OpenOptions};
+use std::{
+    fs::{create_dir_all, OpenOptions},
+    io::IsTerminal,
+    time::Duration,
+};
+use tokio::time::{timeout_at, Instant};
 use tracing::error;
 use axum::{
-    http::{self, HeaderValue, Method},
-    routing::{delete, get},
-    Router,
+    http::header::STRICT_TRANSPORT_SECURITY,
+    middleware,
+    routing::{delete, get, post},
+    Extension, Router,
 };
 use chrono::Local;
+use error::AppError;
 use owo_colors::OwoColorize;
-use routes::
+use routes::{
     blogs::{
-        blog_comments, blog_text, blogs, delete_blog, delete_blog_comment, post_blog,
-        post_blog_comments, post_blog_text, put_blog, put_blog_text, single_blog,
-    };
+        blog_comments, blog_text, blogs, delete_blog, delete_blog_comment, patch_blog,
+        patch_blog_text, post_blog, post_blog_comments, post_blog_text, put_blog, put_blog_text,
+        single_blog,
+    },
+    bulk::{blog_tags, bulk_blogs, bulk_comments, import_blogs},
+    editing::edit_blog,
+    events::{blog_events, events},
+    jobs::{job_overview, retry_job},
+    monitoring::{cache_stats, healthz, prometheus_metrics, readyz, status},
+    webhooks::{delete_webhook, list_webhooks, post_webhook, webhook_deliveries},
+};
 
-use store::Store;
+use store::{
+    events::EventBus,
+    jobs::{schedule_housekeeping, Jobs, PruneJobs, PruneWebhookDeliveries},
+    memory::MemoryStore,
+    webhooks::{DeliverWebhook, Webhooks},
+    Backend, Store,
+};
+use axum_server::tls_rustls::RustlsConfig;
 use tower_http::{
-    cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
     services::ServeDir,
+    set_header::SetResponseHeaderLayer,
 };
 use tracing_subscriber::{
-    fmt::format::{self, FmtSpan},
+    fmt::{
+        self,
+        format::{self as fmt_format, FmtSpan},
+    },
     prelude::*,
+    reload, EnvFilter,
 };
 use types::custom_time::CustomTimer;
 use utils::{
-    arguments::arguments, migration::migrate, setting::config_builder
+    arguments::arguments,
+    auth::require_admin,
+    collab::EditorHub,
+    cors::{apply_cors, CorsPolicies, LiveCors},
+    health::Health,
+    metrics::track_requests,
+    migration::{migrate, mismatches},
+    reload::{LiveConfig, Reloader},
+    setting::{config_builder, log_filter, print_config_source, redacted, save_config, CliOverrides},
+    shutdown::{wait_for_signal, Shutdown},
+    tls::{hsts, redirect_app, server_config, watch_certificates},
 };
 
 #[tokio::main]
 async fn main() {
+    if let Err(e) = run().await {
+        error!("{}", e);
+        eprintln!("{}", e.to_string().bright_red());
+        std::process::exit(e.exit_code());
+    }
+}
+
+// returns instead of exiting so the log file is flushed before the process ends
+async fn run() -> Result<(), AppError> {
     println!("{}", "Starting the application...".magenta());
+    let shutdown = Shutdown::default();
+    let health = Health::start(shutdown.clone());
 
     let arguments = arguments();
 
@@ -46,30 +94,82 @@ async fn main() {
         .get_one::<u16>("server port")
         .cloned();
 
-    let origin = arguments
-        .get_one::<u16>("set origin")
-        .cloned();
+    // a bare port is how origins used to be passed, it still means a localhost frontend
+    let origins = arguments
+        .get_many::<String>("origins")
+        .unwrap_or_default()
+        .map(|origin| match origin.parse::<u16>() {
+            Ok(port) => format!("http://localhost:{port}"),
+            Err(_) => origin.clone(),
+        })
+        .collect::<Vec<_>>();
 
     let log_level = arguments
         .get_one::<String>("log level")
         .cloned();
 
-    // let this be here for when we get in trouble
-    let _construct_config = arguments.get_one::<bool>("config").cloned().unwrap_or(true);
+    let ephemeral = arguments.get_flag("ephemeral");
 
+    // a container has no one to answer a prompt, it should fail and be restarted instead
+    let interactive = !arguments.get_flag("non interactive") && std::io::stdin().is_terminal();
 
-    let config = match config_builder(db_url, server_port, origin, log_level) {
-        Ok(c) => c,
-        Err(e) => {
-            panic!("Couldn't construct {} File: {e}", "config".bright_red())
+    let fixture = arguments
+        .get_one::<String>("fixture")
+        .cloned();
+
+    let config_file = arguments
+        .get_one::<String>("config file")
+        .cloned();
+
+    // kept for reloading, the command line still wins over the file afterwards
+    let cli = CliOverrides {
+        db_url,
+        server_port,
+        origins,
+        log_level,
+    };
+
+    if let Some(("config", command)) = arguments.subcommand() {
+        if let Some(("init", init)) = command.subcommand() {
+            return commands::config::init(config_file.as_deref(), init.get_flag("force"));
         }
+    }
+
+    print_config_source(config_file.as_deref());
+    let config = match config_builder(config_file.as_deref(), cli.clone()) {
+        Ok(c) => c,
+        Err(e) => return Err(AppError::invalid_config(format!("{e:#}"))),
     };
 
-    println!(
-        "{} {}",
-        "Database URL:".cyan(),
-        config.db_url.bright_black()
-    );
+    match arguments.subcommand() {
+        None | Some(("serve", _)) => {}
+        Some(_) if ephemeral => {
+            return Err(AppError::command_failed(
+                "--ephemeral only works with serve".to_string(),
+            ))
+        }
+        Some((name, command)) => return commands::run(name, command, &config, interactive).await,
+    }
+
+    if arguments.get_flag("save config") {
+        if let Err(e) = save_config(config_file.as_deref(), &config) {
+            println!("{} {e:#}", "Couldn't save the config:".bright_red());
+        }
+    }
+
+    if ephemeral {
+        println!(
+            "{} {}",
+            "Database URL:".cyan(),
+            "in-memory (ephemeral)".bright_black()
+        );
+    } else {
+        println!(
+            "{} {}",
+            "Database URL:".cyan(),
+            redacted(&config).db_url.bright_black()
+        );
+    }
     println!(
         "{} {}",
         "Server Port:".cyan(),
@@ -77,107 +177,362 @@ async fn main() {
     );
     println!(
         "{} {}",
-        "Allow Origin on Port:".cyan(),
-        config.origin_port.bright_black()
+        "Allowed Origins:".cyan(),
+        config.cors.origins.join(", ").bright_black()
     );
     println!(
-        "{} {}\n",
+        "{} {}",
         "Log Level:".cyan(),
         config.log_level.bright_black()
     );
+    match &config.tls {
+        Some(tls) => println!(
+            "{} {}\n",
+            "TLS:".cyan(),
+            format!("{}+ ({})", tls.min_version, tls.cert_path).bright_black()
+        ),
+        None => println!("{} {}\n", "TLS:".cyan(), "off".bright_black()),
+    }
 
     let timer = CustomTimer;
-    create_dir_all("log").expect("failed to create log directory");
-    let file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("log/info.log")
-        .expect("couldn't open or create the log file");
-    let (none_blocking, _worker_guard) = tracing_appender::non_blocking(file);
-
-    let log_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| {
-        format!(
-            "blog_api={},tower_http={},axum::rejection=trace",
-            config.log_level, config.log_level
-        )
-    });
-    tracing_subscriber::fmt()
-        .with_timer(timer)
-        .with_writer(none_blocking)
-        .with_ansi(false)
-        .with_env_filter(log_filter)
-        .with_span_events(FmtSpan::CLOSE)
-        .with_target(false)
-        .with_thread_ids(false)
-        .with_thread_names(false)
-        .compact()
-        .fmt_fields(
-            format::debug_fn(|writer, field, value| write!(writer, "[{}: {:?}]", field, value))
-                .delimited(" - "),
+    let file = create_dir_all("log")
+        .and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open("log/info.log")
+        })
+        .map_err(|e| AppError::log_setup(e.to_string()))?;
+    // dropping the guard flushes whatever is still buffered, so it lives until the very end
+    let (none_blocking, worker_guard) = tracing_appender::non_blocking(file);
+
+    // the filter sits behind a handle so a reloaded log_level applies right away
+    let (filter, log_reload) =
+        reload::Layer::new(EnvFilter::new(log_filter(&config.log_level)));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer()
+                .with_timer(timer)
+                .with_writer(none_blocking)
+                .with_ansi(false)
+                .with_span_events(FmtSpan::CLOSE)
+                .with_target(false)
+                .with_thread_ids(false)
+                .with_thread_names(false)
+                .compact()
+                .fmt_fields(
+                    fmt_format::debug_fn(|writer, field, value| {
+                        write!(writer, "[{}: {:?}]", field, value)
+                    })
+                    .delimited(" - "),
+                ),
         )
         .init();
 
-    let store = Store::new(&config.db_url).await;
+    let metrics = match utils::metrics::install() {
+        Ok(handle) => handle,
+        Err(e) => return Err(AppError::metrics_setup(e.to_string())),
+    };
 
-    match migrate(&store).await {
-        Ok(_) => {
-            println!("{}", "Migration successfully executed".on_truecolor(250, 156, 28));
-        }
-        Err(e) => {
-            error!("Migration failed: {}", e);
-            println!("Migration failed: {}", e);
+    let store = match (ephemeral, fixture) {
+        (true, Some(fixture)) => match MemoryStore::from_fixture(&fixture).await {
+            Ok(memory) => Store::from(memory).with_metrics(),
+            Err(e) => return Err(AppError::invalid_fixture(e.to_string())),
+        },
+        (true, None) => Store::from(MemoryStore::new()).with_metrics(),
+        (false, _) => Store::new(&config.db_url, &config.connect, interactive)
+            .await?
+            .with_metrics()
+            .with_cache(Duration::from_secs(config.cache_ttl), config.cache_size),
+    };
+
+    // with postgres the events go through LISTEN/NOTIFY so every instance sees them
+    let bus = match (ephemeral, Backend::from_url(&config.db_url)) {
+        (false, Ok(Backend::Postgres)) => match EventBus::postgres(&config.db_url).await {
+            Ok(bus) => bus,
+            Err(e) => {
+                error!("Couldn't listen for events: {}", e);
+                println!("{} {e}", "Live updates stay local to this instance:".bright_red());
+                EventBus::local()
+            }
+        },
+        _ => EventBus::local(),
+    };
+
+    let jobs = match (ephemeral, Backend::from_url(&config.db_url)) {
+        (false, Ok(Backend::Postgres)) => match Jobs::connect(&config.db_url).await {
+            Ok(jobs) => Some(
+                jobs.register::<DeliverWebhook>()
+                    .register::<PruneWebhookDeliveries>()
+                    .register::<PruneJobs>(),
+            ),
+            Err(e) => {
+                error!("Couldn't set up background jobs: {}", e);
+                println!("{} {e}", "Background jobs are turned off:".bright_red());
+                None
+            }
+        },
+        _ => None,
+    };
+
+    // the queue lives in postgres, the other backends run without webhooks
+    let webhooks = match &jobs {
+        Some(jobs) => match Webhooks::connect(&config.db_url, jobs.clone()).await {
+            Ok(webhooks) => Some(webhooks),
+            Err(e) => {
+                error!("Couldn't set up webhooks: {}", e);
+                println!("{} {e}", "Webhooks are turned off:".bright_red());
+                None
+            }
+        },
+        None => None,
+    };
+    // the live editing sessions save through this one, see utils::collab
+    let quiet = store.clone();
+    let store = store.with_events(bus, webhooks.clone());
+
+    if config.auto_migrate {
+        migrate(&store)
+            .await
+            .map_err(|e| AppError::migration_failed(e.to_string()))?;
+        println!("{}", "Migration successfully executed".on_truecolor(250, 156, 28));
+    }
+    // serving on a schema the queries weren't written for only fails later and less clearly
+    let migrations = store
+        .migration_status()
+        .await
+        .map_err(|e| AppError::migration_failed(e.to_string()))?;
+    let mismatches = mismatches(&migrations);
+    if !mismatches.is_empty() {
+        return Err(AppError::schema_mismatch(format!(
+            "{} (see `migrate status`)",
+            mismatches.join("; ")
+        )));
+    }
+
+    // started after the migration so the queue tables exist
+    let mut workers = Vec::new();
+    if let Some(jobs) = &jobs {
+        if let Err(e) = schedule_housekeeping(jobs).await {
+            error!("Couldn't schedule the housekeeping jobs: {}", e);
         }
+        workers.push(jobs.spawn_worker(shutdown.clone()));
     }
     
-    let cors = CorsLayer::new()
-        .allow_origin(AllowOrigin::exact(
-            format!("http://localhost:{}", config.origin_port)
-                .parse()
-                .unwrap_or_else(|_|{
-                    println!(
-                        "Due to an unexpected error Allow origin value change to http://localhost:9999"
-                    );
-                    HeaderValue::from_static("http://localhost:9999")
-                })
-        ))
-        .allow_methods(AllowMethods::list([
-            Method::GET,
-            Method::POST,
-            Method::PUT,
-            Method::DELETE,
-        ]))
-        .allow_headers(AllowHeaders::list([http::header::CONTENT_TYPE]));
+    let cors = match CorsPolicies::from_config(&config.cors) {
+        Ok(cors) => LiveCors::new(cors),
+        Err(errors) => return Err(AppError::invalid_cors(errors)),
+    };
 
+    // log_level, cors and shutdown_timeout follow the config file (and SIGHUP) from here on
+    let live = LiveConfig::new(config.clone());
+    Reloader {
+        config_file: config_file.clone(),
+        cli,
+        live: live.clone(),
+        cors: cors.clone(),
+        log: log_reload,
+    }
+    .spawn(shutdown.clone());
+
+    let hub = EditorHub::new(quiet);
+    let admin = Router::new()
+        .route("/bulk/comments/{action}", post(bulk_comments))
+        .route("/bulk/blogs/import", post(import_blogs))
+        .route("/bulk/blogs/{action}", post(bulk_blogs))
+        .route("/webhooks", get(list_webhooks).post(post_webhook))
+        .route("/webhooks/{id}", delete(delete_webhook))
+        .route("/webhooks/{id}/deliveries", get(webhook_deliveries))
+        .route("/admin/jobs", get(job_overview))
+        .route("/admin/jobs/{id}/retry", post(retry_job))
+        .route_layer(middleware::from_fn_with_state(store.clone(), require_admin));
     let app = Router::new()
         .route("/blogs", get(blogs).post(post_blog))
         .route(
             "/blogs/{id}",
-            get(single_blog).put(put_blog).delete(delete_blog),
+            get(single_blog)
+                .put(put_blog)
+                .patch(patch_blog)
+                .delete(delete_blog),
         )
         .route(
             "/blogs/{id}/text",
-            get(blog_text).put(put_blog_text).post(post_blog_text),
+            get(blog_text)
+                .put(put_blog_text)
+                .patch(patch_blog_text)
+                .post(post_blog_text),
         )
         .route(
             "/blogs/{id}/comments",
             get(blog_comments).post(post_blog_comments),
         )
         .route("/blogs/{id}/comments/{id}", delete(delete_blog_comment))
-        .with_state(store)
-        .layer(cors)
+        .route("/blogs/{id}/tags", get(blog_tags))
+        .route("/blogs/{id}/events", get(blog_events))
+        .route("/blogs/{id}/edit", get(edit_blog))
+        .route("/events", get(events))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/status", get(status))
+        .route("/metrics", get(prometheus_metrics))
+        .route("/cache/stats", get(cache_stats))
+        .merge(admin)
+        .route_layer(middleware::from_fn(track_requests))
+        .with_state(store.clone())
+        .layer(Extension(hub.clone()))
+        .layer(Extension(jobs.clone()))
+        .layer(Extension(health))
+        .layer(Extension(shutdown.clone()))
+        .layer(Extension(metrics))
+        .layer(middleware::from_fn_with_state(cors.clone(), apply_cors))
         .fallback_service(ServeDir::new("static/dist"));
 
+    // a certificate that can't be loaded at startup is a config mistake, not something to serve around
+    let tls = match config.tls.clone() {
+        Some(tls) => match server_config(&tls) {
+            Ok(rustls) => Some((tls, RustlsConfig::from_config(rustls))),
+            Err(e) => return Err(AppError::tls_setup(format!("{e:#}"))),
+        },
+        None => None,
+    };
+    let app = match config.tls.as_ref().and_then(hsts) {
+        Some(hsts) => app.layer(SetResponseHeaderLayer::if_not_present(
+            STRICT_TRANSPORT_SECURITY,
+            hsts,
+        )),
+        None => app,
+    };
+
     let time = Local::now().format("%Y-%m-%d %H:%M:%S");
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.server_port))
         .await
-        .unwrap();
+        .map_err(|e| AppError::bind_failed(format!("{}: {e}", config.server_port)))?;
     let print_server_start = format!(
-        "{time} start the server on http://localhost:{}/",
+        "{time} start the server on {}://localhost:{}/",
+        if tls.is_some() { "https" } else { "http" },
         config.server_port
     );
     println!("{}", print_server_start);
     tracing::info!(print_server_start);
-    axum::serve(listener, app).await.unwrap();
+
+    let mut server = match tls {
+        Some((tls, rustls)) => {
+            watch_certificates(tls.clone(), rustls.clone(), shutdown.clone());
+            if let Some(redirect_port) = tls.redirect_port {
+                match tokio::net::TcpListener::bind(format!("0.0.0.0:{redirect_port}")).await {
+                    Ok(listener) => {
+                        let redirect = axum::serve(listener, redirect_app(config.server_port.clone()))
+                            .with_graceful_shutdown({
+                                let shutdown = shutdown.clone();
+                                async move { shutdown.triggered().await }
+                            });
+                        workers.push(tokio::spawn(async move {
+                            if let Err(e) = redirect.await {
+                                error!("The https redirect stopped: {}", e);
+                            }
+                        }));
+                        println!(
+                            "{} {}",
+                            "Redirecting http to https from port".cyan(),
+                            redirect_port.bright_black()
+                        );
+                    }
+                    Err(e) => {
+                        error!("Couldn't listen for http on {}: {}", redirect_port, e);
+                        println!("{} {e}", "The https redirect is turned off:".bright_red());
+                    }
+                }
+            }
+
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    shutdown.triggered().await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+            let listener = listener
+                .into_std()
+                .map_err(|e| AppError::bind_failed(format!("{}: {e}", config.server_port)))?;
+            tokio::spawn(
+                axum_server::from_tcp_rustls(listener, rustls)
+                    .handle(handle)
+                    .serve(app.into_make_service()),
+            )
+        }
+        None => {
+            let server = axum::serve(listener, app).with_graceful_shutdown({
+                let shutdown = shutdown.clone();
+                async move { shutdown.triggered().await }
+            });
+            tokio::spawn(async move { server.await })
+        }
+    };
+
+    // only a signal is a clean exit, the rest still shuts down in order and then fails the run
+    let stopped = tokio::select! {
+        _ = wait_for_signal() => None,
+        served = &mut server => Some(match served {
+            Ok(Ok(())) => "it returned without being asked to".to_string(),
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        }),
+    };
+    if let Some(reason) = &stopped {
+        error!("The server stopped on its own: {}", reason);
+        println!("{} {reason}", "The server stopped on its own:".bright_red());
+    }
+
+    // readiness fails from here on, new connections are refused and open ones get to finish
+    shutdown.trigger();
+    let drain = Duration::from_secs(live.current().shutdown_timeout);
+    let deadline = Instant::now() + drain;
+    println!(
+        "{} {}",
+        "Shutting down, waiting for open requests at most".magenta(),
+        format!("{}s", drain.as_secs()).bright_black()
+    );
+    tracing::info!("Shutting down");
+
+    // a server that already stopped can't be awaited again
+    if stopped.is_none() && timeout_at(deadline, &mut server).await.is_err() {
+        tracing::warn!("Requests still open after {}s, dropping them", drain.as_secs());
+        println!("{}", "Some requests didn't finish in time".bright_red());
+        server.abort();
+    }
+    for worker in workers {
+        if timeout_at(deadline, worker).await.is_err() {
+            tracing::warn!("A background worker didn't finish in time");
+        }
+    }
+
+    hub.persist_all(&store).await;
+    // a request we gave up on may still hold a connection, don't wait on it forever
+    let closed = tokio::time::timeout(Duration::from_secs(5), async {
+        store.close().await;
+        if let Some(webhooks) = &webhooks {
+            webhooks.close().await;
+        }
+        if let Some(jobs) = &jobs {
+            jobs.close().await;
+        }
+    })
+    .await;
+    if closed.is_err() {
+        tracing::warn!("Couldn't close the database connections in time");
+    }
+
+    if let Some(reason) = stopped {
+        drop(worker_guard);
+        return Err(AppError::server_stopped(reason));
+    }
+    tracing::info!("Shut down cleanly");
+    println!("{}", "Bye!".magenta());
+    drop(worker_guard);
+    Ok(())
 }
 
 // todos
@@ -1,16 +1,21 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
-    Json,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 
 use crate::{
     error::Error,
     store::Store,
     types::{
-        blog::{Blog, NewBlog, Pagination, Text},
+        blog::{Blog, BlogPatch, NewBlog, Pagination, Text, TextPatch},
         comment::{Comment, NewComment},
     },
+    utils::{
+        collab::EditorHub,
+        conditional::{conditional_json, etag, with_validators, IfMatch},
+    },
 };
 
 pub async fn blogs(
@@ -26,9 +31,13 @@ pub async fn blogs(
 pub async fn single_blog(
     State(store): State<Store>,
     Path(blog_id): Path<i64>,
-) -> Result<Json<Blog>, Error> {
+    headers: HeaderMap,
+) -> Result<Response, Error> {
     match store.get_single_blog(blog_id).await {
-        Ok(res) => Ok(Json(res)),
+        Ok(res) => {
+            let updated_at = res.updated_at;
+            Ok(conditional_json(&headers, res, Some(&updated_at)))
+        }
         Err(e) => Err(e),
     }
 }
@@ -43,13 +52,43 @@ pub async fn post_blog(
     }
 }
 
+// If-Match makes this an optimistic update: it only goes through if nobody changed the blog in between
 pub async fn put_blog(
     State(store): State<Store>,
     Path(blog_id): Path<i64>,
+    headers: HeaderMap,
     Json(payload): Json<Blog>,
-) -> Result<Json<Blog>, Error> {
-    match store.put_blog(payload, blog_id).await {
-        Ok(res) => Ok(Json(res)),
+) -> Result<Response, Error> {
+    match store
+        .put_blog(payload, blog_id, IfMatch::from_headers(&headers))
+        .await
+    {
+        Ok(res) => {
+            let mut response = Json(&res).into_response();
+            with_validators(&mut response, &etag(&res), Some(&res.updated_at));
+            Ok(response)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// json merge patch: only the fields in the body change, null clears the image
+pub async fn patch_blog(
+    State(store): State<Store>,
+    Path(blog_id): Path<i64>,
+    headers: HeaderMap,
+    Json(payload): Json<BlogPatch>,
+) -> Result<Response, Error> {
+    let changes = payload.into_changes()?;
+    match store
+        .patch_blog(changes, blog_id, IfMatch::from_headers(&headers))
+        .await
+    {
+        Ok(res) => {
+            let mut response = Json(&res).into_response();
+            with_validators(&mut response, &etag(&res), Some(&res.updated_at));
+            Ok(response)
+        }
         Err(e) => Err(e),
     }
 }
@@ -67,30 +106,70 @@ pub async fn delete_blog(
 pub async fn blog_text(
     State(store): State<Store>,
     Path(blog_id): Path<i64>,
-) -> Result<Json<Text>, Error> {
+    headers: HeaderMap,
+) -> Result<Response, Error> {
     match store.blog_text(blog_id).await {
-        Ok(res) => Ok(Json(res)),
+        Ok(res) => Ok(conditional_json(&headers, res, None)),
         Err(e) => Err(e),
     }
 }
 
 pub async fn put_blog_text(
     State(store): State<Store>,
+    Extension(hub): Extension<EditorHub>,
     Path(blog_id): Path<i64>,
+    headers: HeaderMap,
     Json(payload): Json<Text>,
-) -> Result<Json<Text>, Error> {
-    match store.put_blog_text(payload, blog_id).await {
-        Ok(res) => Ok(Json(res)),
+) -> Result<Response, Error> {
+    let write = store.put_blog_text(payload, blog_id, IfMatch::from_headers(&headers));
+    match hub.unless_editing(blog_id, write).await {
+        Ok(res) => {
+            let mut response = Json(&res).into_response();
+            with_validators(&mut response, &etag(&res), None);
+            Ok(response)
+        }
         Err(e) => Err(e),
     }
 }
 
+pub async fn patch_blog_text(
+    State(store): State<Store>,
+    Extension(hub): Extension<EditorHub>,
+    Path(blog_id): Path<i64>,
+    headers: HeaderMap,
+    Json(payload): Json<TextPatch>,
+) -> Result<Response, Error> {
+    let text = payload.text.required("text").map_err(Error::invalid_patch)?;
+    let if_match = IfMatch::from_headers(&headers);
+    let res = match text {
+        Some(text) => {
+            let write = store.put_blog_text(Text { blog_id, text }, blog_id, if_match);
+            hub.unless_editing(blog_id, write).await?
+        }
+        // nothing to write, so the precondition is only checked against what's there
+        None => {
+            let current = store.blog_text(blog_id).await;
+            if let Some(if_match) = if_match {
+                if_match.check(current.as_ref().ok())?;
+            }
+            current?
+        }
+    };
+    let mut response = Json(&res).into_response();
+    with_validators(&mut response, &etag(&res), None);
+    Ok(response)
+}
+
 pub async fn post_blog_text(
     State(store): State<Store>,
+    Extension(hub): Extension<EditorHub>,
     Path(blog_id): Path<i64>,
     Json(payload): Json<Text>,
 ) -> Result<Json<Text>, Error> {
-    match store.post_blog_text(payload, blog_id).await {
+    match hub
+        .unless_editing(blog_id, store.post_blog_text(payload, blog_id))
+        .await
+    {
         Ok(res) => Ok(Json(res)),
         Err(e) => Err(e),
     }
@@ -99,9 +178,10 @@ pub async fn post_blog_text(
 pub async fn blog_comments(
     State(store): State<Store>,
     Path(blog_id): Path<i64>,
-) -> Result<Json<Vec<Comment>>, Error> {
+    headers: HeaderMap,
+) -> Result<Response, Error> {
     match store.get_blog_comments(blog_id).await {
-        Ok(res) => Ok(Json(res)),
+        Ok(res) => Ok(conditional_json(&headers, res, None)),
         Err(e) => Err(e),
     }
 }
@@ -122,7 +202,164 @@ pub async fn delete_blog_comment(
     Path((blog_id, comment_id)): Path<(i64, i64)>,
 ) -> Result<StatusCode, Error> {
     match store.delete_blog_comment(blog_id, comment_id).await {
-        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Method, Request},
+        routing::get,
+        Router,
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    fn app() -> Router {
+        let store = Store::from(MemoryStore::new());
+        Router::new()
+            .route("/blogs", get(blogs).post(post_blog))
+            .route(
+                "/blogs/{id}",
+                get(single_blog).put(put_blog).delete(delete_blog),
+            )
+            .route("/blogs/{id}/text", get(blog_text).put(put_blog_text))
+            .with_state(store.clone())
+            .layer(Extension(EditorHub::new(store)))
+    }
+
+    async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        send_if_match(app, method, uri, body, None).await
+    }
+
+    async fn send_if_match(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+        if_match: Option<&str>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(if_match) = if_match {
+            request = request.header("if-match", if_match);
+        }
+        let request = match body {
+            Some(body) => request
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn create(app: &Router, author: &str) -> i64 {
+        let blog = json!({ "image": null, "author": author, "text": format!("# by {author}") });
+        let (status, created) = send(app, Method::POST, "/blogs", Some(blog)).await;
+        assert_eq!(status, StatusCode::OK);
+        created["id"].as_i64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn created_blog_can_be_read_back() {
+        let app = app();
+        let id = create(&app, "ada").await;
+
+        let (status, blog) = send(&app, Method::GET, &format!("/blogs/{id}"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(blog["author"], "ada");
+        assert_eq!(blog["likes"], 0);
+
+        let (status, text) = send(&app, Method::GET, &format!("/blogs/{id}/text"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(text["text"], "# by ada");
+    }
+
+    #[tokio::test]
+    async fn deleted_blog_is_gone() {
+        let app = app();
+        let id = create(&app, "ada").await;
+
+        let (status, _) = send(&app, Method::DELETE, &format!("/blogs/{id}"), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, _) = send(&app, Method::GET, &format!("/blogs/{id}"), None).await;
+        assert!(!status.is_success());
+        let (status, _) = send(&app, Method::GET, &format!("/blogs/{id}/text"), None).await;
+        assert!(!status.is_success());
+        let (_, listed) = send(&app, Method::GET, "/blogs", None).await;
+        assert_eq!(listed, json!([]));
+    }
+
+    #[tokio::test]
+    async fn listing_pages_by_ten() {
+        let app = app();
+        for n in 0..12 {
+            create(&app, &format!("author {n}")).await;
+        }
+
+        let (status, listed) = send(&app, Method::GET, "/blogs", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed.as_array().unwrap().len(), 12);
+
+        let (_, first) = send(&app, Method::GET, "/blogs?page=1", None).await;
+        let first = first.as_array().unwrap();
+        assert_eq!(first[0]["author"], "author 0");
+
+        let (_, second) = send(&app, Method::GET, "/blogs?page=2", None).await;
+        let second = second.as_array().unwrap();
+        assert_eq!(second.last().unwrap()["author"], "author 11");
+
+        let (status, _) = send(&app, Method::GET, "/blogs?page=3", None).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn if_match_only_writes_the_version_it_names() {
+        let app = app();
+        let id = create(&app, "ada").await;
+        let uri = format!("/blogs/{id}/text");
+        let (_, current) = send(&app, Method::GET, &uri, None).await;
+        let current_tag = etag(&serde_json::from_value::<Text>(current).unwrap());
+
+        // a weak tag never satisfies If-Match, even when it names the current version
+        let text = json!({ "blog_id": id, "text": "first" });
+        let weak = format!("W/{current_tag}");
+        let (status, _) = send_if_match(&app, Method::PUT, &uri, Some(text.clone()), Some(&weak)).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+        let (status, _) = send_if_match(&app, Method::PUT, &uri, Some(text), Some(&current_tag)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // the tag is stale now, so the second writer loses
+        let text = json!({ "blog_id": id, "text": "second" });
+        let (status, _) = send_if_match(&app, Method::PUT, &uri, Some(text), Some(&current_tag)).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (_, stored) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(stored["text"], "first");
+    }
+
+    #[tokio::test]
+    async fn if_match_on_a_missing_blog_fails_the_precondition() {
+        let app = app();
+        let blog = json!({
+            "id": 7,
+            "image": null,
+            "author": "ada",
+            "date": "2024-01-01T00:00:00",
+            "likes": 0,
+            "bookmarks": 0
+        });
+        let (status, _) = send_if_match(&app, Method::PUT, "/blogs/7", Some(blog), Some("*")).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    }
+}
@@ -0,0 +1,56 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::{
+    error::Error,
+    store::Store,
+    types::bulk::{BlogAction, BlogBulk, BlogImport, BulkReport, CommentAction, CommentBulk},
+};
+
+// everything selected is handled in one transaction, the report says what happened to each id
+pub async fn bulk_comments(
+    State(store): State<Store>,
+    Path(action): Path<CommentAction>,
+    Json(payload): Json<CommentBulk>,
+) -> Result<Json<BulkReport>, Error> {
+    let selection = payload.selection.validate()?;
+    match store.bulk_comments(action, selection).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn bulk_blogs(
+    State(store): State<Store>,
+    Path(action): Path<BlogAction>,
+    Json(payload): Json<BlogBulk>,
+) -> Result<Json<BulkReport>, Error> {
+    let selection = payload.selection.validate()?;
+    let operation = action.with_tag(payload.tag)?;
+    match store.bulk_blogs(operation, selection).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn import_blogs(
+    State(store): State<Store>,
+    Json(payload): Json<BlogImport>,
+) -> Result<Json<BulkReport>, Error> {
+    match store.import_blogs(payload.blogs).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn blog_tags(
+    State(store): State<Store>,
+    Path(blog_id): Path<i64>,
+) -> Result<Json<Vec<String>>, Error> {
+    match store.blog_tags(blog_id).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,131 @@
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    response::Response,
+    Extension,
+};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    error::Error,
+    store::Store,
+    utils::{
+        collab::{ClientMessage, EditorHub, Joined, ServerMessage},
+        shutdown::Shutdown,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct EditorQuery {
+    pub name: Option<String>,
+}
+
+// GET /blogs/{id}/edit upgrades to a websocket, see utils::collab for the messages
+pub async fn edit_blog(
+    ws: WebSocketUpgrade,
+    State(store): State<Store>,
+    Extension(hub): Extension<EditorHub>,
+    Extension(shutdown): Extension<Shutdown>,
+    Path(blog_id): Path<i64>,
+    Query(query): Query<EditorQuery>,
+) -> Result<Response, Error> {
+    // checked before the upgrade so a missing blog is still a plain http error
+    store.get_single_blog(blog_id).await?;
+    let name = query.name.unwrap_or_else(|| "anonymous".to_string());
+    Ok(ws.on_upgrade(move |socket| {
+        edit_session(socket, store, hub, shutdown, blog_id, name)
+    }))
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(json) => socket.send(Message::Text(json.into())).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+async fn edit_session(
+    mut socket: WebSocket,
+    store: Store,
+    hub: EditorHub,
+    shutdown: Shutdown,
+    blog_id: i64,
+    name: String,
+) {
+    let Joined {
+        session,
+        editor,
+        snapshot,
+        mut updates,
+    } = match hub.join(&store, blog_id, name).await {
+        Ok(joined) => joined,
+        Err(_) => {
+            let message = "Couldn't open the text for editing".to_string();
+            send(&mut socket, &ServerMessage::error { message }).await;
+            return;
+        }
+    };
+
+    if send(&mut socket, &snapshot).await {
+        loop {
+            tokio::select! {
+                incoming = socket.recv() => match incoming {
+                    Some(Ok(Message::Text(text))) => {
+                        let handled = match serde_json::from_str::<ClientMessage>(&text) {
+                            Ok(ClientMessage::operation { revision, operation }) => {
+                                session.receive(editor, revision, operation).await
+                            }
+                            Ok(ClientMessage::cursor { position }) => {
+                                session.cursor(editor, position).await;
+                                Ok(())
+                            }
+                            Err(e) => Err(e.to_string()),
+                        };
+                        if let Err(message) = handled {
+                            if !send(&mut socket, &ServerMessage::error { message }).await {
+                                break;
+                            }
+                        }
+                    }
+                    // pings are answered by axum itself
+                    Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => {}
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                },
+                update = updates.recv() => {
+                    let message = match update {
+                        // our own operation comes back in order with everybody else's, as an ack
+                        Ok((origin, ServerMessage::operation { revision, .. })) if origin == editor => {
+                            ServerMessage::ack { revision }
+                        }
+                        Ok((origin, _)) if origin == editor => continue,
+                        Ok((_, message)) => message,
+                        Err(RecvError::Lagged(_)) => {
+                            let message = "Fell too far behind, reconnect to reload the text".to_string();
+                            send(&mut socket, &ServerMessage::error { message }).await;
+                            break;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    if !send(&mut socket, &message).await {
+                        break;
+                    }
+                }
+                // leaving saves the text, the client can reconnect once we're back
+                _ = shutdown.triggered() => {
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "The server is shutting down".into(),
+                        })))
+                        .await;
+                    break;
+                }
+            }
+        }
+    }
+
+    hub.leave(&store, &session, editor).await;
+}
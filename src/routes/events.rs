@@ -0,0 +1,65 @@
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, WatchStream},
+    Stream, StreamExt,
+};
+
+use crate::{
+    error::Error,
+    store::{events::EventBus, Store},
+    utils::shutdown::Shutdown,
+};
+
+fn event_bus(store: &Store) -> Result<EventBus, Error> {
+    store
+        .events()
+        .ok_or(Error::unavailable("Live updates are turned off".to_string()))
+}
+
+// `None` streams the events of every blog. the stream ends when we shut down,
+// otherwise the open connections would hold up the drain until it times out
+fn event_stream(
+    bus: EventBus,
+    blog_id: Option<i64>,
+    shutdown: Shutdown,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let stopped = WatchStream::new(shutdown.subscribe())
+        .filter(|stopping| *stopping)
+        .map(|_| None);
+    let stream = BroadcastStream::new(bus.subscribe())
+        .map(Some)
+        .merge(stopped)
+        .take_while(Option::is_some)
+        .filter_map(move |event| match event? {
+            Ok(event) if blog_id.is_none_or(|blog_id| event.blog_id() == blog_id) => {
+                Some(Event::default().event(event.name()).json_data(&event))
+            }
+            Ok(_) => None,
+            // the client missed some events and should reload what it shows
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                Some(Ok(Event::default().event("lagged").data(skipped.to_string())))
+            }
+        });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn events(
+    State(store): State<Store>,
+    Extension(shutdown): Extension<Shutdown>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, Error> {
+    Ok(event_stream(event_bus(&store)?, None, shutdown))
+}
+
+pub async fn blog_events(
+    State(store): State<Store>,
+    Extension(shutdown): Extension<Shutdown>,
+    Path(blog_id): Path<i64>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, Error> {
+    let bus = event_bus(&store)?;
+    store.get_single_blog(blog_id).await?;
+    Ok(event_stream(bus, Some(blog_id), shutdown))
+}
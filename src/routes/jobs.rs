@@ -0,0 +1,37 @@
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+
+use crate::{
+    error::Error,
+    store::jobs::Jobs,
+    types::job::{JobOverview, JobQuery, JobRecord},
+};
+
+fn jobs(jobs: Option<Jobs>) -> Result<Jobs, Error> {
+    jobs.ok_or(Error::unavailable(
+        "Background jobs need the postgres backend".to_string(),
+    ))
+}
+
+// counts per status, the schedules and the latest jobs, `?status=dead` lists the dead letters
+pub async fn job_overview(
+    Extension(extension): Extension<Option<Jobs>>,
+    Query(query): Query<JobQuery>,
+) -> Result<Json<JobOverview>, Error> {
+    match jobs(extension)?.overview(query).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn retry_job(
+    Extension(extension): Extension<Option<Jobs>>,
+    Path(job_id): Path<i64>,
+) -> Result<Json<JobRecord>, Error> {
+    match jobs(extension)?.retry(job_id).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
@@ -1,2 +1,8 @@
 pub mod blogs;
+pub mod bulk;
+pub mod editing;
+pub mod events;
+pub mod jobs;
+pub mod monitoring;
+pub mod webhooks;
 
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde_json::json;
+
+use crate::{
+    store::{cache::CacheStats, Store},
+    utils::health::{log_writable, Health},
+};
+
+// the database gets this long to answer a probe before we call it unreachable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// hits/misses of the read cache, null when the cache is turned off
+pub async fn cache_stats(State(store): State<Store>) -> Json<Option<CacheStats>> {
+    Json(store.cache_stats())
+}
+
+// the process is up and answering, nothing else is checked
+pub async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+// 503 with the failed checks while we can't serve traffic
+pub async fn readyz(
+    State(store): State<Store>,
+    Extension(health): Extension<Health>,
+) -> impl IntoResponse {
+    let (database, migrations) = match tokio::time::timeout(PROBE_TIMEOUT, store.health()).await {
+        Ok(Ok(database)) if database.migrated() => (Ok(()), Ok(())),
+        Ok(Ok(database)) => (
+            Ok(()),
+            Err(format!(
+                "At migration {:?}, expected {:?}",
+                database.migration, database.latest_migration
+            )),
+        ),
+        Ok(Err(_)) => (
+            Err("The database is unreachable".to_string()),
+            Err("Unknown".to_string()),
+        ),
+        Err(_) => (
+            Err("The database didn't answer in time".to_string()),
+            Err("Unknown".to_string()),
+        ),
+    };
+    let disk = log_writable();
+    let shutdown = match health.shutting_down() {
+        true => Err("The server is shutting down".to_string()),
+        false => Ok(()),
+    };
+
+    let ready = database.is_ok() && migrations.is_ok() && disk.is_ok() && shutdown.is_ok();
+    let check = |result: Result<(), String>| match result {
+        Ok(_) => json!("ok"),
+        Err(e) => json!(e),
+    };
+    let body = json!({
+        "status": if ready { "ready" } else { "not ready" },
+        "checks": {
+            "database": check(database),
+            "migrations": check(migrations),
+            "log_directory": check(disk),
+            "shutdown": check(shutdown),
+        }
+    });
+    match ready {
+        true => (StatusCode::OK, Json(body)),
+        false => (StatusCode::SERVICE_UNAVAILABLE, Json(body)),
+    }
+}
+
+pub async fn status(
+    State(store): State<Store>,
+    Extension(health): Extension<Health>,
+) -> impl IntoResponse {
+    let database = match tokio::time::timeout(PROBE_TIMEOUT, store.health()).await {
+        Ok(Ok(database)) => json!(database),
+        Ok(Err(_)) => json!({ "error": "The database is unreachable" }),
+        Err(_) => json!({ "error": "The database didn't answer in time" }),
+    };
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "started_at": health.started_at.to_rfc3339(),
+        "uptime_secs": health.uptime().as_secs(),
+        "shutting_down": health.shutting_down(),
+        "database": database,
+        "cache": store.cache_stats(),
+    }))
+}
+
+// prometheus text format. the pool gauges are read at scrape time, the rest is recorded as it happens
+pub async fn prometheus_metrics(
+    State(store): State<Store>,
+    Extension(handle): Extension<PrometheusHandle>,
+) -> impl IntoResponse {
+    if let Ok(Ok(database)) = tokio::time::timeout(PROBE_TIMEOUT, store.health()).await {
+        if let Some(pool) = database.pool {
+            metrics::gauge!("db_pool_connections").set(pool.size as f64);
+            metrics::gauge!("db_pool_idle_connections").set(pool.idle as f64);
+            metrics::gauge!("db_pool_max_connections").set(pool.max_connections as f64);
+        }
+    }
+    if let Some(cache) = store.cache_stats() {
+        metrics::counter!("cache_hits_total").absolute(cache.hits);
+        metrics::counter!("cache_misses_total").absolute(cache.misses);
+        metrics::gauge!("cache_entries").set(cache.entries as f64);
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+}
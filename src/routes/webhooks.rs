@@ -0,0 +1,58 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{
+    error::Error,
+    store::{webhooks::Webhooks, Store},
+    types::webhook::{Delivery, DeliveryQuery, NewWebhook, Webhook},
+};
+
+fn webhooks(store: &Store) -> Result<Webhooks, Error> {
+    store
+        .webhooks()
+        .ok_or(Error::unavailable("Webhooks need the postgres backend".to_string()))
+}
+
+pub async fn list_webhooks(State(store): State<Store>) -> Result<Json<Vec<Webhook>>, Error> {
+    match webhooks(&store)?.list().await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn post_webhook(
+    State(store): State<Store>,
+    Json(payload): Json<NewWebhook>,
+) -> Result<(StatusCode, Json<Webhook>), Error> {
+    let payload = payload.validate()?;
+    match webhooks(&store)?.create(payload).await {
+        Ok(res) => Ok((StatusCode::CREATED, Json(res))),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn delete_webhook(
+    State(store): State<Store>,
+    Path(webhook_id): Path<i64>,
+) -> Result<StatusCode, Error> {
+    match webhooks(&store)?.delete(webhook_id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
+        Err(e) => Err(e),
+    }
+}
+
+// newest first, `?status=failed` narrows it down to what needs attention
+pub async fn webhook_deliveries(
+    State(store): State<Store>,
+    Path(webhook_id): Path<i64>,
+    Query(query): Query<DeliveryQuery>,
+) -> Result<Json<Vec<Delivery>>, Error> {
+    match webhooks(&store)?.deliveries(webhook_id, query).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
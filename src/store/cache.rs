@@ -0,0 +1,533 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use super::{events::EventBus, Storage};
+use crate::{
+    error::Error,
+    types::{
+        backup::{Backup, RestoreReport},
+        blog::{Blog, BlogChanges, NewBlog, Pagination, PublishedPost, Text},
+        bulk::{BlogFilter, BlogOperation, BulkReport, CommentAction, CommentFilter, ImportBlog, Selection},
+        comment::{Comment, NewComment},
+        event::BlogEvent,
+        health::DatabaseHealth,
+        markdown::{MarkdownImported, MarkdownPost},
+        user::{Credentials, NewUser, User},
+    },
+    utils::{conditional::IfMatch, migration::MigrationStatus},
+};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+// one entry limit shared by all the maps of a CachedStore, a list weighs one per item
+#[derive(Debug)]
+struct Budget {
+    capacity: usize,
+    used: AtomicUsize,
+}
+
+impl Budget {
+    fn reserve(&self, weight: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                (used + weight <= self.capacity).then_some(used + weight)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, weight: usize) {
+        self.used.fetch_sub(weight, Ordering::AcqRel);
+    }
+
+    fn fits(&self, weight: usize) -> bool {
+        self.used.load(Ordering::Acquire) + weight <= self.capacity
+    }
+}
+
+// a small ttl map, it only stores what the shared budget has room for
+#[derive(Debug)]
+struct TtlCache<K, V> {
+    entries: Mutex<HashMap<K, (Instant, usize, V)>>,
+    // bumped on every invalidation so a read that started before a write can't store stale data
+    generation: AtomicU64,
+    ttl: Duration,
+    budget: Arc<Budget>,
+    weigh: fn(&V) -> usize,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> TtlCache<K, V> {
+    fn new(ttl: Duration, budget: Arc<Budget>, weigh: fn(&V) -> usize) -> Self {
+        TtlCache {
+            entries: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+            ttl,
+            budget,
+            weigh,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<K, (Instant, usize, V)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn weight(&self, value: &V) -> usize {
+        (self.weigh)(value).max(1)
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.lock();
+        match entries.get(key) {
+            Some((stored_at, _, value)) if stored_at.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                if let Some((_, weight, _)) = entries.remove(key) {
+                    self.budget.release(weight);
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    // CachedStore::make_room runs first, if another insert took the room since then this one is skipped
+    fn insert(&self, key: K, value: V, generation: u64) {
+        let weight = self.weight(&value);
+        let mut entries = self.lock();
+        if generation != self.generation() {
+            return;
+        }
+        if let Some((_, old, _)) = entries.remove(&key) {
+            self.budget.release(old);
+        }
+        if self.budget.reserve(weight) {
+            entries.insert(key, (Instant::now(), weight, value));
+        }
+    }
+
+    fn invalidate(&self, key: &K) {
+        let mut entries = self.lock();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        if let Some((_, weight, _)) = entries.remove(key) {
+            self.budget.release(weight);
+        }
+    }
+
+    fn clear(&self) {
+        let mut entries = self.lock();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.budget
+            .release(entries.drain().map(|(_, (_, weight, _))| weight).sum());
+    }
+
+    fn len(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+// lets make_room evict across maps with different key and value types
+trait Evict {
+    fn purge_expired(&self);
+    fn oldest(&self) -> Option<Instant>;
+    fn evict_oldest(&self);
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Evict for TtlCache<K, V> {
+    fn purge_expired(&self) {
+        let mut released = 0;
+        self.lock().retain(|_, (stored_at, weight, _)| {
+            let keep = stored_at.elapsed() < self.ttl;
+            if !keep {
+                released += *weight;
+            }
+            keep
+        });
+        self.budget.release(released);
+    }
+
+    fn oldest(&self) -> Option<Instant> {
+        self.lock().values().map(|(stored_at, _, _)| *stored_at).min()
+    }
+
+    fn evict_oldest(&self) {
+        let mut entries = self.lock();
+        let oldest = entries
+            .iter()
+            .min_by_key(|(_, (stored_at, _, _))| *stored_at)
+            .map(|(key, _)| key.clone());
+        if let Some((_, weight, _)) = oldest.and_then(|oldest| entries.remove(&oldest)) {
+            self.budget.release(weight);
+        }
+    }
+}
+
+// sits in front of a backend and answers the read methods from memory until a write touches them
+#[derive(Debug)]
+pub struct CachedStore {
+    inner: Arc<dyn Storage>,
+    budget: Arc<Budget>,
+    blog_pages: TtlCache<Option<i64>, Vec<Blog>>,
+    blogs: TtlCache<i64, Blog>,
+    texts: TtlCache<i64, Text>,
+    comments: TtlCache<i64, Vec<Comment>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachedStore {
+    // capacity is the entry limit of the whole cache, not of each map
+    pub fn new(inner: Arc<dyn Storage>, ttl: Duration, capacity: usize) -> Self {
+        let budget = Arc::new(Budget {
+            capacity,
+            used: AtomicUsize::new(0),
+        });
+        CachedStore {
+            inner,
+            blog_pages: TtlCache::new(ttl, budget.clone(), Vec::len),
+            blogs: TtlCache::new(ttl, budget.clone(), |_| 1),
+            texts: TtlCache::new(ttl, budget.clone(), |_| 1),
+            comments: TtlCache::new(ttl, budget.clone(), Vec::len),
+            budget,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn maps(&self) -> [&dyn Evict; 4] {
+        [&self.blog_pages, &self.blogs, &self.texts, &self.comments]
+    }
+
+    // expired entries go first, then the oldest entry of whichever map has it
+    fn make_room(&self, weight: usize) {
+        if weight > self.budget.capacity || self.budget.fits(weight) {
+            return;
+        }
+        self.maps().iter().for_each(|map| map.purge_expired());
+        while !self.budget.fits(weight) {
+            let oldest = self
+                .maps()
+                .into_iter()
+                .filter_map(|map| map.oldest().map(|stored_at| (stored_at, map)))
+                .min_by_key(|(stored_at, _)| *stored_at);
+            match oldest {
+                Some((_, map)) => map.evict_oldest(),
+                None => break,
+            }
+        }
+    }
+
+    // bulk writes can touch any blog, so they drop everything instead of tracking ids
+    fn clear_all(&self) {
+        self.blog_pages.clear();
+        self.blogs.clear();
+        self.texts.clear();
+        self.comments.clear();
+    }
+
+    async fn cached<K, V, F>(&self, cache: &TtlCache<K, V>, key: K, load: F) -> Result<V, Error>
+    where
+        K: Eq + Hash + Clone,
+        V: Clone,
+        F: std::future::Future<Output = Result<V, Error>>,
+    {
+        if let Some(value) = cache.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let generation = cache.generation();
+        let value = load.await?;
+        self.make_room(cache.weight(&value));
+        cache.insert(key, value.clone(), generation);
+        Ok(value)
+    }
+}
+
+#[async_trait]
+impl Storage for CachedStore {
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        self.inner.migrate().await
+    }
+
+    async fn revert(&self, target: i64) -> Result<(), sqlx::Error> {
+        self.inner.revert(target).await
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+        self.inner.migration_status().await
+    }
+
+    async fn health(&self) -> Result<DatabaseHealth, Error> {
+        self.inner.health().await
+    }
+
+    async fn close(&self) {
+        self.inner.close().await;
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.blog_pages.len()
+                + self.blogs.len()
+                + self.texts.len()
+                + self.comments.len(),
+        })
+    }
+
+    fn events(&self) -> Option<EventBus> {
+        self.inner.events()
+    }
+
+    fn evict(&self, event: Option<&BlogEvent>) {
+        let Some(event) = event else {
+            self.clear_all();
+            return;
+        };
+        match event {
+            BlogEvent::blog_published { .. } => self.blog_pages.clear(),
+            BlogEvent::blog_updated { blog } => {
+                self.blogs.invalidate(&blog.id.0);
+                self.blog_pages.clear();
+            }
+            BlogEvent::blog_deleted { blog_id } => {
+                self.blogs.invalidate(blog_id);
+                self.texts.invalidate(blog_id);
+                self.comments.invalidate(blog_id);
+                self.blog_pages.clear();
+            }
+            BlogEvent::comment_added { comment } => self.comments.invalidate(&comment.blog_id),
+            BlogEvent::comment_deleted { blog_id, .. } => self.comments.invalidate(blog_id),
+            BlogEvent::likes_changed { blog_id, .. } => {
+                self.blogs.invalidate(blog_id);
+                self.blog_pages.clear();
+            }
+            BlogEvent::text_updated { blog_id } => self.texts.invalidate(blog_id),
+        }
+    }
+
+    async fn blogs(&self, page: Pagination) -> Result<Vec<Blog>, Error> {
+        self.cached(&self.blog_pages, page.page, self.inner.blogs(page))
+            .await
+    }
+
+    async fn get_single_blog(&self, blog_id: i64) -> Result<Blog, Error> {
+        self.cached(&self.blogs, blog_id, self.inner.get_single_blog(blog_id))
+            .await
+    }
+
+    async fn post_blog(&self, blog: NewBlog) -> Result<Blog, Error> {
+        let created = self.inner.post_blog(blog).await;
+        self.blog_pages.clear();
+        created
+    }
+
+    async fn put_blog(&self, blog: Blog, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let updated = self.inner.put_blog(blog, blog_id, if_match).await;
+        self.blogs.invalidate(&blog_id);
+        self.blog_pages.clear();
+        updated
+    }
+
+    async fn patch_blog(&self, changes: BlogChanges, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let updated = self.inner.patch_blog(changes, blog_id, if_match).await;
+        self.blogs.invalidate(&blog_id);
+        self.blog_pages.clear();
+        updated
+    }
+
+    async fn delete_blog(&self, blog_id: i64) -> Result<bool, Error> {
+        let deleted = self.inner.delete_blog(blog_id).await;
+        self.blogs.invalidate(&blog_id);
+        self.texts.invalidate(&blog_id);
+        self.comments.invalidate(&blog_id);
+        self.blog_pages.clear();
+        deleted
+    }
+
+    async fn blog_text(&self, blog_id: i64) -> Result<Text, Error> {
+        self.cached(&self.texts, blog_id, self.inner.blog_text(blog_id))
+            .await
+    }
+
+    async fn put_blog_text(&self, text: Text, blog_id: i64, if_match: Option<IfMatch>) -> Result<Text, Error> {
+        let updated = self.inner.put_blog_text(text, blog_id, if_match).await;
+        self.texts.invalidate(&blog_id);
+        self.blogs.invalidate(&blog_id);
+        self.blog_pages.clear();
+        updated
+    }
+
+    async fn post_blog_text(&self, text: Text, blog_id: i64) -> Result<Text, Error> {
+        let created = self.inner.post_blog_text(text, blog_id).await;
+        self.texts.invalidate(&blog_id);
+        created
+    }
+
+    async fn get_blog_comments(&self, blog_id: i64) -> Result<Vec<Comment>, Error> {
+        self.cached(&self.comments, blog_id, self.inner.get_blog_comments(blog_id))
+            .await
+    }
+
+    async fn post_blog_comments(
+        &self,
+        comment: NewComment,
+        blog_id: i64,
+    ) -> Result<Comment, Error> {
+        let created = self.inner.post_blog_comments(comment, blog_id).await;
+        self.comments.invalidate(&blog_id);
+        created
+    }
+
+    async fn delete_blog_comment(&self, blog_id: i64, comment_id: i64) -> Result<bool, Error> {
+        let deleted = self.inner.delete_blog_comment(blog_id, comment_id).await;
+        self.comments.invalidate(&blog_id);
+        deleted
+    }
+
+    async fn blog_tags(&self, blog_id: i64) -> Result<Vec<String>, Error> {
+        self.inner.blog_tags(blog_id).await
+    }
+
+    async fn bulk_comments(
+        &self,
+        action: CommentAction,
+        selection: Selection<CommentFilter>,
+    ) -> Result<BulkReport, Error> {
+        let report = self.inner.bulk_comments(action, selection).await;
+        self.clear_all();
+        report
+    }
+
+    async fn bulk_blogs(
+        &self,
+        operation: BlogOperation,
+        selection: Selection<BlogFilter>,
+    ) -> Result<BulkReport, Error> {
+        let report = self.inner.bulk_blogs(operation, selection).await;
+        self.clear_all();
+        report
+    }
+
+    async fn import_blogs(&self, blogs: Vec<ImportBlog>) -> Result<BulkReport, Error> {
+        let report = self.inner.import_blogs(blogs).await;
+        self.blog_pages.clear();
+        report
+    }
+
+    async fn import_markdown(&self, posts: Vec<MarkdownPost>) -> Result<Vec<MarkdownImported>, Error> {
+        let imported = self.inner.import_markdown(posts).await;
+        self.clear_all();
+        imported
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<User, Error> {
+        self.inner.create_user(user).await
+    }
+
+    async fn set_password(&self, username: &str, password_hash: String) -> Result<bool, Error> {
+        self.inner.set_password(username, password_hash).await
+    }
+
+    async fn set_role(&self, username: &str, role: String) -> Result<bool, Error> {
+        self.inner.set_role(username, role).await
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>, Error> {
+        self.inner.credentials(username).await
+    }
+
+    async fn published_posts(&self) -> Result<Vec<PublishedPost>, Error> {
+        self.inner.published_posts().await
+    }
+
+    async fn backup(&self) -> Result<Backup, Error> {
+        self.inner.backup().await
+    }
+
+    async fn restore(&self, backup: Backup) -> Result<RestoreReport, Error> {
+        let report = self.inner.restore(backup).await;
+        self.clear_all();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn the_capacity_is_shared_by_every_map() {
+        let memory = MemoryStore::new();
+        for author in ["ada", "grace", "linus"] {
+            memory
+                .post_blog(NewBlog {
+                    image: None,
+                    author: author.to_string(),
+                    text: format!("by {author}"),
+                })
+                .await
+                .unwrap();
+        }
+        let cache = CachedStore::new(Arc::new(memory), Duration::from_secs(60), 4);
+
+        // the page of three blogs weighs three, a single text takes the last slot
+        cache.blogs(Pagination { page: None }).await.unwrap();
+        cache.blog_text(1).await.unwrap();
+        assert_eq!(cache.budget.used.load(Ordering::Acquire), 4);
+
+        // the page is the oldest entry, so it makes room for the next two reads
+        cache.blog_text(2).await.unwrap();
+        cache.get_single_blog(3).await.unwrap();
+        assert_eq!(cache.budget.used.load(Ordering::Acquire), 3);
+        assert_eq!(cache.blog_pages.len(), 0);
+
+        cache.clear_all();
+        assert_eq!(cache.budget.used.load(Ordering::Acquire), 0);
+    }
+
+    #[tokio::test]
+    async fn events_from_other_instances_evict() {
+        let memory = MemoryStore::new();
+        let blog = memory
+            .post_blog(NewBlog {
+                image: None,
+                author: "ada".to_string(),
+                text: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+        let bus = EventBus::local();
+        let cache = CachedStore::new(Arc::new(memory.clone()), Duration::from_secs(60), 16);
+        let store = crate::store::Store::from(cache).with_events(bus.clone(), None);
+        assert_eq!(store.get_single_blog(blog.id.0).await.unwrap().author, "ada");
+
+        // another instance writes straight to the database and only tells us through the bus
+        let mut changed = blog.clone();
+        changed.author = "grace".to_string();
+        let changed = memory.put_blog(changed, blog.id.0, None).await.unwrap();
+        assert_eq!(store.get_single_blog(blog.id.0).await.unwrap().author, "ada");
+        bus.publish(BlogEvent::blog_updated { blog: changed }).await;
+        tokio::task::yield_now().await;
+        assert_eq!(store.get_single_blog(blog.id.0).await.unwrap().author, "grace");
+    }
+}
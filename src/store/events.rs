@@ -0,0 +1,594 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use sqlx::{
+    postgres::{PgListener, PgPoolOptions},
+    PgPool,
+};
+use tokio::sync::broadcast;
+
+use serde::{Deserialize, Serialize};
+
+use super::{cache::CacheStats, postgres::PgStore, webhooks::Webhooks, Storage};
+use crate::{
+    error::Error,
+    types::{
+        backup::{Backup, RestoreReport},
+        blog::{Blog, BlogChanges, NewBlog, Pagination, PublishedPost, Text},
+        bulk::{
+            BlogFilter, BlogOperation, BulkReport, BulkStatus, CommentAction, CommentFilter,
+            ImportBlog, Selection,
+        },
+        comment::{Comment, NewComment},
+        event::BlogEvent,
+        health::DatabaseHealth,
+        markdown::{MarkdownImported, MarkdownPost, MarkdownStatus},
+        user::{Credentials, NewUser, User},
+    },
+    utils::{conditional::IfMatch, migration::MigrationStatus},
+};
+
+const CHANNEL: &str = "blog_events";
+
+// a slow sse client that falls this far behind skips ahead instead of holding everyone up
+const CAPACITY: usize = 256;
+
+// what goes through NOTIFY. its payload is capped at 8000 bytes, so rows are sent as ids and
+// every listener reads them back, the other events are small enough to go as they are
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+enum Notification {
+    blog_published(i64),
+    blog_updated(i64),
+    comment_added { blog_id: i64, comment_id: i64 },
+    event(BlogEvent),
+}
+
+impl Notification {
+    fn of(event: &BlogEvent) -> Self {
+        match event {
+            BlogEvent::blog_published { blog } => Notification::blog_published(blog.id.0),
+            BlogEvent::blog_updated { blog } => Notification::blog_updated(blog.id.0),
+            BlogEvent::comment_added { comment } => Notification::comment_added {
+                blog_id: comment.blog_id,
+                comment_id: comment.id,
+            },
+            event => Notification::event(event.clone()),
+        }
+    }
+
+    // None when the row is gone again by now, its delete event comes right after
+    async fn into_event(self, reader: &PgStore) -> Result<Option<BlogEvent>, Error> {
+        let blog = |blog_id| async move {
+            match reader.get_single_blog(blog_id).await {
+                Ok(blog) => Ok(Some(blog)),
+                Err(Error::db_query_error(sqlx::Error::RowNotFound)) => Ok(None),
+                Err(e) => Err(e),
+            }
+        };
+        Ok(match self {
+            Notification::blog_published(blog_id) => blog(blog_id)
+                .await?
+                .map(|blog| BlogEvent::blog_published { blog }),
+            Notification::blog_updated(blog_id) => blog(blog_id)
+                .await?
+                .map(|blog| BlogEvent::blog_updated { blog }),
+            Notification::comment_added { blog_id, comment_id } => reader
+                .get_blog_comments(blog_id)
+                .await?
+                .into_iter()
+                .find(|comment| comment.id == comment_id)
+                .map(|comment| BlogEvent::comment_added { comment }),
+            Notification::event(event) => Some(event),
+        })
+    }
+}
+
+// fan-out for live updates. with postgres every event goes through NOTIFY and comes back
+// through LISTEN, so all server instances see the same events in the same order
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<BlogEvent>,
+    notify: Option<PgPool>,
+}
+
+impl EventBus {
+    pub fn local() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        EventBus {
+            sender,
+            notify: None,
+        }
+    }
+
+    pub async fn postgres(db_url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(db_url)
+            .await?;
+        let mut listener = PgListener::connect_with(&pool).await?;
+        listener.listen(CHANNEL).await?;
+
+        // reads straight from the database, a cache in between could still hold the old row
+        let reader = PgStore {
+            connection: pool.clone(),
+        };
+        let bus = EventBus {
+            notify: Some(pool),
+            ..EventBus::local()
+        };
+        let sender = bus.sender.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.recv().await {
+                    Ok(notification) => {
+                        let notification =
+                            match serde_json::from_str::<Notification>(notification.payload()) {
+                                Ok(notification) => notification,
+                                Err(e) => {
+                                    tracing::warn!("Ignoring a malformed event: {}", e);
+                                    continue;
+                                }
+                            };
+                        match notification.into_event(&reader).await {
+                            Ok(Some(event)) => {
+                                let _ = sender.send(event);
+                            }
+                            Ok(None) => {}
+                            Err(e) => tracing::error!("Couldn't read back an event: {}", e),
+                        }
+                    }
+                    // the listener reconnects on the next recv, don't spin while the db is away
+                    Err(e) => {
+                        tracing::error!("Lost the event listener connection: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+        Ok(bus)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BlogEvent> {
+        self.sender.subscribe()
+    }
+
+    pub async fn publish(&self, event: BlogEvent) {
+        if let Some(pool) = &self.notify {
+            let notified = match serde_json::to_string(&Notification::of(&event)) {
+                Ok(payload) => sqlx::query("SELECT pg_notify($1, $2)")
+                    .bind(CHANNEL)
+                    .bind(payload)
+                    .execute(pool)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match notified {
+                Ok(_) => return,
+                // at least the clients of this instance still get it
+                Err(e) => tracing::error!("Couldn't notify the other instances: {}", e),
+            }
+        }
+        // nobody listening isn't an error
+        let _ = self.sender.send(event);
+    }
+}
+
+// publishes an event after every successful write that readers may be watching,
+// and queues the webhook deliveries for it when webhooks are available
+#[derive(Debug)]
+pub struct EventedStore {
+    inner: Arc<dyn Storage>,
+    bus: EventBus,
+    webhooks: Option<Webhooks>,
+}
+
+impl EventedStore {
+    pub fn new(inner: Arc<dyn Storage>, bus: EventBus, webhooks: Option<Webhooks>) -> Self {
+        // the bus carries the writes of every instance, so the layers below can forget what they changed
+        let mut events = bus.subscribe();
+        let layers = inner.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => layers.evict(Some(&event)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => layers.evict(None),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        EventedStore {
+            inner,
+            bus,
+            webhooks,
+        }
+    }
+
+    // only the instance that made the change queues webhooks, the bus reaches every instance
+    async fn emit(&self, event: BlogEvent) {
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.enqueue(&event).await;
+        }
+        self.bus.publish(event).await;
+    }
+
+    // the bulk writes only report ids, the blogs are read back for the events
+    async fn emit_blogs(&self, blog_ids: Vec<i64>, published: bool) {
+        for blog_id in blog_ids {
+            match self.inner.get_single_blog(blog_id).await {
+                Ok(blog) if published => self.emit(BlogEvent::blog_published { blog }).await,
+                Ok(blog) => self.emit(BlogEvent::blog_updated { blog }).await,
+                Err(e) => tracing::error!("Couldn't announce blog {}: {}", blog_id, e),
+            }
+        }
+    }
+
+    // the same for comments, read back per blog since that's what the store offers
+    async fn emit_comments(&self, comment_ids: BTreeMap<i64, Vec<i64>>) {
+        for (blog_id, ids) in comment_ids {
+            match self.inner.get_blog_comments(blog_id).await {
+                Ok(comments) => {
+                    for comment in comments.into_iter().filter(|comment| ids.contains(&comment.id)) {
+                        self.emit(BlogEvent::comment_added { comment }).await;
+                    }
+                }
+                Err(e) => tracing::error!("Couldn't announce the comments of blog {}: {}", blog_id, e),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for EventedStore {
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        self.inner.migrate().await
+    }
+
+    async fn revert(&self, target: i64) -> Result<(), sqlx::Error> {
+        self.inner.revert(target).await
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+        self.inner.migration_status().await
+    }
+
+    async fn health(&self) -> Result<DatabaseHealth, Error> {
+        self.inner.health().await
+    }
+
+    async fn close(&self) {
+        self.inner.close().await;
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn events(&self) -> Option<EventBus> {
+        Some(self.bus.clone())
+    }
+
+    fn webhooks(&self) -> Option<Webhooks> {
+        self.webhooks.clone()
+    }
+
+    async fn blogs(&self, page: Pagination) -> Result<Vec<Blog>, Error> {
+        self.inner.blogs(page).await
+    }
+
+    async fn get_single_blog(&self, blog_id: i64) -> Result<Blog, Error> {
+        self.inner.get_single_blog(blog_id).await
+    }
+
+    async fn post_blog(&self, blog: NewBlog) -> Result<Blog, Error> {
+        let created = self.inner.post_blog(blog).await?;
+        self.emit(BlogEvent::blog_published {
+            blog: created.clone(),
+        })
+        .await;
+        Ok(created)
+    }
+
+    // a put always replaces the likes, so we can't tell if they changed without another read
+    async fn put_blog(&self, blog: Blog, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let updated = self.inner.put_blog(blog, blog_id, if_match).await?;
+        self.emit(BlogEvent::blog_updated {
+            blog: updated.clone(),
+        })
+        .await;
+        self.emit(BlogEvent::likes_changed {
+            blog_id,
+            likes: updated.likes,
+        })
+        .await;
+        Ok(updated)
+    }
+
+    async fn patch_blog(&self, changes: BlogChanges, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let likes_changed = changes.likes.is_some();
+        let updated = self.inner.patch_blog(changes, blog_id, if_match).await?;
+        self.emit(BlogEvent::blog_updated {
+            blog: updated.clone(),
+        })
+        .await;
+        if likes_changed {
+            self.emit(BlogEvent::likes_changed {
+                blog_id,
+                likes: updated.likes,
+            })
+            .await;
+        }
+        Ok(updated)
+    }
+
+    async fn delete_blog(&self, blog_id: i64) -> Result<bool, Error> {
+        let deleted = self.inner.delete_blog(blog_id).await?;
+        if deleted {
+            self.emit(BlogEvent::blog_deleted { blog_id }).await;
+        }
+        Ok(deleted)
+    }
+
+    async fn blog_text(&self, blog_id: i64) -> Result<Text, Error> {
+        self.inner.blog_text(blog_id).await
+    }
+
+    async fn put_blog_text(&self, text: Text, blog_id: i64, if_match: Option<IfMatch>) -> Result<Text, Error> {
+        let updated = self.inner.put_blog_text(text, blog_id, if_match).await?;
+        self.emit(BlogEvent::text_updated { blog_id }).await;
+        Ok(updated)
+    }
+
+    async fn post_blog_text(&self, text: Text, blog_id: i64) -> Result<Text, Error> {
+        let created = self.inner.post_blog_text(text, blog_id).await?;
+        self.emit(BlogEvent::text_updated { blog_id }).await;
+        Ok(created)
+    }
+
+    async fn get_blog_comments(&self, blog_id: i64) -> Result<Vec<Comment>, Error> {
+        self.inner.get_blog_comments(blog_id).await
+    }
+
+    async fn post_blog_comments(
+        &self,
+        comment: NewComment,
+        blog_id: i64,
+    ) -> Result<Comment, Error> {
+        let created = self.inner.post_blog_comments(comment, blog_id).await?;
+        // new comments wait for a moderator, they're announced once they're approved
+        if created.approved == Some(true) {
+            self.emit(BlogEvent::comment_added {
+                comment: created.clone(),
+            })
+            .await;
+        }
+        Ok(created)
+    }
+
+    async fn delete_blog_comment(&self, blog_id: i64, comment_id: i64) -> Result<bool, Error> {
+        let deleted = self.inner.delete_blog_comment(blog_id, comment_id).await?;
+        if deleted {
+            self.emit(BlogEvent::comment_deleted {
+                blog_id,
+                comment_id,
+            })
+            .await;
+        }
+        Ok(deleted)
+    }
+
+    async fn blog_tags(&self, blog_id: i64) -> Result<Vec<String>, Error> {
+        self.inner.blog_tags(blog_id).await
+    }
+
+    async fn bulk_comments(
+        &self,
+        action: CommentAction,
+        selection: Selection<CommentFilter>,
+    ) -> Result<BulkReport, Error> {
+        let report = self.inner.bulk_comments(action, selection).await?;
+        // approving is what makes a comment public, rejecting or deleting takes it down again
+        let mut approved = BTreeMap::<i64, Vec<i64>>::new();
+        for item in &report.results {
+            let Some(blog_id) = item.blog_id else {
+                continue;
+            };
+            match item.status {
+                BulkStatus::approved => approved.entry(blog_id).or_default().push(item.id),
+                BulkStatus::rejected | BulkStatus::deleted => {
+                    self.emit(BlogEvent::comment_deleted {
+                        blog_id,
+                        comment_id: item.id,
+                    })
+                    .await
+                }
+                _ => {}
+            }
+        }
+        self.emit_comments(approved).await;
+        Ok(report)
+    }
+
+    async fn bulk_blogs(
+        &self,
+        operation: BlogOperation,
+        selection: Selection<BlogFilter>,
+    ) -> Result<BulkReport, Error> {
+        let report = self.inner.bulk_blogs(operation, selection).await?;
+        // tags aren't part of a blog, (un)archiving is
+        let updated = report
+            .results
+            .iter()
+            .filter(|item| matches!(item.status, BulkStatus::archived | BulkStatus::unarchived))
+            .map(|item| item.id)
+            .collect();
+        self.emit_blogs(updated, false).await;
+        Ok(report)
+    }
+
+    async fn import_blogs(&self, blogs: Vec<ImportBlog>) -> Result<BulkReport, Error> {
+        let report = self.inner.import_blogs(blogs).await?;
+        let imported = report
+            .results
+            .iter()
+            .filter(|item| matches!(item.status, BulkStatus::imported))
+            .map(|item| item.id)
+            .collect();
+        self.emit_blogs(imported, true).await;
+        Ok(report)
+    }
+
+    async fn import_markdown(&self, posts: Vec<MarkdownPost>) -> Result<Vec<MarkdownImported>, Error> {
+        let imported = self.inner.import_markdown(posts).await?;
+        let of = |status| {
+            imported
+                .iter()
+                .filter(|post| post.status == status)
+                .map(|post| post.blog_id)
+                .collect()
+        };
+        self.emit_blogs(of(MarkdownStatus::created), true).await;
+        self.emit_blogs(of(MarkdownStatus::updated), false).await;
+        Ok(imported)
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<User, Error> {
+        self.inner.create_user(user).await
+    }
+
+    async fn set_password(&self, username: &str, password_hash: String) -> Result<bool, Error> {
+        self.inner.set_password(username, password_hash).await
+    }
+
+    async fn set_role(&self, username: &str, role: String) -> Result<bool, Error> {
+        self.inner.set_role(username, role).await
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>, Error> {
+        self.inner.credentials(username).await
+    }
+
+    async fn published_posts(&self) -> Result<Vec<PublishedPost>, Error> {
+        self.inner.published_posts().await
+    }
+
+    async fn backup(&self) -> Result<Backup, Error> {
+        self.inner.backup().await
+    }
+
+    // nobody is watching an empty database, but webhook receivers still learn about the restored blogs
+    async fn restore(&self, backup: Backup) -> Result<RestoreReport, Error> {
+        let report = self.inner.restore(backup).await?;
+        self.emit_blogs(report.blog_ids.values().copied().collect(), true)
+            .await;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::{memory::MemoryStore, Store},
+        types::blog::NewBlog,
+    };
+
+    #[tokio::test]
+    async fn likes_and_text_changes_are_announced() {
+        let bus = EventBus::local();
+        let store = Store::from(MemoryStore::new()).with_events(bus.clone(), None);
+        let blog = store
+            .post_blog(NewBlog {
+                image: None,
+                author: "ada".to_string(),
+                text: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+        let mut events = bus.subscribe();
+
+        let likes = BlogChanges {
+            likes: Some(7),
+            ..Default::default()
+        };
+        store.patch_blog(likes, blog.id.0, None).await.unwrap();
+        // an author change is only a blog update, not a likes change
+        let author = BlogChanges {
+            author: Some("grace".to_string()),
+            ..Default::default()
+        };
+        store.patch_blog(author, blog.id.0, None).await.unwrap();
+        let text = Text {
+            blog_id: blog.id.0,
+            text: "bye".to_string(),
+        };
+        store.put_blog_text(text, blog.id.0, None).await.unwrap();
+
+        match events.try_recv().unwrap() {
+            BlogEvent::blog_updated { blog } => assert_eq!(blog.likes, 7),
+            event => panic!("unexpected {event:?}"),
+        }
+        match events.try_recv().unwrap() {
+            BlogEvent::likes_changed { blog_id, likes } => assert_eq!((blog_id, likes), (blog.id.0, 7)),
+            event => panic!("unexpected {event:?}"),
+        }
+        match events.try_recv().unwrap() {
+            BlogEvent::blog_updated { blog } => assert_eq!(blog.author, "grace"),
+            event => panic!("unexpected {event:?}"),
+        }
+        match events.try_recv().unwrap() {
+            BlogEvent::text_updated { blog_id } => assert_eq!(blog_id, blog.id.0),
+            event => panic!("unexpected {event:?}"),
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn comments_are_announced_when_moderated() {
+        let bus = EventBus::local();
+        let store = Store::from(MemoryStore::new()).with_events(bus.clone(), None);
+        let blog = store
+            .post_blog(NewBlog {
+                image: None,
+                author: "ada".to_string(),
+                text: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+        let mut events = bus.subscribe();
+
+        let mut ids = Vec::new();
+        for author in ["grace", "linus"] {
+            let comment = NewComment {
+                blog_id: blog.id.0,
+                author: author.to_string(),
+                text: "nice".to_string(),
+                likes: 0,
+                date: String::new(),
+            };
+            ids.push(store.post_blog_comments(comment, blog.id.0).await.unwrap().id);
+        }
+        assert!(events.try_recv().is_err());
+
+        store
+            .bulk_comments(CommentAction::approve, Selection::ids(vec![ids[0]]))
+            .await
+            .unwrap();
+        store
+            .bulk_comments(CommentAction::reject, Selection::ids(vec![ids[1]]))
+            .await
+            .unwrap();
+        match events.try_recv().unwrap() {
+            BlogEvent::comment_added { comment } => assert_eq!(comment.id, ids[0]),
+            event => panic!("unexpected {event:?}"),
+        }
+        match events.try_recv().unwrap() {
+            BlogEvent::comment_deleted { blog_id, comment_id } => {
+                assert_eq!((blog_id, comment_id), (blog.id.0, ids[1]))
+            }
+            event => panic!("unexpected {event:?}"),
+        }
+        assert!(events.try_recv().is_err());
+    }
+}
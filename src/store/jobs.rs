@@ -0,0 +1,465 @@
+use std::{
+    collections::HashMap, fmt, future::Future, pin::Pin, str::FromStr, sync::Arc, time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use cron::Schedule;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::{postgres::PgPoolOptions, PgExecutor, PgPool};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::{
+    error::Error,
+    types::job::{JobCount, JobOverview, JobQuery, JobRecord, JobSchedule},
+    utils::shutdown::Shutdown,
+};
+
+// a running job that isn't finished after this is considered lost and picked up again
+pub const LEASE_SECS: i32 = 300;
+const POLL_EVERY: Duration = Duration::from_secs(5);
+const FIRST_RETRY_SECS: i32 = 30;
+const MAX_RETRY_SECS: i32 = 3600;
+
+// what a handler gets to work with
+#[derive(Debug, Clone)]
+pub struct JobContext {
+    pub connection: PgPool,
+    pub client: reqwest::Client,
+    // this run included, a retried dead job starts counting from 1 again
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+// a job is its own payload: it's stored as json and deserialized again right before it runs
+#[async_trait]
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    const KIND: &'static str;
+    const MAX_ATTEMPTS: i32 = 5;
+
+    async fn run(self, context: &JobContext) -> Result<(), String>;
+}
+
+type Handler =
+    Arc<dyn Fn(String, JobContext) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> + Send + Sync>;
+
+struct Claimed {
+    id: i64,
+    kind: String,
+    payload: String,
+    attempts: i32,
+    max_attempts: i32,
+}
+
+struct DueSchedule {
+    name: String,
+    cron: String,
+    kind: String,
+    payload: String,
+    max_attempts: i32,
+}
+
+// the jobs table is the queue, any number of instances can work off it at the same time
+#[derive(Clone)]
+pub struct Jobs {
+    connection: PgPool,
+    client: reqwest::Client,
+    handlers: HashMap<&'static str, Handler>,
+    wake: Arc<Notify>,
+}
+
+impl fmt::Debug for Jobs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jobs")
+            .field("kinds", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Jobs {
+    pub async fn connect(db_url: &str) -> Result<Self, sqlx::Error> {
+        let connection = PgPoolOptions::new()
+            .max_connections(4)
+            .acquire_timeout(Duration::from_secs(3))
+            .connect(db_url)
+            .await?;
+        let client = reqwest::Client::builder()
+            .build()
+            .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+        Ok(Jobs {
+            connection,
+            client,
+            handlers: HashMap::new(),
+            wake: Arc::new(Notify::new()),
+        })
+    }
+
+    pub fn register<J: Job>(mut self) -> Self {
+        let handler: Handler = Arc::new(|payload, context| {
+            Box::pin(async move {
+                let job: J = serde_json::from_str(&payload).map_err(|e| e.to_string())?;
+                job.run(&context).await
+            })
+        });
+        self.handlers.insert(J::KIND, handler);
+        self
+    }
+
+    // for jobs queued with insert_job, once the transaction they're in is committed
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    // keeps the next run of an existing schedule unless its cron expression changed
+    pub async fn schedule<J: Job>(&self, name: &str, cron: &str, job: &J) -> anyhow::Result<()> {
+        let next_run_at = next_run(cron)?;
+        let payload = serde_json::to_string(job)?;
+        sqlx::query!(
+            "INSERT INTO job_schedules (name, cron, kind, payload, max_attempts, next_run_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (name) DO UPDATE SET
+            cron = EXCLUDED.cron, kind = EXCLUDED.kind, payload = EXCLUDED.payload,
+            max_attempts = EXCLUDED.max_attempts,
+            next_run_at = CASE WHEN job_schedules.cron = EXCLUDED.cron
+                THEN job_schedules.next_run_at ELSE EXCLUDED.next_run_at END",
+            name,
+            cron,
+            J::KIND,
+            payload,
+            J::MAX_ATTEMPTS,
+            next_run_at,
+        )
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn overview(&self, query: JobQuery) -> Result<JobOverview, Error> {
+        let counts = sqlx::query_as!(
+            JobCount,
+            r#"SELECT status, COUNT(*) AS "count!" FROM jobs GROUP BY status ORDER BY status"#
+        )
+        .fetch_all(&self.connection)
+        .await
+        .map_err(Error::db_query_error)?;
+        let schedules = sqlx::query_as!(
+            JobSchedule,
+            "SELECT name, cron, kind, next_run_at FROM job_schedules ORDER BY name"
+        )
+        .fetch_all(&self.connection)
+        .await
+        .map_err(Error::db_query_error)?;
+        let jobs = sqlx::query_as!(
+            JobRecord,
+            "SELECT id, kind, payload, status, attempts, max_attempts, run_at, last_error,
+            created_at, finished_at
+            FROM jobs
+            WHERE ($1::TEXT IS NULL OR status = $1) AND ($2::TEXT IS NULL OR kind = $2)
+            ORDER BY id DESC LIMIT $3",
+            query.status,
+            query.kind,
+            query.limit.unwrap_or(50).clamp(1, 500),
+        )
+        .fetch_all(&self.connection)
+        .await
+        .map_err(Error::db_query_error)?;
+        Ok(JobOverview {
+            counts,
+            schedules,
+            jobs,
+        })
+    }
+
+    // gives a dead job a fresh set of attempts
+    pub async fn retry(&self, job_id: i64) -> Result<JobRecord, Error> {
+        let retried = sqlx::query_as!(
+            JobRecord,
+            "UPDATE jobs SET status = 'queued', attempts = 0, run_at = NOW() AT TIME ZONE 'UTC',
+            finished_at = NULL
+            WHERE id = $1 AND status = 'dead'
+            RETURNING id, kind, payload, status, attempts, max_attempts, run_at, last_error,
+            created_at, finished_at",
+            job_id,
+        )
+        .fetch_optional(&self.connection)
+        .await
+        .map_err(Error::db_query_error)?;
+        match retried {
+            Some(job) => {
+                self.wake.notify_one();
+                Ok(job)
+            }
+            None => Err(Error::conflict(format!("job {job_id} doesn't exist or isn't dead"))),
+        }
+    }
+
+    // the job that's running when we shut down gets to finish, the rest waits for the next start
+    pub fn spawn_worker(&self, shutdown: Shutdown) -> JoinHandle<()> {
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            while !shutdown.is_triggered() {
+                if let Err(e) = jobs.enqueue_scheduled().await {
+                    tracing::error!("Couldn't queue the scheduled jobs: {}", e);
+                }
+                while !shutdown.is_triggered() {
+                    match jobs.run_next().await {
+                        Ok(true) => continue,
+                        Ok(false) => break,
+                        Err(e) => {
+                            tracing::error!("Job worker failed: {}", e);
+                            break;
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = tokio::time::timeout(POLL_EVERY, jobs.wake.notified()) => {}
+                    _ = shutdown.triggered() => {}
+                }
+            }
+        })
+    }
+
+    pub async fn close(&self) {
+        self.connection.close().await;
+    }
+
+    async fn enqueue_scheduled(&self) -> Result<(), sqlx::Error> {
+        let mut transaction = self.connection.begin().await?;
+        let due = sqlx::query_as!(
+            DueSchedule,
+            "SELECT name, cron, kind, payload, max_attempts FROM job_schedules
+            WHERE next_run_at <= NOW() AT TIME ZONE 'UTC'
+            FOR UPDATE SKIP LOCKED"
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        for schedule in due {
+            let Ok(next_run_at) = next_run(&schedule.cron) else {
+                tracing::error!("Schedule {} has an invalid cron expression", schedule.name);
+                continue;
+            };
+            sqlx::query!(
+                "INSERT INTO jobs (kind, payload, max_attempts) VALUES ($1, $2, $3)",
+                schedule.kind,
+                schedule.payload,
+                schedule.max_attempts,
+            )
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query!(
+                "UPDATE job_schedules SET next_run_at = $2 WHERE name = $1",
+                schedule.name,
+                next_run_at,
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await
+    }
+
+    // returns whether there was a job to run
+    async fn run_next(&self) -> Result<bool, sqlx::Error> {
+        let claimed = sqlx::query_as!(
+            Claimed,
+            "UPDATE jobs SET status = 'running', attempts = attempts + 1,
+            locked_until = NOW() AT TIME ZONE 'UTC' + $1 * INTERVAL '1 second'
+            WHERE id = (
+                SELECT id FROM jobs
+                WHERE (status = 'queued' AND run_at <= NOW() AT TIME ZONE 'UTC')
+                OR (status = 'running' AND locked_until < NOW() AT TIME ZONE 'UTC')
+                ORDER BY run_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, kind, payload, attempts, max_attempts",
+            LEASE_SECS as f64,
+        )
+        .fetch_optional(&self.connection)
+        .await?;
+        let Some(job) = claimed else {
+            return Ok(false);
+        };
+
+        let outcome = match self.handlers.get(job.kind.as_str()) {
+            // spawned so a panicking handler fails its job instead of the worker
+            Some(handler) => {
+                let context = JobContext {
+                    connection: self.connection.clone(),
+                    client: self.client.clone(),
+                    attempts: job.attempts,
+                    max_attempts: job.max_attempts,
+                };
+                match tokio::spawn(handler(job.payload, context)).await {
+                    Ok(outcome) => outcome,
+                    Err(e) => Err(format!("The job panicked: {e}")),
+                }
+            }
+            None => Err(format!("No handler is registered for {:?}", job.kind)),
+        };
+
+        match outcome {
+            Ok(()) => {
+                sqlx::query!(
+                    "UPDATE jobs SET status = 'done', locked_until = NULL, last_error = NULL,
+                    finished_at = NOW() AT TIME ZONE 'UTC'
+                    WHERE id = $1",
+                    job.id,
+                )
+                .execute(&self.connection)
+                .await?;
+            }
+            // out of attempts, or nobody here could ever run it: straight to the dead letters
+            Err(error) if job.attempts >= job.max_attempts || !self.handlers.contains_key(job.kind.as_str()) => {
+                tracing::error!("Job {} ({}) is dead: {}", job.id, job.kind, error);
+                sqlx::query!(
+                    "UPDATE jobs SET status = 'dead', locked_until = NULL, last_error = $2,
+                    finished_at = NOW() AT TIME ZONE 'UTC'
+                    WHERE id = $1",
+                    job.id,
+                    error,
+                )
+                .execute(&self.connection)
+                .await?;
+            }
+            Err(error) => {
+                sqlx::query!(
+                    "UPDATE jobs SET status = 'queued', locked_until = NULL, last_error = $2,
+                    run_at = NOW() AT TIME ZONE 'UTC' + $3 * INTERVAL '1 second'
+                    WHERE id = $1",
+                    job.id,
+                    error,
+                    retry_delay(job.attempts) as f64,
+                )
+                .execute(&self.connection)
+                .await?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+// queues a job as part of the transaction that needs it done, so neither happens without the other.
+// `run_at` of None means as soon as a worker is free
+pub async fn insert_job<'e, J: Job>(
+    executor: impl PgExecutor<'e>,
+    job: &J,
+    run_at: Option<NaiveDateTime>,
+) -> Result<i64, Error> {
+    let payload = serde_json::to_string(job)
+        .map_err(|e| Error::invalid_request(format!("The job can't be stored: {e}")))?;
+    match sqlx::query_scalar!(
+        "INSERT INTO jobs (kind, payload, max_attempts, run_at)
+        VALUES ($1, $2, $3, COALESCE($4, NOW() AT TIME ZONE 'UTC'))
+        RETURNING id",
+        J::KIND,
+        payload,
+        J::MAX_ATTEMPTS,
+        run_at,
+    )
+    .fetch_one(executor)
+    .await
+    {
+        Ok(id) => Ok(id),
+        Err(e) => Err(Error::db_query_error(e)),
+    }
+}
+
+// the housekeeping every instance sets up at start, registering the same schedule twice is harmless
+pub async fn schedule_housekeeping(jobs: &Jobs) -> anyhow::Result<()> {
+    jobs.schedule(
+        "prune_webhook_deliveries",
+        "0 0 3 * * *",
+        &PruneWebhookDeliveries { older_than_days: 30 },
+    )
+    .await?;
+    jobs.schedule("prune_jobs", "0 30 3 * * *", &PruneJobs { older_than_days: 14 })
+        .await
+}
+
+// how long a job waits after its nth failed attempt: 30s, 1m, 2m, ... up to an hour
+pub fn retry_delay(attempts: i32) -> i32 {
+    FIRST_RETRY_SECS
+        .saturating_mul(1 << (attempts - 1).clamp(0, 20))
+        .min(MAX_RETRY_SECS)
+}
+
+// cron expressions have a seconds field: "0 30 3 * * *" is every day at 03:30 utc
+fn next_run(cron: &str) -> anyhow::Result<NaiveDateTime> {
+    let schedule = Schedule::from_str(cron)?;
+    match schedule.upcoming(Utc).next() {
+        Some(next) => Ok(next.naive_utc()),
+        None => Err(anyhow::anyhow!("{cron:?} never runs")),
+    }
+}
+
+// delivered webhooks only matter for a while, failed ones stay for a look
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PruneWebhookDeliveries {
+    pub older_than_days: i32,
+}
+
+#[async_trait]
+impl Job for PruneWebhookDeliveries {
+    const KIND: &'static str = "prune_webhook_deliveries";
+
+    async fn run(self, context: &JobContext) -> Result<(), String> {
+        sqlx::query!(
+            "DELETE FROM webhook_deliveries
+            WHERE status = 'delivered' AND delivered_at < NOW() - $1 * INTERVAL '1 day'",
+            self.older_than_days as f64,
+        )
+        .execute(&context.connection)
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PruneJobs {
+    pub older_than_days: i32,
+}
+
+#[async_trait]
+impl Job for PruneJobs {
+    const KIND: &'static str = "prune_jobs";
+
+    async fn run(self, context: &JobContext) -> Result<(), String> {
+        sqlx::query!(
+            "DELETE FROM jobs
+            WHERE status = 'done' AND finished_at < NOW() AT TIME ZONE 'UTC' - $1 * INTERVAL '1 day'",
+            self.older_than_days as f64,
+        )
+        .execute(&context.connection)
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cron_expressions_have_a_seconds_field() {
+        let now = Utc::now().naive_utc();
+        let next = next_run("0 30 3 * * *").unwrap();
+        assert!(next > now && next - now <= chrono::Duration::days(1));
+        assert_eq!(next.format("%H:%M:%S").to_string(), "03:30:00");
+
+        // the five field form most crontabs use is refused, not read with shifted fields
+        assert!(next_run("30 3 * * *").is_err());
+        assert!(next_run("every day").is_err());
+    }
+
+    #[test]
+    fn retries_back_off_up_to_an_hour() {
+        let delays = (1..=9).map(retry_delay).collect::<Vec<_>>();
+        assert_eq!(delays, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        // a job that never ran before doesn't wait less than the first retry
+        assert_eq!(retry_delay(0), 30);
+        assert_eq!(retry_delay(1000), 3600);
+    }
+}
@@ -0,0 +1,803 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::Deserialize;
+
+use super::{remapped, Storage};
+use crate::{
+    error::Error,
+    types::{
+        backup::{Backup, BackupBlog, BackupSource, BackupUser, RestoreReport},
+        blog::{Blog, BlogChanges, BlogID, NewBlog, Pagination, PublishedPost, Text},
+        bulk::{
+            BlogFilter, BlogOperation, BulkReport, BulkStatus, CommentAction, CommentFilter,
+            ImportBlog, Selection,
+        },
+        comment::{Comment, NewComment},
+        health::DatabaseHealth,
+        markdown::{MarkdownImported, MarkdownPost, MarkdownStatus},
+        user::{Credentials, NewUser, User},
+    },
+    utils::{conditional::IfMatch, migration::MigrationStatus},
+};
+
+// nothing in here survives a restart, it's for `--ephemeral` demos and tests
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    data: Arc<RwLock<MemoryData>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryData {
+    blogs: BTreeMap<i64, Blog>,
+    texts: HashMap<i64, Text>,
+    comments: BTreeMap<i64, Comment>,
+    tags: HashMap<i64, BTreeSet<String>>,
+    archived: HashSet<i64>,
+    // source path -> what was imported from it
+    sources: HashMap<String, MarkdownSource>,
+    // id -> the user and their password hash
+    users: BTreeMap<i64, (User, String)>,
+    last_blog_id: i64,
+    last_comment_id: i64,
+    last_user_id: i64,
+}
+
+#[derive(Debug, Clone)]
+struct MarkdownSource {
+    slug: String,
+    blog_id: i64,
+    checksum: String,
+}
+
+// {"blogs": [{"image": null, "author": "...", "text": "...", "comments": [{"author": "...", "text": "..."}]}]}
+#[derive(Debug, Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub blogs: Vec<FixtureBlog>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixtureBlog {
+    pub image: Option<String>,
+    pub author: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub comments: Vec<FixtureComment>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixtureComment {
+    pub author: String,
+    pub text: String,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    pub async fn from_fixture(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let fixture: Fixture = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let store = MemoryStore::new();
+        for blog in fixture.blogs {
+            let created = store
+                .post_blog(NewBlog {
+                    image: blog.image,
+                    author: blog.author,
+                    text: blog.text,
+                })
+                .await
+                .map_err(|_| anyhow::anyhow!("couldn't seed the in-memory store"))?;
+            for comment in blog.comments {
+                store
+                    .insert_comment(created.id.0, comment.author, comment.text)
+                    .map_err(|_| anyhow::anyhow!("couldn't seed the in-memory store"))?;
+            }
+        }
+        Ok(store)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, MemoryData> {
+        self.data.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, MemoryData> {
+        self.data.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn insert_comment(&self, blog_id: i64, author: String, text: String) -> Result<Comment, Error> {
+        let mut data = self.write();
+        if !data.blogs.contains_key(&blog_id) {
+            return Err(Error::db_query_error(sqlx::Error::RowNotFound));
+        }
+        data.last_comment_id += 1;
+        let comment = Comment {
+            id: data.last_comment_id,
+            blog_id,
+            author,
+            text,
+            likes: 0,
+            date: Utc::now().naive_utc(),
+            approved: None,
+        };
+        data.comments.insert(comment.id, comment.clone());
+        Ok(comment)
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStore {
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn revert(&self, _target: i64) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+
+    async fn health(&self) -> Result<DatabaseHealth, Error> {
+        Ok(DatabaseHealth {
+            backend: "memory",
+            pool: None,
+            migration: None,
+            latest_migration: None,
+        })
+    }
+
+    async fn blogs(&self, page: Pagination) -> Result<Vec<Blog>, Error> {
+        let data = self.read();
+        let listed = data.blogs.len() - data.archived.len();
+        let (offset, limit) = page.calculate_items(listed as i64)?;
+        let blogs = data
+            .blogs
+            .values()
+            .filter(|blog| !data.archived.contains(&blog.id.0))
+            .skip(offset as usize)
+            .cloned();
+        Ok(match limit {
+            Some(limit) => blogs.take(limit as usize).collect(),
+            None => blogs.collect(),
+        })
+    }
+
+    async fn get_single_blog(&self, blog_id: i64) -> Result<Blog, Error> {
+        self.read()
+            .blogs
+            .get(&blog_id)
+            .cloned()
+            .ok_or(Error::db_query_error(sqlx::Error::RowNotFound))
+    }
+
+    async fn post_blog(&self, blog: NewBlog) -> Result<Blog, Error> {
+        let mut data = self.write();
+        data.last_blog_id += 1;
+        let now = Utc::now().naive_utc();
+        let created = Blog {
+            id: BlogID(data.last_blog_id),
+            image: blog.image,
+            author: blog.author,
+            date: now,
+            updated_at: now,
+            likes: 0,
+            bookmarks: 0,
+        };
+        data.blogs.insert(created.id.0, created.clone());
+        if !blog.text.is_empty() {
+            data.texts.insert(
+                created.id.0,
+                Text {
+                    blog_id: created.id.0,
+                    text: blog.text,
+                },
+            );
+        }
+        Ok(created)
+    }
+
+    async fn put_blog(&self, blog: Blog, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let mut data = self.write();
+        if let Some(if_match) = if_match {
+            if_match.check(data.blogs.get(&blog_id))?;
+        }
+        match data.blogs.get_mut(&blog_id) {
+            Some(stored) => {
+                stored.image = blog.image;
+                stored.author = blog.author;
+                stored.updated_at = Utc::now().naive_utc();
+                stored.likes = blog.likes;
+                stored.bookmarks = blog.bookmarks;
+                Ok(stored.clone())
+            }
+            None => Err(Error::db_query_error(sqlx::Error::RowNotFound)),
+        }
+    }
+
+    async fn patch_blog(&self, changes: BlogChanges, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let mut data = self.write();
+        if let Some(if_match) = if_match {
+            if_match.check(data.blogs.get(&blog_id))?;
+        }
+        let Some(stored) = data.blogs.get_mut(&blog_id) else {
+            return Err(Error::db_query_error(sqlx::Error::RowNotFound));
+        };
+        if changes.is_empty() {
+            return Ok(stored.clone());
+        }
+        if changes.set_image {
+            stored.image = changes.image;
+        }
+        if let Some(author) = changes.author {
+            stored.author = author;
+        }
+        if let Some(likes) = changes.likes {
+            stored.likes = likes;
+        }
+        if let Some(bookmarks) = changes.bookmarks {
+            stored.bookmarks = bookmarks;
+        }
+        stored.updated_at = Utc::now().naive_utc();
+        Ok(stored.clone())
+    }
+
+    async fn delete_blog(&self, blog_id: i64) -> Result<bool, Error> {
+        let mut data = self.write();
+        data.blogs.remove(&blog_id);
+        data.texts.remove(&blog_id);
+        data.tags.remove(&blog_id);
+        data.archived.remove(&blog_id);
+        data.sources.retain(|_, source| source.blog_id != blog_id);
+        data.comments.retain(|_, comment| comment.blog_id != blog_id);
+        Ok(true)
+    }
+
+    async fn blog_text(&self, blog_id: i64) -> Result<Text, Error> {
+        self.read()
+            .texts
+            .get(&blog_id)
+            .cloned()
+            .ok_or(Error::db_query_error(sqlx::Error::RowNotFound))
+    }
+
+    async fn put_blog_text(&self, text: Text, blog_id: i64, if_match: Option<IfMatch>) -> Result<Text, Error> {
+        let mut data = self.write();
+        if let Some(if_match) = if_match {
+            if_match.check(data.texts.get(&blog_id))?;
+        }
+        let updated = match data.texts.get_mut(&blog_id) {
+            Some(stored) => {
+                stored.text = text.text;
+                stored.clone()
+            }
+            None => return Err(Error::db_query_error(sqlx::Error::RowNotFound)),
+        };
+        if let Some(blog) = data.blogs.get_mut(&blog_id) {
+            blog.updated_at = Utc::now().naive_utc();
+        }
+        Ok(updated)
+    }
+
+    async fn post_blog_text(&self, text: Text, blog_id: i64) -> Result<Text, Error> {
+        let mut data = self.write();
+        if !data.blogs.contains_key(&blog_id) {
+            return Err(Error::db_query_error(sqlx::Error::RowNotFound));
+        }
+        if data.texts.contains_key(&blog_id) {
+            return Err(Error::conflict(format!("blog {blog_id} already has a text")));
+        }
+        let text = Text {
+            blog_id,
+            text: text.text,
+        };
+        data.texts.insert(blog_id, text.clone());
+        Ok(text)
+    }
+
+    async fn get_blog_comments(&self, blog_id: i64) -> Result<Vec<Comment>, Error> {
+        Ok(self
+            .read()
+            .comments
+            .values()
+            .filter(|comment| comment.blog_id == blog_id && comment.approved == Some(true))
+            .cloned()
+            .collect())
+    }
+
+    async fn post_blog_comments(
+        &self,
+        comment: NewComment,
+        blog_id: i64,
+    ) -> Result<Comment, Error> {
+        self.insert_comment(blog_id, comment.author, comment.text)
+    }
+
+    async fn delete_blog_comment(&self, blog_id: i64, comment_id: i64) -> Result<bool, Error> {
+        let mut data = self.write();
+        match data.comments.get(&comment_id) {
+            Some(comment) if comment.blog_id == blog_id => {
+                data.comments.remove(&comment_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn blog_tags(&self, blog_id: i64) -> Result<Vec<String>, Error> {
+        Ok(self
+            .read()
+            .tags
+            .get(&blog_id)
+            .map(|tags| tags.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn bulk_comments(
+        &self,
+        action: CommentAction,
+        selection: Selection<CommentFilter>,
+    ) -> Result<BulkReport, Error> {
+        let mut data = self.write();
+        let ids = match selection {
+            Selection::ids(ids) => ids,
+            Selection::filter(filter) => data
+                .comments
+                .values()
+                .filter(|comment| filter.blog_id.is_none_or(|blog_id| comment.blog_id == blog_id))
+                .filter(|comment| filter.author.as_ref().is_none_or(|author| &comment.author == author))
+                .filter(|comment| {
+                    filter.contains.as_ref().is_none_or(|contains| {
+                        comment.text.to_lowercase().contains(&contains.to_lowercase())
+                    })
+                })
+                .filter(|comment| {
+                    filter
+                        .moderation
+                        .is_none_or(|moderation| comment.approved == moderation.as_approved())
+                })
+                .map(|comment| comment.id)
+                .collect(),
+        };
+
+        let mut report = BulkReport::default();
+        for id in ids {
+            let (blog_id, status) = match action {
+                CommentAction::delete => match data.comments.remove(&id) {
+                    Some(comment) => (Some(comment.blog_id), BulkStatus::deleted),
+                    None => (None, BulkStatus::not_found),
+                },
+                CommentAction::approve => match data.comments.get_mut(&id) {
+                    Some(comment) => {
+                        comment.approved = Some(true);
+                        (Some(comment.blog_id), BulkStatus::approved)
+                    }
+                    None => (None, BulkStatus::not_found),
+                },
+                CommentAction::reject => match data.comments.get_mut(&id) {
+                    Some(comment) => {
+                        comment.approved = Some(false);
+                        (Some(comment.blog_id), BulkStatus::rejected)
+                    }
+                    None => (None, BulkStatus::not_found),
+                },
+            };
+            report.push_comment(id, blog_id, status);
+        }
+        Ok(report)
+    }
+
+    async fn bulk_blogs(
+        &self,
+        operation: BlogOperation,
+        selection: Selection<BlogFilter>,
+    ) -> Result<BulkReport, Error> {
+        let mut data = self.write();
+        let ids = match selection {
+            Selection::ids(ids) => ids,
+            Selection::filter(filter) => data
+                .blogs
+                .values()
+                .filter(|blog| filter.author.as_ref().is_none_or(|author| &blog.author == author))
+                .filter(|blog| {
+                    filter.tag.as_ref().is_none_or(|tag| {
+                        data.tags.get(&blog.id.0).is_some_and(|tags| tags.contains(tag))
+                    })
+                })
+                .map(|blog| blog.id.0)
+                .collect(),
+        };
+
+        let mut report = BulkReport::default();
+        for id in ids {
+            if !data.blogs.contains_key(&id) {
+                report.push(id, BulkStatus::not_found);
+                continue;
+            }
+            let (changed, done) = match &operation {
+                BlogOperation::tag(tag) => (
+                    data.tags.entry(id).or_default().insert(tag.clone()),
+                    BulkStatus::tagged,
+                ),
+                BlogOperation::untag(tag) => (
+                    data.tags.get_mut(&id).is_some_and(|tags| tags.remove(tag)),
+                    BulkStatus::untagged,
+                ),
+                BlogOperation::archive => (data.archived.insert(id), BulkStatus::archived),
+                BlogOperation::unarchive => (data.archived.remove(&id), BulkStatus::unarchived),
+            };
+            if changed && matches!(operation, BlogOperation::archive | BlogOperation::unarchive) {
+                if let Some(blog) = data.blogs.get_mut(&id) {
+                    blog.updated_at = Utc::now().naive_utc();
+                }
+            }
+            report.push(id, if changed { done } else { BulkStatus::unchanged });
+        }
+        Ok(report)
+    }
+
+    async fn import_blogs(&self, blogs: Vec<ImportBlog>) -> Result<BulkReport, Error> {
+        let mut report = BulkReport::default();
+        for blog in blogs {
+            let created = self
+                .post_blog(NewBlog {
+                    image: blog.image,
+                    author: blog.author,
+                    text: blog.text,
+                })
+                .await?;
+            let tags = blog
+                .tags
+                .iter()
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty());
+            self.write().tags.entry(created.id.0).or_default().extend(tags);
+            report.push(created.id.0, BulkStatus::imported);
+        }
+        Ok(report)
+    }
+
+    async fn import_markdown(&self, posts: Vec<MarkdownPost>) -> Result<Vec<MarkdownImported>, Error> {
+        let mut data = self.write();
+        // checked up front, the memory store has no transaction to roll back
+        for post in &posts {
+            let owner = data
+                .sources
+                .iter()
+                .find(|(source, known)| known.slug == post.slug && **source != post.source);
+            if let Some((other, _)) = owner {
+                if data.sources.contains_key(&post.source) {
+                    return Err(Error::conflict(format!(
+                        "{} and {} both have the slug \"{}\"",
+                        post.source, other, post.slug
+                    )));
+                }
+            }
+        }
+
+        let now = Utc::now().naive_utc();
+        let mut imported = Vec::new();
+        for post in posts {
+            let checksum = post.checksum();
+            // a renamed file keeps its post through the slug, a changed slug keeps it through the path
+            let known = match data.sources.get(&post.source) {
+                Some(known) => Some((post.source.clone(), known.clone())),
+                None => data
+                    .sources
+                    .iter()
+                    .find(|(_, known)| known.slug == post.slug)
+                    .map(|(source, known)| (source.clone(), known.clone())),
+            };
+
+            let (blog_id, status) = match known {
+                Some((source, known)) if source == post.source && known.checksum == checksum => {
+                    imported.push(MarkdownImported {
+                        source: post.source,
+                        blog_id: known.blog_id,
+                        status: MarkdownStatus::unchanged,
+                    });
+                    continue;
+                }
+                Some((source, known)) => {
+                    data.sources.remove(&source);
+                    if let Some(blog) = data.blogs.get_mut(&known.blog_id) {
+                        blog.image = post.image;
+                        blog.author = post.author;
+                        blog.date = post.date.unwrap_or(blog.date);
+                        blog.updated_at = now;
+                    }
+                    (known.blog_id, MarkdownStatus::updated)
+                }
+                None => {
+                    data.last_blog_id += 1;
+                    let id = data.last_blog_id;
+                    data.blogs.insert(
+                        id,
+                        Blog {
+                            id: BlogID(id),
+                            image: post.image,
+                            author: post.author,
+                            date: post.date.unwrap_or(now),
+                            updated_at: now,
+                            likes: 0,
+                            bookmarks: 0,
+                        },
+                    );
+                    (id, MarkdownStatus::created)
+                }
+            };
+
+            match post.archived {
+                true => data.archived.insert(blog_id),
+                false => data.archived.remove(&blog_id),
+            };
+            data.tags.insert(blog_id, post.tags.into_iter().collect());
+            data.texts.insert(
+                blog_id,
+                Text {
+                    blog_id,
+                    text: post.text,
+                },
+            );
+            data.sources.insert(
+                post.source.clone(),
+                MarkdownSource {
+                    slug: post.slug,
+                    blog_id,
+                    checksum,
+                },
+            );
+            imported.push(MarkdownImported {
+                source: post.source,
+                blog_id,
+                status,
+            });
+        }
+        Ok(imported)
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<User, Error> {
+        let mut data = self.write();
+        if data.users.values().any(|(existing, _)| existing.username == user.username) {
+            return Err(Error::conflict(format!("the username {:?} is taken", user.username)));
+        }
+        data.last_user_id += 1;
+        let now = Utc::now().naive_utc();
+        let created = User {
+            id: data.last_user_id,
+            username: user.username,
+            role: user.role,
+            created_at: now,
+            updated_at: now,
+        };
+        data.users.insert(created.id, (created.clone(), user.password_hash));
+        Ok(created)
+    }
+
+    async fn set_password(&self, username: &str, password_hash: String) -> Result<bool, Error> {
+        let mut data = self.write();
+        match data.users.values_mut().find(|(user, _)| user.username == username) {
+            Some((user, hash)) => {
+                user.updated_at = Utc::now().naive_utc();
+                *hash = password_hash;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_role(&self, username: &str, role: String) -> Result<bool, Error> {
+        let mut data = self.write();
+        match data.users.values_mut().find(|(user, _)| user.username == username) {
+            Some((user, _)) => {
+                user.updated_at = Utc::now().naive_utc();
+                user.role = role;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>, Error> {
+        Ok(self
+            .read()
+            .users
+            .values()
+            .find(|(user, _)| user.username == username)
+            .map(|(user, password_hash)| Credentials {
+                role: user.role.clone(),
+                password_hash: password_hash.clone(),
+            }))
+    }
+
+    async fn published_posts(&self) -> Result<Vec<PublishedPost>, Error> {
+        let data = self.read();
+        Ok(data
+            .blogs
+            .values()
+            .filter(|blog| !data.archived.contains(&blog.id.0))
+            .map(|blog| PublishedPost {
+                id: blog.id.0,
+                image: blog.image.clone(),
+                author: blog.author.clone(),
+                date: blog.date,
+                updated_at: blog.updated_at,
+                text: data
+                    .texts
+                    .get(&blog.id.0)
+                    .map(|text| text.text.clone())
+                    .unwrap_or_default(),
+                tags: data
+                    .tags
+                    .get(&blog.id.0)
+                    .map(|tags| tags.iter().cloned().collect())
+                    .unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn backup(&self) -> Result<Backup, Error> {
+        let data = self.read();
+        let blogs = data
+            .blogs
+            .values()
+            .map(|blog| BackupBlog {
+                id: blog.id.0,
+                image: blog.image.clone(),
+                author: blog.author.clone(),
+                date: blog.date,
+                updated_at: blog.updated_at,
+                likes: blog.likes,
+                bookmarks: blog.bookmarks,
+                archived: data.archived.contains(&blog.id.0),
+                tags: data
+                    .tags
+                    .get(&blog.id.0)
+                    .map(|tags| tags.iter().cloned().collect())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        let mut texts = data.texts.values().cloned().collect::<Vec<_>>();
+        texts.sort_by_key(|text| text.blog_id);
+        let users = data
+            .users
+            .values()
+            .map(|(user, password_hash)| BackupUser {
+                id: user.id,
+                username: user.username.clone(),
+                password_hash: password_hash.clone(),
+                role: user.role.clone(),
+                created_at: user.created_at,
+                updated_at: user.updated_at,
+            })
+            .collect();
+        let mut sources = data
+            .sources
+            .iter()
+            .map(|(source, known)| BackupSource {
+                source: source.clone(),
+                slug: known.slug.clone(),
+                blog_id: known.blog_id,
+                checksum: known.checksum.clone(),
+            })
+            .collect::<Vec<_>>();
+        sources.sort_by(|a, b| a.source.cmp(&b.source));
+        Ok(Backup {
+            blogs,
+            texts,
+            comments: data.comments.values().cloned().collect(),
+            users,
+            sources,
+        })
+    }
+
+    async fn restore(&self, backup: Backup) -> Result<RestoreReport, Error> {
+        let mut data = self.write();
+        if !data.blogs.is_empty() || !data.users.is_empty() {
+            return Err(Error::conflict(
+                "The database isn't empty, a backup only restores into an empty one".to_string(),
+            ));
+        }
+
+        // checked up front, the memory store has no transaction to roll back
+        let mut report = RestoreReport::default();
+        let mut next_id = data.last_blog_id;
+        for blog in &backup.blogs {
+            next_id += 1;
+            report.blog_ids.insert(blog.id, next_id);
+        }
+        for blog_id in backup
+            .texts
+            .iter()
+            .map(|text| text.blog_id)
+            .chain(backup.comments.iter().map(|comment| comment.blog_id))
+            .chain(backup.sources.iter().map(|source| source.blog_id))
+        {
+            remapped(&report, blog_id)?;
+        }
+
+        for blog in backup.blogs {
+            let id = report.blog_ids[&blog.id];
+            if blog.archived {
+                data.archived.insert(id);
+            }
+            report.tags += blog.tags.len();
+            if !blog.tags.is_empty() {
+                data.tags.insert(id, blog.tags.into_iter().collect());
+            }
+            data.blogs.insert(
+                id,
+                Blog {
+                    id: BlogID(id),
+                    image: blog.image,
+                    author: blog.author,
+                    date: blog.date,
+                    updated_at: blog.updated_at,
+                    likes: blog.likes,
+                    bookmarks: blog.bookmarks,
+                },
+            );
+            report.blogs += 1;
+        }
+        data.last_blog_id = next_id;
+
+        for text in backup.texts {
+            let blog_id = report.blog_ids[&text.blog_id];
+            data.texts.insert(
+                blog_id,
+                Text {
+                    blog_id,
+                    text: text.text,
+                },
+            );
+            report.texts += 1;
+        }
+        for comment in backup.comments {
+            data.last_comment_id += 1;
+            let id = data.last_comment_id;
+            data.comments.insert(
+                id,
+                Comment {
+                    id,
+                    blog_id: report.blog_ids[&comment.blog_id],
+                    ..comment
+                },
+            );
+            report.comments += 1;
+        }
+        for user in backup.users {
+            data.last_user_id += 1;
+            let id = data.last_user_id;
+            let restored = User {
+                id,
+                username: user.username,
+                role: user.role,
+                created_at: user.created_at,
+                updated_at: user.updated_at,
+            };
+            data.users.insert(id, (restored, user.password_hash));
+            report.users += 1;
+        }
+        for source in backup.sources {
+            data.sources.insert(
+                source.source,
+                MarkdownSource {
+                    slug: source.slug,
+                    blog_id: report.blog_ids[&source.blog_id],
+                    checksum: source.checksum,
+                },
+            );
+            report.sources += 1;
+        }
+        Ok(report)
+    }
+}
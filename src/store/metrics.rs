@@ -0,0 +1,249 @@
+use std::{future::Future, sync::Arc, time::Instant};
+
+use async_trait::async_trait;
+
+use super::{cache::CacheStats, events::EventBus, Storage};
+use crate::{
+    error::Error,
+    types::{
+        backup::{Backup, RestoreReport},
+        blog::{Blog, BlogChanges, NewBlog, Pagination, PublishedPost, Text},
+        bulk::{
+            BlogFilter, BlogOperation, BulkReport, BulkStatus, CommentAction, CommentFilter,
+            ImportBlog, Selection,
+        },
+        comment::{Comment, NewComment},
+        event::BlogEvent,
+        health::DatabaseHealth,
+        markdown::{MarkdownImported, MarkdownPost, MarkdownStatus},
+        user::{Credentials, NewUser, User},
+    },
+    utils::{conditional::IfMatch, migration::MigrationStatus},
+};
+
+// times every call that reaches the backend and counts what happened.
+// it sits under the cache, so cache hits don't show up as queries
+#[derive(Debug)]
+pub struct MeteredStore {
+    inner: Arc<dyn Storage>,
+}
+
+impl MeteredStore {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        MeteredStore { inner }
+    }
+
+    async fn timed<T>(
+        &self,
+        query: &'static str,
+        work: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let started = Instant::now();
+        let result = work.await;
+        let outcome = match result {
+            Ok(_) => "ok",
+            Err(_) => "error",
+        };
+        metrics::histogram!(
+            "db_query_duration_seconds",
+            "query" => query,
+            "outcome" => outcome,
+        )
+        .record(started.elapsed().as_secs_f64());
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for MeteredStore {
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        self.inner.migrate().await
+    }
+
+    async fn revert(&self, target: i64) -> Result<(), sqlx::Error> {
+        self.inner.revert(target).await
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+        self.inner.migration_status().await
+    }
+
+    async fn health(&self) -> Result<DatabaseHealth, Error> {
+        self.inner.health().await
+    }
+
+    async fn close(&self) {
+        self.inner.close().await;
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn events(&self) -> Option<EventBus> {
+        self.inner.events()
+    }
+
+    fn evict(&self, event: Option<&BlogEvent>) {
+        self.inner.evict(event);
+    }
+
+    async fn blogs(&self, page: Pagination) -> Result<Vec<Blog>, Error> {
+        self.timed("blogs", self.inner.blogs(page)).await
+    }
+
+    async fn get_single_blog(&self, blog_id: i64) -> Result<Blog, Error> {
+        self.timed("get_single_blog", self.inner.get_single_blog(blog_id))
+            .await
+    }
+
+    async fn post_blog(&self, blog: NewBlog) -> Result<Blog, Error> {
+        let created = self.timed("post_blog", self.inner.post_blog(blog)).await?;
+        metrics::counter!("blog_posts_created_total", "source" => "api").increment(1);
+        Ok(created)
+    }
+
+    async fn put_blog(&self, blog: Blog, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let updated = self
+            .timed("put_blog", self.inner.put_blog(blog, blog_id, if_match))
+            .await?;
+        metrics::counter!("blog_like_updates_total").increment(1);
+        Ok(updated)
+    }
+
+    async fn patch_blog(&self, changes: BlogChanges, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        let likes_changed = changes.likes.is_some();
+        let updated = self
+            .timed("patch_blog", self.inner.patch_blog(changes, blog_id, if_match))
+            .await?;
+        if likes_changed {
+            metrics::counter!("blog_like_updates_total").increment(1);
+        }
+        Ok(updated)
+    }
+
+    async fn delete_blog(&self, blog_id: i64) -> Result<bool, Error> {
+        self.timed("delete_blog", self.inner.delete_blog(blog_id))
+            .await
+    }
+
+    async fn blog_text(&self, blog_id: i64) -> Result<Text, Error> {
+        self.timed("blog_text", self.inner.blog_text(blog_id)).await
+    }
+
+    async fn put_blog_text(&self, text: Text, blog_id: i64, if_match: Option<IfMatch>) -> Result<Text, Error> {
+        self.timed("put_blog_text", self.inner.put_blog_text(text, blog_id, if_match))
+            .await
+    }
+
+    async fn post_blog_text(&self, text: Text, blog_id: i64) -> Result<Text, Error> {
+        self.timed("post_blog_text", self.inner.post_blog_text(text, blog_id))
+            .await
+    }
+
+    async fn get_blog_comments(&self, blog_id: i64) -> Result<Vec<Comment>, Error> {
+        self.timed("get_blog_comments", self.inner.get_blog_comments(blog_id))
+            .await
+    }
+
+    async fn post_blog_comments(
+        &self,
+        comment: NewComment,
+        blog_id: i64,
+    ) -> Result<Comment, Error> {
+        let created = self
+            .timed(
+                "post_blog_comments",
+                self.inner.post_blog_comments(comment, blog_id),
+            )
+            .await?;
+        metrics::counter!("blog_comments_posted_total").increment(1);
+        Ok(created)
+    }
+
+    async fn delete_blog_comment(&self, blog_id: i64, comment_id: i64) -> Result<bool, Error> {
+        self.timed(
+            "delete_blog_comment",
+            self.inner.delete_blog_comment(blog_id, comment_id),
+        )
+        .await
+    }
+
+    async fn blog_tags(&self, blog_id: i64) -> Result<Vec<String>, Error> {
+        self.timed("blog_tags", self.inner.blog_tags(blog_id)).await
+    }
+
+    async fn bulk_comments(
+        &self,
+        action: CommentAction,
+        selection: Selection<CommentFilter>,
+    ) -> Result<BulkReport, Error> {
+        self.timed("bulk_comments", self.inner.bulk_comments(action, selection))
+            .await
+    }
+
+    async fn bulk_blogs(
+        &self,
+        operation: BlogOperation,
+        selection: Selection<BlogFilter>,
+    ) -> Result<BulkReport, Error> {
+        self.timed("bulk_blogs", self.inner.bulk_blogs(operation, selection))
+            .await
+    }
+
+    async fn import_blogs(&self, blogs: Vec<ImportBlog>) -> Result<BulkReport, Error> {
+        let report = self
+            .timed("import_blogs", self.inner.import_blogs(blogs))
+            .await?;
+        let imported = report
+            .results
+            .iter()
+            .filter(|item| matches!(item.status, BulkStatus::imported))
+            .count();
+        metrics::counter!("blog_posts_created_total", "source" => "import")
+            .increment(imported as u64);
+        Ok(report)
+    }
+
+    async fn import_markdown(&self, posts: Vec<MarkdownPost>) -> Result<Vec<MarkdownImported>, Error> {
+        let imported = self
+            .timed("import_markdown", self.inner.import_markdown(posts))
+            .await?;
+        let created = imported
+            .iter()
+            .filter(|post| post.status == MarkdownStatus::created)
+            .count();
+        metrics::counter!("blog_posts_created_total", "source" => "markdown")
+            .increment(created as u64);
+        Ok(imported)
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<User, Error> {
+        self.timed("create_user", self.inner.create_user(user)).await
+    }
+
+    async fn set_password(&self, username: &str, password_hash: String) -> Result<bool, Error> {
+        self.timed("set_password", self.inner.set_password(username, password_hash))
+            .await
+    }
+
+    async fn set_role(&self, username: &str, role: String) -> Result<bool, Error> {
+        self.timed("set_role", self.inner.set_role(username, role)).await
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>, Error> {
+        self.timed("credentials", self.inner.credentials(username)).await
+    }
+
+    async fn published_posts(&self) -> Result<Vec<PublishedPost>, Error> {
+        self.timed("published_posts", self.inner.published_posts()).await
+    }
+
+    async fn backup(&self) -> Result<Backup, Error> {
+        self.timed("backup", self.inner.backup()).await
+    }
+
+    async fn restore(&self, backup: Backup) -> Result<RestoreReport, Error> {
+        self.timed("restore", self.inner.restore(backup)).await
+    }
+}
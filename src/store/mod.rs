@@ -0,0 +1,298 @@
+pub mod cache;
+pub mod events;
+pub mod jobs;
+pub mod memory;
+pub mod metrics;
+pub mod postgres;
+pub mod sqlite;
+pub mod webhooks;
+
+use std::{fmt::Debug, future::Future, ops::Deref, pin::Pin, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use sqlx::{Database, Pool, Transaction};
+
+use crate::{
+    error::{AppError, Error},
+    types::{
+        backup::{Backup, RestoreReport},
+        blog::{Blog, BlogChanges, NewBlog, Pagination, PublishedPost, Text},
+        bulk::{
+            BlogFilter, BlogOperation, BulkReport, CommentAction, CommentFilter, ImportBlog,
+            Selection,
+        },
+        comment::{Comment, NewComment},
+        event::BlogEvent,
+        health::DatabaseHealth,
+        markdown::{MarkdownImported, MarkdownPost},
+        user::{Credentials, NewUser, User},
+    },
+    utils::{conditional::IfMatch, input::db_input, migration::MigrationStatus, setting::ConnectConfig},
+};
+use cache::{CacheStats, CachedStore};
+use events::{EventBus, EventedStore};
+use metrics::MeteredStore;
+use webhooks::Webhooks;
+use postgres::PgStore;
+use sqlite::SqliteStore;
+
+// every backend the server can run on has to implement this, the handlers only talk to `Store`
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    async fn migrate(&self) -> Result<(), sqlx::Error>;
+    // reverts every applied migration newer than `target`
+    async fn revert(&self, target: i64) -> Result<(), sqlx::Error>;
+    // what the database has applied next to what this binary ships, empty without a schema
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, sqlx::Error>;
+
+    // fails when the database can't be reached
+    async fn health(&self) -> Result<DatabaseHealth, Error>;
+
+    // waits for the borrowed connections to come back, called once on shutdown
+    async fn close(&self) {}
+
+    // only the cache layer has something to report here
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    // and only the event layer has a bus to subscribe to
+    fn events(&self) -> Option<EventBus> {
+        None
+    }
+
+    // what the cache layer drops when an event comes in, from this instance or another one.
+    // None is for events that were missed, nothing cached can be trusted after that
+    fn evict(&self, _event: Option<&BlogEvent>) {}
+
+    // only there on postgres
+    fn webhooks(&self) -> Option<Webhooks> {
+        None
+    }
+
+    async fn blogs(&self, page: Pagination) -> Result<Vec<Blog>, Error>;
+    async fn get_single_blog(&self, blog_id: i64) -> Result<Blog, Error>;
+    async fn post_blog(&self, blog: NewBlog) -> Result<Blog, Error>;
+    async fn put_blog(&self, blog: Blog, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error>;
+    async fn patch_blog(&self, changes: BlogChanges, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error>;
+    async fn delete_blog(&self, blog_id: i64) -> Result<bool, Error>;
+
+    async fn blog_text(&self, blog_id: i64) -> Result<Text, Error>;
+    async fn put_blog_text(&self, text: Text, blog_id: i64, if_match: Option<IfMatch>) -> Result<Text, Error>;
+    async fn post_blog_text(&self, text: Text, blog_id: i64) -> Result<Text, Error>;
+
+    async fn get_blog_comments(&self, blog_id: i64) -> Result<Vec<Comment>, Error>;
+    async fn post_blog_comments(
+        &self,
+        comment: NewComment,
+        blog_id: i64,
+    ) -> Result<Comment, Error>;
+    async fn delete_blog_comment(&self, blog_id: i64, comment_id: i64) -> Result<bool, Error>;
+
+    async fn blog_tags(&self, blog_id: i64) -> Result<Vec<String>, Error>;
+
+    // bulk operations run in one transaction, a missing item is reported instead of failing the batch
+    async fn bulk_comments(
+        &self,
+        action: CommentAction,
+        selection: Selection<CommentFilter>,
+    ) -> Result<BulkReport, Error>;
+    async fn bulk_blogs(
+        &self,
+        operation: BlogOperation,
+        selection: Selection<BlogFilter>,
+    ) -> Result<BulkReport, Error>;
+    async fn import_blogs(&self, blogs: Vec<ImportBlog>) -> Result<BulkReport, Error>;
+    // creates or updates one post per file in a single transaction, matched by source path first and slug second
+    async fn import_markdown(&self, posts: Vec<MarkdownPost>) -> Result<Vec<MarkdownImported>, Error>;
+
+    // a taken username is a conflict, the others return false when there's no such user
+    async fn create_user(&self, user: NewUser) -> Result<User, Error>;
+    async fn set_password(&self, username: &str, password_hash: String) -> Result<bool, Error>;
+    async fn set_role(&self, username: &str, role: String) -> Result<bool, Error>;
+
+    // None when there's no such user, the hash is only ever compared against, see utils::auth
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>, Error>;
+
+    // the posts that aren't archived with their text and tags, oldest first
+    async fn published_posts(&self) -> Result<Vec<PublishedPost>, Error>;
+
+    // everything in one consistent read, see commands::backup
+    async fn backup(&self) -> Result<Backup, Error>;
+    // only into an empty database, every row gets a new id and the references follow it
+    async fn restore(&self, backup: Backup) -> Result<RestoreReport, Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+}
+
+impl Backend {
+    // the scheme of `db_url` decides which backend we connect to
+    pub fn from_url(db_url: &str) -> Result<Self, AppError> {
+        let scheme = match db_url.split_once(':') {
+            Some((scheme, _)) => scheme.to_lowercase(),
+            None if db_url.trim().is_empty() => return Err(AppError::no_db_url),
+            None => return Err(AppError::invalid_db_url),
+        };
+        match scheme.as_str() {
+            "postgres" | "postgresql" => Ok(Backend::Postgres),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(AppError::invalid_db_url),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Store {
+    backend: Arc<dyn Storage>,
+}
+
+impl Store {
+    // without anyone at the terminal a bad database is an exit code, not a prompt that waits forever
+    pub async fn new(db_url: &str, connect: &ConnectConfig, interactive: bool) -> Result<Self, AppError> {
+        let mut db_url = db_url.to_string();
+        loop {
+            match Store::connect_retrying(&db_url, connect).await {
+                Ok(store) => return Ok(store),
+                Err(e) if interactive => {
+                    eprintln!("couldn't establish a database connection: {e}");
+                    db_url = db_input()?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // the database often comes up after us in a container setup, so give it a moment
+    async fn connect_retrying(db_url: &str, connect: &ConnectConfig) -> Result<Self, AppError> {
+        let max_backoff = Duration::from_millis(connect.max_backoff_ms);
+        let mut backoff = Duration::from_millis(connect.backoff_ms).min(max_backoff);
+        let mut attempt = 1;
+        loop {
+            match Store::connect(db_url).await {
+                Err(AppError::db_connection_failed) if attempt < connect.attempts => {
+                    tracing::warn!(
+                        "Couldn't connect to the database (attempt {}/{}), retrying in {:?}",
+                        attempt,
+                        connect.attempts,
+                        backoff
+                    );
+                    eprintln!(
+                        "couldn't connect to the database (attempt {attempt}/{}), retrying in {backoff:?}",
+                        connect.attempts
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub async fn connect(db_url: &str) -> Result<Self, AppError> {
+        let store = match Backend::from_url(db_url)? {
+            Backend::Postgres => PgStore::connect(db_url).await.map(Store::from),
+            Backend::Sqlite => SqliteStore::connect(db_url).await.map(Store::from),
+        };
+        store.map_err(|_| AppError::db_connection_failed)
+    }
+
+    // goes right on top of the backend so only real queries are timed
+    pub fn with_metrics(self) -> Self {
+        Store::from(MeteredStore::new(self.backend))
+    }
+
+    // a ttl of 0 turns the cache off
+    pub fn with_cache(self, ttl: Duration, capacity: usize) -> Self {
+        if ttl.is_zero() {
+            return self;
+        }
+        Store::from(CachedStore::new(self.backend, ttl, capacity))
+    }
+
+    pub fn with_events(self, bus: EventBus, webhooks: Option<Webhooks>) -> Self {
+        Store::from(EventedStore::new(self.backend, bus, webhooks))
+    }
+}
+
+impl<S: Storage + 'static> From<S> for Store {
+    fn from(storage: S) -> Self {
+        Store {
+            backend: Arc::new(storage),
+        }
+    }
+}
+
+impl Deref for Store {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        self.backend.as_ref()
+    }
+}
+
+pub type WorkFuture<'t, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 't>>;
+
+// runs every statement of `work` inside one transaction: commit if it returns Ok, rollback otherwise.
+// anything that writes to more than one table should go through here
+pub async fn unit_of_work<DB, T, F>(pool: &Pool<DB>, work: F) -> Result<T, Error>
+where
+    DB: Database,
+    F: for<'t> FnOnce(&'t mut Transaction<'static, DB>) -> WorkFuture<'t, T>,
+{
+    let mut transaction = pool.begin().await.map_err(Error::db_query_error)?;
+    match work(&mut transaction).await {
+        Ok(result) => {
+            transaction.commit().await.map_err(Error::db_query_error)?;
+            Ok(result)
+        }
+        Err(e) => {
+            if let Err(rollback) = transaction.rollback().await {
+                tracing::error!("Rollback failed: {}", rollback);
+            }
+            Err(e)
+        }
+    }
+}
+
+// the new id of a blog restored earlier in the same run
+fn remapped(report: &RestoreReport, blog_id: i64) -> Result<i64, Error> {
+    report.blog_ids.get(&blog_id).copied().ok_or_else(|| {
+        Error::invalid_request(format!("The backup refers to blog {blog_id}, which isn't in it"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_scheme_picks_the_backend() {
+        assert_eq!(Backend::from_url("postgres://localhost/blog").unwrap(), Backend::Postgres);
+        assert_eq!(Backend::from_url("postgresql://localhost/blog").unwrap(), Backend::Postgres);
+        assert_eq!(Backend::from_url("SQLite://blog.db").unwrap(), Backend::Sqlite);
+
+        assert!(matches!(Backend::from_url("  "), Err(AppError::no_db_url)));
+        assert!(matches!(Backend::from_url("blog.db"), Err(AppError::invalid_db_url)));
+        assert!(matches!(Backend::from_url("mysql://localhost/blog"), Err(AppError::invalid_db_url)));
+    }
+
+    #[tokio::test]
+    async fn without_a_terminal_a_bad_database_is_an_error() {
+        let connect = ConnectConfig {
+            attempts: 3,
+            backoff_ms: 1,
+            max_backoff_ms: 2,
+        };
+        // the folder doesn't exist, so sqlite can't create the file
+        let result = Store::new("sqlite://./there/is/no/folder/blog.db", &connect, false).await;
+        assert!(matches!(result, Err(AppError::db_connection_failed)));
+        // a url that can never work isn't retried
+        let result = Store::new("blog.db", &connect, false).await;
+        assert!(matches!(result, Err(AppError::invalid_db_url)));
+    }
+}
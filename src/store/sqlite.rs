@@ -0,0 +1,1349 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+
+use super::{remapped, unit_of_work, Backend, Storage};
+use crate::{
+    error::Error,
+    types::{
+        backup::{Backup, BackupBlog, BackupSource, BackupUser, RestoreReport},
+        blog::{Blog, BlogChanges, BlogID, NewBlog, Pagination, PublishedPost, Text},
+        bulk::{
+            BlogFilter, BlogOperation, BulkReport, BulkStatus, CommentAction, CommentFilter,
+            ImportBlog, Selection,
+        },
+        comment::{Comment, Moderation, NewComment},
+        health::{DatabaseHealth, PoolStats},
+        markdown::{MarkdownImported, MarkdownPost, MarkdownStatus},
+        user::{Credentials, NewUser, User},
+    },
+    utils::{
+        conditional::IfMatch,
+        migration::{latest_version, migration_status, migrator, MigrationStatus},
+    },
+};
+use sqlx::{
+    migrate::Migrate,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Sqlite, SqlitePool, Transaction,
+};
+
+// single file database for small deployments and local demos (sqlite://blog.db)
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    pub connection: SqlitePool,
+}
+
+impl SqliteStore {
+    pub async fn connect(db_url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(db_url)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(10)
+            .acquire_timeout(Duration::from_secs(3))
+            .connect_with(options)
+            .await?;
+        Ok(SqliteStore {
+            connection: db_pool,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStore {
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        migrator(Backend::Sqlite).await?.run(&self.connection).await?;
+        Ok(())
+    }
+
+    async fn revert(&self, target: i64) -> Result<(), sqlx::Error> {
+        migrator(Backend::Sqlite).await?.undo(&self.connection, target).await?;
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+        let mut connection = self.connection.acquire().await?;
+        connection.ensure_migrations_table().await?;
+        let applied = connection.list_applied_migrations().await?;
+        Ok(migration_status(Backend::Sqlite, &applied))
+    }
+
+    async fn close(&self) {
+        self.connection.close().await;
+    }
+
+    async fn health(&self) -> Result<DatabaseHealth, Error> {
+        let mut connection = self
+            .connection
+            .acquire()
+            .await
+            .map_err(Error::db_query_error)?;
+        // no migrations table yet just means nothing was applied
+        let applied = connection.list_applied_migrations().await.unwrap_or_default();
+        Ok(DatabaseHealth {
+            backend: "sqlite",
+            pool: Some(PoolStats {
+                size: self.connection.size(),
+                idle: self.connection.num_idle(),
+                max_connections: self.connection.options().get_max_connections(),
+            }),
+            migration: applied.iter().map(|migration| migration.version).max(),
+            latest_migration: Some(latest_version(Backend::Sqlite)),
+        })
+    }
+
+    async fn blogs(&self, page: Pagination) -> Result<Vec<Blog>, Error> {
+        let total_items =
+            match sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!: i64" FROM main.blogs WHERE NOT archived"#)
+                .fetch_one(&self.connection)
+                .await
+            {
+                Ok(t) => t,
+                Err(e) => return Err(Error::db_query_error(e)),
+            };
+        let pagination = page.calculate_items(total_items)?;
+
+        // sqlite doesn't take NULL as "no limit", a negative limit does the same thing
+        let limit = pagination.1.unwrap_or(-1);
+        match sqlx::query_as!(
+            Blog,
+            r#"SELECT id AS "id!: BlogID", image, author, date, updated_at AS "updated_at!", likes, bookmarks AS "bookmarks: i32"
+            FROM blogs WHERE NOT archived LIMIT ?1 OFFSET ?2"#,
+            limit,
+            pagination.0,
+        )
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(blogs) => Ok(blogs),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn get_single_blog(&self, blog_id: i64) -> Result<Blog, Error> {
+        match sqlx::query_as!(
+            Blog,
+            r#"SELECT id AS "id!: BlogID", image, author, date, updated_at AS "updated_at!", likes, bookmarks AS "bookmarks: i32"
+            FROM blogs WHERE id = ?1"#,
+            blog_id,
+        )
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(blog) => Ok(blog),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn post_blog(&self, blog: NewBlog) -> Result<Blog, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(insert_blog(transaction, blog))
+        })
+        .await
+    }
+
+    async fn put_blog(&self, blog: Blog, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                if let Some(if_match) = if_match {
+                    if_match.check(locked_blog(transaction, blog_id).await?.as_ref())?;
+                }
+                match sqlx::query_as!(
+                    Blog,
+                    r#"UPDATE blogs
+            SET image = ?1, author = ?2, likes = ?3, bookmarks = ?4, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?5
+            RETURNING id AS "id!: BlogID", image, author, date, updated_at AS "updated_at!", likes, bookmarks AS "bookmarks: i32""#,
+                    blog.image,
+                    blog.author,
+                    blog.likes,
+                    blog.bookmarks,
+                    blog_id,
+                )
+                .fetch_one(&mut **transaction)
+                .await
+                {
+                    Ok(blog) => Ok(blog),
+                    Err(e) => Err(Error::db_query_error(e)),
+                }
+            })
+        })
+        .await
+    }
+
+    // only the supplied columns change, everything else keeps its current value
+    async fn patch_blog(&self, changes: BlogChanges, blog_id: i64, if_match: Option<IfMatch>) -> Result<Blog, Error> {
+        if changes.is_empty() {
+            let current = self.get_single_blog(blog_id).await;
+            if let Some(if_match) = if_match {
+                if_match.check(current.as_ref().ok())?;
+            }
+            return current;
+        }
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                if let Some(if_match) = if_match {
+                    if_match.check(locked_blog(transaction, blog_id).await?.as_ref())?;
+                }
+                match sqlx::query_as!(
+                    Blog,
+                    r#"UPDATE blogs
+            SET image = CASE WHEN ?1 THEN ?2 ELSE image END,
+                author = COALESCE(?3, author),
+                likes = COALESCE(?4, likes),
+                bookmarks = COALESCE(?5, bookmarks),
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?6
+            RETURNING id AS "id!: BlogID", image, author, date, updated_at AS "updated_at!", likes, bookmarks AS "bookmarks: i32""#,
+                    changes.set_image,
+                    changes.image,
+                    changes.author,
+                    changes.likes,
+                    changes.bookmarks,
+                    blog_id,
+                )
+                .fetch_one(&mut **transaction)
+                .await
+                {
+                    Ok(blog) => Ok(blog),
+                    Err(e) => Err(Error::db_query_error(e)),
+                }
+            })
+        })
+        .await
+    }
+
+    async fn delete_blog(&self, blog_id: i64) -> Result<bool, Error> {
+        // the foreign keys cascade too, but we don't want to depend on how old the schema is
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                sqlx::query!("DELETE FROM comments WHERE blog_id = ?1", blog_id)
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+                sqlx::query!("DELETE FROM texts WHERE blog_id = ?1", blog_id)
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+
+                match sqlx::query!("DELETE FROM blogs WHERE id = ?1", blog_id)
+                    .execute(&mut **transaction)
+                    .await
+                {
+                    Ok(result) => Ok(result.rows_affected() > 0),
+                    Err(e) => Err(Error::db_query_error(e)),
+                }
+            })
+        })
+        .await
+    }
+
+    async fn blog_text(&self, blog_id: i64) -> Result<Text, Error> {
+        match sqlx::query_as!(
+            Text,
+            "SELECT blog_id, text FROM texts
+            WHERE blog_id = ?1",
+            blog_id,
+        )
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(text) => Ok(text),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    // editing the text is editing the post, so it bumps the blog's updated_at as well
+    async fn put_blog_text(&self, text: Text, blog_id: i64, if_match: Option<IfMatch>) -> Result<Text, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                if let Some(if_match) = if_match {
+                    if_match.check(locked_text(transaction, blog_id).await?.as_ref())?;
+                }
+                let updated = sqlx::query_as!(
+                    Text,
+                    "UPDATE texts
+                    SET text = ?1
+                    WHERE blog_id = ?2
+                    RETURNING blog_id, text",
+                    text.text,
+                    blog_id,
+                )
+                .fetch_one(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?;
+
+                sqlx::query!("UPDATE blogs SET updated_at = CURRENT_TIMESTAMP WHERE id = ?1", blog_id)
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+
+                Ok(updated)
+            })
+        })
+        .await
+    }
+
+    async fn post_blog_text(&self, text: Text, blog_id: i64) -> Result<Text, Error> {
+        match sqlx::query_as!(
+            Text,
+            "INSERT INTO texts (blog_id, text) VALUES (?1, ?2)
+            RETURNING blog_id, text",
+            blog_id,
+            text.text,
+        )
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(text) => Ok(text),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn get_blog_comments(&self, blog_id: i64) -> Result<Vec<Comment>, Error> {
+        match sqlx::query_as!(
+            Comment,
+            r#"SELECT id AS "id!", blog_id, author, text, likes AS "likes: i32", date, approved FROM comments
+            WHERE blog_id = ?1 AND approved"#,
+            blog_id,
+        )
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(comments) => Ok(comments),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn post_blog_comments(
+        &self,
+        comment: NewComment,
+        blog_id: i64,
+    ) -> Result<Comment, Error> {
+        match sqlx::query_as!(
+            Comment,
+            r#"INSERT INTO comments
+        (blog_id, author, text, likes, approved)
+        VALUES (?1, ?2, ?3, 0, NULL)
+        RETURNING id AS "id!", blog_id, author, text, likes AS "likes: i32", date, approved"#,
+            blog_id,
+            comment.author,
+            comment.text,
+        )
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(comment) => Ok(comment),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn delete_blog_comment(&self, blog_id: i64, comment_id: i64) -> Result<bool, Error> {
+        match sqlx::query!(
+            "DELETE FROM comments
+            WHERE id = ?1 AND blog_id = ?2",
+            comment_id,
+            blog_id,
+        )
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn blog_tags(&self, blog_id: i64) -> Result<Vec<String>, Error> {
+        match sqlx::query_scalar!("SELECT tag FROM blog_tags WHERE blog_id = ?1 ORDER BY tag", blog_id)
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(tags) => Ok(tags),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn bulk_comments(
+        &self,
+        action: CommentAction,
+        selection: Selection<CommentFilter>,
+    ) -> Result<BulkReport, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let ids = match selection {
+                    Selection::ids(ids) => ids,
+                    Selection::filter(filter) => {
+                        let moderated = filter.moderation.is_some();
+                        let approved = filter.moderation.and_then(Moderation::as_approved);
+                        let contains = filter.contains_pattern();
+                        sqlx::query_scalar!(
+                            r#"SELECT id AS "id!" FROM comments
+                            WHERE (?1 IS NULL OR blog_id = ?1)
+                            AND (?2 IS NULL OR author = ?2)
+                            AND (?3 IS NULL OR text LIKE ?3 ESCAPE '\')
+                            AND (NOT ?4 OR approved IS ?5)
+                            ORDER BY id"#,
+                            filter.blog_id,
+                            filter.author,
+                            contains,
+                            moderated,
+                            approved,
+                        )
+                        .fetch_all(&mut **transaction)
+                        .await
+                        .map_err(Error::db_query_error)?
+                    }
+                };
+
+                let mut report = BulkReport::default();
+                for id in ids {
+                    let (result, done) = match action {
+                        CommentAction::delete => (
+                            sqlx::query_scalar!(
+                                "DELETE FROM comments WHERE id = ?1 RETURNING blog_id",
+                                id
+                            )
+                            .fetch_optional(&mut **transaction)
+                            .await,
+                            BulkStatus::deleted,
+                        ),
+                        CommentAction::approve => (
+                            sqlx::query_scalar!(
+                                "UPDATE comments SET approved = TRUE WHERE id = ?1 RETURNING blog_id",
+                                id
+                            )
+                            .fetch_optional(&mut **transaction)
+                            .await,
+                            BulkStatus::approved,
+                        ),
+                        CommentAction::reject => (
+                            sqlx::query_scalar!(
+                                "UPDATE comments SET approved = FALSE WHERE id = ?1 RETURNING blog_id",
+                                id
+                            )
+                            .fetch_optional(&mut **transaction)
+                            .await,
+                            BulkStatus::rejected,
+                        ),
+                    };
+                    match result {
+                        Ok(Some(blog_id)) => report.push_comment(id, Some(blog_id), done),
+                        Ok(None) => report.push(id, BulkStatus::not_found),
+                        Err(e) => return Err(Error::db_query_error(e)),
+                    }
+                }
+                Ok(report)
+            })
+        })
+        .await
+    }
+
+    async fn bulk_blogs(
+        &self,
+        operation: BlogOperation,
+        selection: Selection<BlogFilter>,
+    ) -> Result<BulkReport, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let ids = match selection {
+                    Selection::ids(ids) => ids,
+                    Selection::filter(filter) => sqlx::query_scalar!(
+                        r#"SELECT id AS "id!" FROM blogs
+                        WHERE (?1 IS NULL OR author = ?1)
+                        AND (?2 IS NULL OR id IN (SELECT blog_id FROM blog_tags WHERE tag = ?2))
+                        ORDER BY id"#,
+                        filter.author,
+                        filter.tag,
+                    )
+                    .fetch_all(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?,
+                };
+
+                let mut report = BulkReport::default();
+                for id in ids {
+                    let exists = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM blogs WHERE id = ?1) AS "exists!: bool""#, id)
+                        .fetch_one(&mut **transaction)
+                        .await
+                        .map_err(Error::db_query_error)?;
+                    if !exists {
+                        report.push(id, BulkStatus::not_found);
+                        continue;
+                    }
+
+                    let (result, done) = match &operation {
+                        BlogOperation::tag(tag) => (
+                            sqlx::query!(
+                                "INSERT INTO blog_tags (blog_id, tag) VALUES (?1, ?2)
+                                ON CONFLICT DO NOTHING",
+                                id,
+                                tag,
+                            )
+                            .execute(&mut **transaction)
+                            .await,
+                            BulkStatus::tagged,
+                        ),
+                        BlogOperation::untag(tag) => (
+                            sqlx::query!(
+                                "DELETE FROM blog_tags WHERE blog_id = ?1 AND tag = ?2",
+                                id,
+                                tag,
+                            )
+                            .execute(&mut **transaction)
+                            .await,
+                            BulkStatus::untagged,
+                        ),
+                        BlogOperation::archive => (
+                            sqlx::query!(
+                                "UPDATE blogs SET archived = TRUE, updated_at = CURRENT_TIMESTAMP
+                                WHERE id = ?1 AND NOT archived",
+                                id,
+                            )
+                            .execute(&mut **transaction)
+                            .await,
+                            BulkStatus::archived,
+                        ),
+                        BlogOperation::unarchive => (
+                            sqlx::query!(
+                                "UPDATE blogs SET archived = FALSE, updated_at = CURRENT_TIMESTAMP
+                                WHERE id = ?1 AND archived",
+                                id,
+                            )
+                            .execute(&mut **transaction)
+                            .await,
+                            BulkStatus::unarchived,
+                        ),
+                    };
+                    match result {
+                        Ok(result) if result.rows_affected() > 0 => report.push(id, done),
+                        Ok(_) => report.push(id, BulkStatus::unchanged),
+                        Err(e) => return Err(Error::db_query_error(e)),
+                    }
+                }
+                Ok(report)
+            })
+        })
+        .await
+    }
+
+    async fn import_blogs(&self, blogs: Vec<ImportBlog>) -> Result<BulkReport, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let mut report = BulkReport::default();
+                for blog in blogs {
+                    let created = insert_blog(
+                        transaction,
+                        NewBlog {
+                            image: blog.image,
+                            author: blog.author,
+                            text: blog.text,
+                        },
+                    )
+                    .await?;
+                    for tag in blog.tags.iter().map(|tag| tag.trim()).filter(|tag| !tag.is_empty()) {
+                        sqlx::query!(
+                            "INSERT INTO blog_tags (blog_id, tag) VALUES (?1, ?2)
+                            ON CONFLICT DO NOTHING",
+                            created.id.0,
+                            tag,
+                        )
+                        .execute(&mut **transaction)
+                        .await
+                        .map_err(Error::db_query_error)?;
+                    }
+                    report.push(created.id.0, BulkStatus::imported);
+                }
+                Ok(report)
+            })
+        })
+        .await
+    }
+
+    async fn import_markdown(&self, posts: Vec<MarkdownPost>) -> Result<Vec<MarkdownImported>, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let mut imported = Vec::new();
+                for post in posts {
+                    imported.push(import_markdown_post(transaction, post).await?);
+                }
+                Ok(imported)
+            })
+        })
+        .await
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<User, Error> {
+        match sqlx::query_as!(
+            User,
+            r#"INSERT INTO users (username, password_hash, role) VALUES (?1, ?2, ?3)
+            ON CONFLICT (username) DO NOTHING
+            RETURNING id AS "id!", username, role, created_at AS "created_at!", updated_at AS "updated_at!""#,
+            user.username,
+            user.password_hash,
+            user.role,
+        )
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(created)) => Ok(created),
+            Ok(None) => Err(Error::conflict(format!("the username {:?} is taken", user.username))),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn set_password(&self, username: &str, password_hash: String) -> Result<bool, Error> {
+        match sqlx::query!(
+            "UPDATE users SET password_hash = ?2, updated_at = CURRENT_TIMESTAMP WHERE username = ?1",
+            username,
+            password_hash,
+        )
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn set_role(&self, username: &str, role: String) -> Result<bool, Error> {
+        match sqlx::query!(
+            "UPDATE users SET role = ?2, updated_at = CURRENT_TIMESTAMP WHERE username = ?1",
+            username,
+            role,
+        )
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>, Error> {
+        match sqlx::query_as!(
+            Credentials,
+            "SELECT role, password_hash FROM users WHERE username = ?1",
+            username,
+        )
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(res) => Ok(res),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    async fn published_posts(&self) -> Result<Vec<PublishedPost>, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let mut tags = HashMap::<i64, Vec<String>>::new();
+                for row in sqlx::query!(
+                    r#"SELECT blog_id, tag FROM blog_tags JOIN blogs ON blogs.id = blog_id
+                    WHERE NOT blogs.archived ORDER BY blog_id, tag"#
+                )
+                .fetch_all(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?
+                {
+                    tags.entry(row.blog_id).or_default().push(row.tag);
+                }
+
+                let posts = sqlx::query!(
+                    r#"SELECT blogs.id AS "id!", image, author, date, updated_at AS "updated_at!", COALESCE(texts.text, '') AS "text!: String"
+                    FROM blogs LEFT JOIN texts ON texts.blog_id = blogs.id
+                    WHERE NOT archived ORDER BY blogs.id"#
+                )
+                .fetch_all(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?
+                .into_iter()
+                .map(|row| PublishedPost {
+                    id: row.id,
+                    image: row.image,
+                    author: row.author,
+                    date: row.date,
+                    updated_at: row.updated_at,
+                    text: row.text,
+                    tags: tags.remove(&row.id).unwrap_or_default(),
+                })
+                .collect();
+                Ok(posts)
+            })
+        })
+        .await
+    }
+
+    async fn backup(&self) -> Result<Backup, Error> {
+        // a sqlite transaction already reads one snapshot
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let mut tags = HashMap::<i64, Vec<String>>::new();
+                for row in sqlx::query!(r#"SELECT blog_id AS "blog_id!", tag FROM blog_tags ORDER BY blog_id, tag"#)
+                    .fetch_all(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?
+                {
+                    tags.entry(row.blog_id).or_default().push(row.tag);
+                }
+
+                let blogs = sqlx::query!(
+                    r#"SELECT id AS "id!", image, author, date, COALESCE(updated_at, date) AS "updated_at!: NaiveDateTime",
+                    likes, bookmarks AS "bookmarks: i32", archived
+                    FROM blogs ORDER BY id"#
+                )
+                .fetch_all(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?
+                .into_iter()
+                .map(|row| BackupBlog {
+                    id: row.id,
+                    image: row.image,
+                    author: row.author,
+                    date: row.date,
+                    updated_at: row.updated_at,
+                    likes: row.likes,
+                    bookmarks: row.bookmarks,
+                    archived: row.archived,
+                    tags: tags.remove(&row.id).unwrap_or_default(),
+                })
+                .collect();
+
+                let texts = sqlx::query_as!(Text, r#"SELECT blog_id AS "blog_id!", text FROM texts ORDER BY blog_id"#)
+                    .fetch_all(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+
+                let comments = sqlx::query_as!(
+                    Comment,
+                    r#"SELECT id AS "id!", blog_id, author, text, likes AS "likes: i32", date, approved FROM comments
+                    ORDER BY id"#
+                )
+                .fetch_all(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?;
+
+                let users = sqlx::query_as!(
+                    BackupUser,
+                    r#"SELECT id AS "id!", username, password_hash, role, created_at AS "created_at!",
+                    updated_at AS "updated_at!" FROM users
+                    ORDER BY id"#
+                )
+                .fetch_all(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?;
+
+                let sources = sqlx::query_as!(
+                    BackupSource,
+                    r#"SELECT source AS "source!", slug, blog_id, checksum FROM markdown_sources ORDER BY source"#
+                )
+                .fetch_all(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?;
+
+                Ok(Backup {
+                    blogs,
+                    texts,
+                    comments,
+                    users,
+                    sources,
+                })
+            })
+        })
+        .await
+    }
+
+    async fn restore(&self, backup: Backup) -> Result<RestoreReport, Error> {
+        unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let existing = sqlx::query_scalar!(
+                    r#"SELECT (SELECT COUNT(*) FROM blogs) + (SELECT COUNT(*) FROM users) AS "count!: i64""#
+                )
+                .fetch_one(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?;
+                if existing > 0 {
+                    return Err(Error::conflict(
+                        "The database isn't empty, a backup only restores into an empty one"
+                            .to_string(),
+                    ));
+                }
+
+                let mut report = RestoreReport::default();
+                for blog in backup.blogs {
+                    let id = sqlx::query_scalar!(
+                        r#"INSERT INTO blogs (image, author, date, updated_at, likes, bookmarks, archived)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                        RETURNING id AS "id!""#,
+                        blog.image,
+                        blog.author,
+                        blog.date,
+                        blog.updated_at,
+                        blog.likes,
+                        blog.bookmarks,
+                        blog.archived,
+                    )
+                    .fetch_one(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+                    for tag in &blog.tags {
+                        sqlx::query!(
+                            "INSERT INTO blog_tags (blog_id, tag) VALUES (?1, ?2)",
+                            id,
+                            tag,
+                        )
+                        .execute(&mut **transaction)
+                        .await
+                        .map_err(Error::db_query_error)?;
+                        report.tags += 1;
+                    }
+                    report.blog_ids.insert(blog.id, id);
+                    report.blogs += 1;
+                }
+
+                for text in backup.texts {
+                    let blog_id = remapped(&report, text.blog_id)?;
+                    sqlx::query!(
+                        "INSERT INTO texts (blog_id, text) VALUES (?1, ?2)",
+                        blog_id,
+                        text.text,
+                    )
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+                    report.texts += 1;
+                }
+
+                for comment in backup.comments {
+                    let blog_id = remapped(&report, comment.blog_id)?;
+                    sqlx::query!(
+                        "INSERT INTO comments (blog_id, author, text, likes, date, approved)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        blog_id,
+                        comment.author,
+                        comment.text,
+                        comment.likes,
+                        comment.date,
+                        comment.approved,
+                    )
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+                    report.comments += 1;
+                }
+
+                // nothing refers to a user by id, they just get new ones
+                for user in backup.users {
+                    sqlx::query!(
+                        "INSERT INTO users (username, password_hash, role, created_at, updated_at)
+                        VALUES (?1, ?2, ?3, ?4, ?5)",
+                        user.username,
+                        user.password_hash,
+                        user.role,
+                        user.created_at,
+                        user.updated_at,
+                    )
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+                    report.users += 1;
+                }
+
+                for source in backup.sources {
+                    let blog_id = remapped(&report, source.blog_id)?;
+                    sqlx::query!(
+                        "INSERT INTO markdown_sources (source, slug, blog_id, checksum)
+                        VALUES (?1, ?2, ?3, ?4)",
+                        source.source,
+                        source.slug,
+                        blog_id,
+                        source.checksum,
+                    )
+                    .execute(&mut **transaction)
+                    .await
+                    .map_err(Error::db_query_error)?;
+                    report.sources += 1;
+                }
+                Ok(report)
+            })
+        })
+        .await
+    }
+}
+
+// sqlite has no FOR UPDATE, a write takes the database lock before we read what If-Match is checked against
+async fn lock_for_write(transaction: &mut Transaction<'static, Sqlite>, blog_id: i64) -> Result<(), Error> {
+    sqlx::query!("UPDATE blogs SET likes = likes WHERE id = ?1", blog_id)
+        .execute(&mut **transaction)
+        .await
+        .map_err(Error::db_query_error)?;
+    Ok(())
+}
+
+async fn locked_blog(
+    transaction: &mut Transaction<'static, Sqlite>,
+    blog_id: i64,
+) -> Result<Option<Blog>, Error> {
+    lock_for_write(transaction, blog_id).await?;
+    sqlx::query_as!(
+        Blog,
+        r#"SELECT id AS "id!: BlogID", image, author, date, updated_at AS "updated_at!", likes, bookmarks AS "bookmarks: i32"
+            FROM blogs WHERE id = ?1"#,
+        blog_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(Error::db_query_error)
+}
+
+async fn locked_text(
+    transaction: &mut Transaction<'static, Sqlite>,
+    blog_id: i64,
+) -> Result<Option<Text>, Error> {
+    lock_for_write(transaction, blog_id).await?;
+    sqlx::query_as!(
+        Text,
+        "SELECT blog_id, text FROM texts
+            WHERE blog_id = ?1",
+        blog_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(Error::db_query_error)
+}
+
+// shared by post_blog and the bulk import so both create a blog the same way
+async fn insert_blog(
+    transaction: &mut Transaction<'static, Sqlite>,
+    blog: NewBlog,
+) -> Result<Blog, Error> {
+    let created = sqlx::query_as!(
+        Blog,
+        r#"INSERT INTO blogs (image, author, likes, bookmarks, updated_at)
+        VALUES (?1, ?2, 0, 0, CURRENT_TIMESTAMP)
+        RETURNING id AS "id!: BlogID", image, author, date, updated_at AS "updated_at!", likes, bookmarks AS "bookmarks: i32""#,
+        blog.image,
+        blog.author,
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(Error::db_query_error)?;
+
+    if !blog.text.is_empty() {
+        sqlx::query!(
+            "INSERT INTO texts (blog_id, text) VALUES (?1, ?2)",
+            created.id.0,
+            blog.text,
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(Error::db_query_error)?;
+    }
+
+    Ok(created)
+}
+
+// a renamed file keeps its post through the slug, a changed slug keeps it through the path
+async fn import_markdown_post(
+    transaction: &mut Transaction<'static, Sqlite>,
+    post: MarkdownPost,
+) -> Result<MarkdownImported, Error> {
+    let checksum = post.checksum();
+    let known = sqlx::query!(
+        r#"SELECT source AS "source!", blog_id, checksum FROM markdown_sources
+        WHERE source = ?1 OR slug = ?2
+        ORDER BY source = ?1 DESC"#,
+        post.source,
+        post.slug,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(Error::db_query_error)?;
+    if let [_, other] = known.as_slice() {
+        return Err(Error::conflict(format!(
+            "{} and {} both have the slug \"{}\"",
+            post.source, other.source, post.slug
+        )));
+    }
+
+    let (blog_id, status) = match known.into_iter().next() {
+        Some(known) if known.source == post.source && known.checksum == checksum => {
+            return Ok(MarkdownImported {
+                source: post.source,
+                blog_id: known.blog_id,
+                status: MarkdownStatus::unchanged,
+            });
+        }
+        Some(known) => {
+            sqlx::query!(
+                "UPDATE blogs SET image = ?2, author = ?3, date = COALESCE(?4, date), archived = ?5,
+                updated_at = CURRENT_TIMESTAMP
+                WHERE id = ?1",
+                known.blog_id,
+                post.image,
+                post.author,
+                post.date,
+                post.archived,
+            )
+            .execute(&mut **transaction)
+            .await
+            .map_err(Error::db_query_error)?;
+            (known.blog_id, MarkdownStatus::updated)
+        }
+        None => {
+            let blog_id = sqlx::query_scalar!(
+                r#"INSERT INTO blogs (image, author, date, updated_at, archived)
+                VALUES (?1, ?2, COALESCE(?3, CURRENT_TIMESTAMP), CURRENT_TIMESTAMP, ?4)
+                RETURNING id AS "id!""#,
+                post.image,
+                post.author,
+                post.date,
+                post.archived,
+            )
+            .fetch_one(&mut **transaction)
+            .await
+            .map_err(Error::db_query_error)?;
+            (blog_id, MarkdownStatus::created)
+        }
+    };
+
+    sqlx::query!(
+        "INSERT INTO texts (blog_id, text) VALUES (?1, ?2)
+        ON CONFLICT (blog_id) DO UPDATE SET text = EXCLUDED.text",
+        blog_id,
+        post.text,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(Error::db_query_error)?;
+
+    sqlx::query!("DELETE FROM blog_tags WHERE blog_id = ?1", blog_id)
+        .execute(&mut **transaction)
+        .await
+        .map_err(Error::db_query_error)?;
+    for tag in &post.tags {
+        sqlx::query!(
+            "INSERT INTO blog_tags (blog_id, tag) VALUES (?1, ?2)
+            ON CONFLICT DO NOTHING",
+            blog_id,
+            tag,
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(Error::db_query_error)?;
+    }
+
+    sqlx::query!(
+        "INSERT INTO markdown_sources (source, slug, blog_id, checksum) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (blog_id) DO UPDATE
+        SET source = EXCLUDED.source, slug = EXCLUDED.slug, checksum = EXCLUDED.checksum",
+        post.source,
+        post.slug,
+        blog_id,
+        checksum,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(Error::db_query_error)?;
+
+    Ok(MarkdownImported {
+        source: post.source,
+        blog_id,
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every sqlite::memory: url gets its own database, shared by the connections of one pool
+    async fn store() -> SqliteStore {
+        let store = SqliteStore::connect("sqlite::memory:").await.unwrap();
+        store.migrate().await.unwrap();
+        store
+    }
+
+    async fn count(store: &SqliteStore, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+            .fetch_one(&store.connection)
+            .await
+            .unwrap()
+    }
+
+    fn new_blog(text: &str) -> NewBlog {
+        NewBlog {
+            image: None,
+            author: "ada".to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn migrations_revert_and_apply_again() {
+        use crate::utils::migration::MigrationState::{applied, pending};
+
+        let store = store().await;
+        let states = |status: Vec<MigrationStatus>| {
+            status
+                .into_iter()
+                .map(|migration| (migration.version, migration.state))
+                .collect::<Vec<_>>()
+        };
+
+        store.revert(3).await.unwrap();
+        assert_eq!(
+            states(store.migration_status().await.unwrap()),
+            [(1, applied), (3, applied), (4, pending), (7, pending), (8, pending), (9, pending)]
+        );
+        assert!(sqlx::query("SELECT * FROM users").fetch_all(&store.connection).await.is_err());
+
+        store.migrate().await.unwrap();
+        assert_eq!(count(&store, "users").await, 0);
+        assert!(states(store.migration_status().await.unwrap())
+            .iter()
+            .all(|(_, state)| *state == applied));
+    }
+
+    #[tokio::test]
+    async fn failed_text_insert_rolls_back_the_blog() {
+        let store = store().await;
+        sqlx::query("DROP TABLE texts").execute(&store.connection).await.unwrap();
+
+        assert!(store.post_blog(new_blog("hello")).await.is_err());
+        assert_eq!(count(&store, "blogs").await, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_import_rolls_back_earlier_blogs_and_tags() {
+        let store = store().await;
+        sqlx::query("DROP TABLE texts").execute(&store.connection).await.unwrap();
+
+        // the first blog has no text so it gets as far as its tags, the second one fails
+        let blogs = vec![
+            ImportBlog {
+                image: None,
+                author: "ada".to_string(),
+                text: String::new(),
+                tags: vec!["rust".to_string(), "sql".to_string()],
+            },
+            ImportBlog {
+                image: None,
+                author: "grace".to_string(),
+                text: "hello".to_string(),
+                tags: vec!["cobol".to_string()],
+            },
+        ];
+        assert!(store.import_blogs(blogs).await.is_err());
+        assert_eq!(count(&store, "blogs").await, 0);
+        assert_eq!(count(&store, "blog_tags").await, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_delete_keeps_the_text_and_comments() {
+        let store = store().await;
+        let blog = store.post_blog(new_blog("hello")).await.unwrap();
+        store
+            .post_blog_comments(
+                NewComment {
+                    blog_id: blog.id.0,
+                    author: "grace".to_string(),
+                    text: "nice".to_string(),
+                    likes: 0,
+                    date: String::new(),
+                },
+                blog.id.0,
+            )
+            .await
+            .unwrap();
+        // the blog row is deleted last, after the comments and the text are already gone
+        sqlx::query(
+            "CREATE TRIGGER keep_blogs BEFORE DELETE ON blogs
+            BEGIN SELECT RAISE(ABORT, 'injected'); END",
+        )
+        .execute(&store.connection)
+        .await
+        .unwrap();
+
+        assert!(store.delete_blog(blog.id.0).await.is_err());
+        assert_eq!(count(&store, "blogs").await, 1);
+        assert_eq!(count(&store, "texts").await, 1);
+        assert_eq!(count(&store, "comments").await, 1);
+    }
+
+    #[tokio::test]
+    async fn blogs_texts_and_comments_round_trip() {
+        let store = store().await;
+        let blog = store.post_blog(new_blog("hello")).await.unwrap();
+        assert_eq!(store.get_single_blog(blog.id.0).await.unwrap().author, "ada");
+        assert_eq!(store.blog_text(blog.id.0).await.unwrap().text, "hello");
+
+        let comment = store
+            .post_blog_comments(
+                NewComment {
+                    blog_id: blog.id.0,
+                    author: "grace".to_string(),
+                    text: "nice".to_string(),
+                    likes: 0,
+                    date: String::new(),
+                },
+                blog.id.0,
+            )
+            .await
+            .unwrap();
+        // only approved comments are listed
+        assert!(store.get_blog_comments(blog.id.0).await.unwrap().is_empty());
+        store
+            .bulk_comments(CommentAction::approve, Selection::ids(vec![comment.id]))
+            .await
+            .unwrap();
+        let comments = store.get_blog_comments(blog.id.0).await.unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].id, comment.id);
+
+        assert!(store.delete_blog_comment(blog.id.0, comment.id).await.unwrap());
+        assert!(store.get_blog_comments(blog.id.0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn updates_land_in_their_own_columns() {
+        let store = store().await;
+        let blog = store.post_blog(new_blog("hello")).await.unwrap();
+        let changed = Blog {
+            image: Some("cover.png".to_string()),
+            author: "grace".to_string(),
+            likes: 3,
+            ..blog.clone()
+        };
+        let updated = store.put_blog(changed, blog.id.0, None).await.unwrap();
+        assert_eq!(updated.image.as_deref(), Some("cover.png"));
+        assert_eq!(updated.author, "grace");
+        assert_eq!(updated.likes, 3);
+
+        let text = Text {
+            blog_id: blog.id.0,
+            text: "bye".to_string(),
+        };
+        assert_eq!(store.put_blog_text(text, blog.id.0, None).await.unwrap().text, "bye");
+        assert_eq!(store.blog_text(blog.id.0).await.unwrap().text, "bye");
+    }
+
+    #[tokio::test]
+    async fn a_patch_only_touches_the_columns_it_names() {
+        let store = store().await;
+        let blog = store
+            .post_blog(NewBlog {
+                image: Some("cover.png".to_string()),
+                ..new_blog("hello")
+            })
+            .await
+            .unwrap();
+        let changes = BlogChanges {
+            set_image: true,
+            image: None,
+            likes: Some(5),
+            ..Default::default()
+        };
+        let patched = store.patch_blog(changes, blog.id.0, None).await.unwrap();
+        assert_eq!(patched.image, None);
+        assert_eq!(patched.author, "ada");
+        assert_eq!(patched.likes, 5);
+        assert_eq!(patched.date, blog.date);
+        assert!(patched.updated_at >= blog.updated_at);
+    }
+
+    #[tokio::test]
+    async fn importing_a_directory_again_updates_in_place() {
+        let store = store().await;
+        let post = |source: &str, text: &str| MarkdownPost {
+            source: source.to_string(),
+            slug: source.trim_end_matches(".md").to_string(),
+            image: None,
+            author: "ada".to_string(),
+            date: None,
+            archived: false,
+            tags: vec!["rust".to_string()],
+            text: text.to_string(),
+        };
+        let statuses = |imported: Vec<MarkdownImported>| {
+            imported
+                .into_iter()
+                .map(|post| post.status)
+                .collect::<Vec<_>>()
+        };
+
+        let first = store
+            .import_markdown(vec![post("a.md", "# A\n"), post("b.md", "# B\n")])
+            .await
+            .unwrap();
+        assert_eq!(statuses(first.clone()), [MarkdownStatus::created, MarkdownStatus::created]);
+
+        let again = store
+            .import_markdown(vec![post("a.md", "# A\n"), post("b.md", "# B, edited\n")])
+            .await
+            .unwrap();
+        assert_eq!(statuses(again.clone()), [MarkdownStatus::unchanged, MarkdownStatus::updated]);
+        assert_eq!(again[1].blog_id, first[1].blog_id);
+        assert_eq!(store.blog_text(first[1].blog_id).await.unwrap().text, "# B, edited\n");
+        assert_eq!(count(&store, "blogs").await, 2);
+        assert_eq!(count(&store, "blog_tags").await, 2);
+    }
+
+    #[tokio::test]
+    async fn new_comments_wait_for_moderation_and_purging_keeps_them() {
+        let store = store().await;
+        let blog = store.post_blog(new_blog("hello")).await.unwrap();
+        let mut ids = Vec::new();
+        for author in ["ada", "grace", "linus"] {
+            let comment = store
+                .post_blog_comments(
+                    NewComment {
+                        blog_id: blog.id.0,
+                        author: author.to_string(),
+                        text: "nice".to_string(),
+                        likes: 0,
+                        date: String::new(),
+                    },
+                    blog.id.0,
+                )
+                .await
+                .unwrap();
+            assert_eq!(comment.approved, None);
+            ids.push(comment.id);
+        }
+        store
+            .bulk_comments(CommentAction::approve, Selection::ids(vec![ids[0]]))
+            .await
+            .unwrap();
+        store
+            .bulk_comments(CommentAction::reject, Selection::ids(vec![ids[1]]))
+            .await
+            .unwrap();
+
+        // what comments purge-spam runs
+        let filter = CommentFilter {
+            moderation: Some(Moderation::rejected),
+            ..Default::default()
+        };
+        let report = store
+            .bulk_comments(CommentAction::delete, Selection::filter(filter))
+            .await
+            .unwrap();
+        assert_eq!(report.succeeded, 1);
+
+        // only the approved comment is public, the pending one is still waiting
+        let left = store.get_blog_comments(blog.id.0).await.unwrap();
+        let left = left.iter().map(|comment| (comment.id, comment.approved)).collect::<Vec<_>>();
+        assert_eq!(left, vec![(ids[0], Some(true))]);
+        let filter = CommentFilter {
+            moderation: Some(Moderation::pending),
+            ..Default::default()
+        };
+        let report = store
+            .bulk_comments(CommentAction::approve, Selection::filter(filter))
+            .await
+            .unwrap();
+        assert_eq!(report.results.iter().map(|item| item.id).collect::<Vec<_>>(), vec![ids[2]]);
+    }
+}
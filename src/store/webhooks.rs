@@ -0,0 +1,366 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+
+use crate::{
+    error::Error,
+    store::{
+        jobs::{insert_job, retry_delay, Job, JobContext, Jobs, LEASE_SECS},
+        unit_of_work,
+    },
+    types::{
+        event::BlogEvent,
+        webhook::{is_internal, Delivery, DeliveryQuery, NewWebhook, Webhook},
+    },
+};
+
+// every delivery is a job of its own, a slow receiver can't outlast the lease and get the delivery sent twice
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const _: () = assert!(REQUEST_TIMEOUT.as_secs() < LEASE_SECS as u64);
+
+// subscriptions and the delivery log live here, the deliveries themselves go out as jobs
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    connection: PgPool,
+    jobs: Jobs,
+}
+
+struct Due {
+    id: i64,
+    event: String,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+impl Webhooks {
+    pub async fn connect(db_url: &str, jobs: Jobs) -> Result<Self, sqlx::Error> {
+        let connection = PgPoolOptions::new()
+            .max_connections(4)
+            .acquire_timeout(Duration::from_secs(3))
+            .connect(db_url)
+            .await?;
+        Ok(Webhooks { connection, jobs })
+    }
+
+    pub async fn create(&self, webhook: NewWebhook) -> Result<Webhook, Error> {
+        check_destination(&webhook.url)
+            .await
+            .map_err(Error::invalid_request)?;
+        match sqlx::query_as!(
+            Webhook,
+            "INSERT INTO webhooks (url, events, secret) VALUES ($1, $2, $3)
+            RETURNING id, url, events, created_at",
+            webhook.url,
+            &webhook.events,
+            webhook.secret,
+        )
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(res) => Ok(res),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<Webhook>, Error> {
+        match sqlx::query_as!(
+            Webhook,
+            "SELECT id, url, events, created_at FROM webhooks ORDER BY id"
+        )
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(res) => Ok(res),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    // its deliveries go with it
+    pub async fn delete(&self, webhook_id: i64) -> Result<bool, Error> {
+        match sqlx::query!("DELETE FROM webhooks WHERE id = $1", webhook_id)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    pub async fn deliveries(
+        &self,
+        webhook_id: i64,
+        query: DeliveryQuery,
+    ) -> Result<Vec<Delivery>, Error> {
+        match sqlx::query_as!(
+            Delivery,
+            "SELECT id, webhook_id, event, payload, status, attempts, next_attempt_at,
+            last_status_code, last_error, created_at, delivered_at
+            FROM webhook_deliveries
+            WHERE webhook_id = $1 AND ($2::TEXT IS NULL OR status = $2)
+            ORDER BY id DESC LIMIT $3",
+            webhook_id,
+            query.status,
+            query.limit.unwrap_or(50).clamp(1, 500),
+        )
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(res) => Ok(res),
+            Err(e) => Err(Error::db_query_error(e)),
+        }
+    }
+
+    // logs one delivery per subscribed webhook and queues a job for each, all or nothing
+    pub async fn enqueue(&self, event: &BlogEvent) {
+        let Some(name) = event.webhook_event() else {
+            return;
+        };
+        let payload = json!({ "event": name, "data": event }).to_string();
+        let queued = unit_of_work(&self.connection, |transaction| {
+            Box::pin(async move {
+                let delivery_ids = sqlx::query_scalar!(
+                    "INSERT INTO webhook_deliveries (webhook_id, event, payload)
+                    SELECT id, $1, $2 FROM webhooks WHERE $1 = ANY(events)
+                    RETURNING id",
+                    name,
+                    payload,
+                )
+                .fetch_all(&mut **transaction)
+                .await
+                .map_err(Error::db_query_error)?;
+                for &delivery_id in &delivery_ids {
+                    insert_job(&mut **transaction, &DeliverWebhook { delivery_id }, None).await?;
+                }
+                Ok(delivery_ids.len())
+            })
+        })
+        .await;
+        match queued {
+            Ok(0) => {}
+            Ok(_) => self.jobs.wake(),
+            Err(e) => tracing::error!("Couldn't queue the {} webhooks: {}", name, e),
+        }
+    }
+
+    pub async fn close(&self) {
+        self.connection.close().await;
+    }
+}
+
+// one attempt per run, the job queue takes care of the retries and the backoff.
+// the job's last attempt marks the delivery failed, retrying the dead job starts a new round
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeliverWebhook {
+    pub delivery_id: i64,
+}
+
+#[async_trait]
+impl Job for DeliverWebhook {
+    const KIND: &'static str = "deliver_webhook";
+    const MAX_ATTEMPTS: i32 = 8;
+
+    async fn run(self, context: &JobContext) -> Result<(), String> {
+        // gone with its webhook, or already delivered by an earlier run
+        let due = sqlx::query_as!(
+            Due,
+            "SELECT webhook_deliveries.id, event, payload, attempts, webhooks.url, webhooks.secret
+            FROM webhook_deliveries JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+            WHERE webhook_deliveries.id = $1 AND status <> 'delivered'",
+            self.delivery_id,
+        )
+        .fetch_optional(&context.connection)
+        .await
+        .map_err(|e| e.to_string())?;
+        let Some(delivery) = due else {
+            return Ok(());
+        };
+        deliver(context, delivery).await
+    }
+}
+
+async fn deliver(context: &JobContext, delivery: Due) -> Result<(), String> {
+    // the name may have been pointed somewhere internal since the webhook was created
+    let sent = match check_destination(&delivery.url).await {
+        Ok(()) => send(&context.client, &delivery).await,
+        Err(e) => Err((None, e)),
+    };
+    let (status_code, error) = match sent {
+        Ok(status_code) => {
+            sqlx::query!(
+                "UPDATE webhook_deliveries
+                SET status = 'delivered', attempts = attempts + 1, last_status_code = $2,
+                last_error = NULL, delivered_at = NOW()
+                WHERE id = $1",
+                delivery.id,
+                status_code,
+            )
+            .execute(&context.connection)
+            .await
+            .map_err(|e| e.to_string())?;
+            return Ok(());
+        }
+        Err(failed) => failed,
+    };
+
+    let attempts = delivery.attempts + 1;
+    let status = if context.attempts >= context.max_attempts { "failed" } else { "pending" };
+    sqlx::query!(
+        "UPDATE webhook_deliveries
+        SET status = $2, attempts = $3, last_status_code = $4, last_error = $5,
+        next_attempt_at = NOW() + $6 * INTERVAL '1 second'
+        WHERE id = $1",
+        delivery.id,
+        status,
+        attempts,
+        status_code,
+        error,
+        retry_delay(context.attempts) as f64,
+    )
+    .execute(&context.connection)
+    .await
+    .map_err(|e| e.to_string())?;
+    Err(error)
+}
+
+// every address the url's host resolves to has to be public, see types::webhook::is_internal
+async fn check_destination(url: &str) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| format!("{url:?} isn't a valid url: {e}"))?;
+    let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("{host} can't be resolved: {e}"))?;
+    for address in addresses {
+        if is_internal(address.ip()) {
+            return Err(format!("{host} resolves to {}, which isn't public", address.ip()));
+        }
+    }
+    Ok(())
+}
+
+// the status code of a 2xx answer, otherwise the status code (if there was one) and what went wrong
+async fn send(client: &reqwest::Client, delivery: &Due) -> Result<i32, (Option<i32>, String)> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let response = client
+        .post(&delivery.url)
+        .timeout(REQUEST_TIMEOUT)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", delivery.id.to_string())
+        .header("X-Webhook-Event", &delivery.event)
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "X-Webhook-Signature",
+            signature(&delivery.secret, timestamp, &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => Ok(response.status().as_u16() as i32),
+        Ok(response) => Err((
+            Some(response.status().as_u16() as i32),
+            format!("The receiver answered with {}", response.status()),
+        )),
+        Err(e) => Err((None, e.to_string())),
+    }
+}
+
+// receivers recompute this over "{timestamp}.{body}" and compare, the timestamp guards against replays
+fn signature(secret: &str, timestamp: u64, payload: &str) -> String {
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        // hmac takes keys of any length, this can't happen
+        Err(_) => return String::new(),
+    };
+    mac.update(format!("{timestamp}.{payload}").as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    // answers /ok with 200 and /broken with 500, and keeps what it was sent
+    async fn receiver() -> (String, Received) {
+        let received = Received::default();
+        let keep = |received: Received, status: StatusCode| {
+            move |headers: HeaderMap, body: String| async move {
+                received.lock().unwrap().push((headers, body));
+                status
+            }
+        };
+        let app = Router::new()
+            .route("/ok", post(keep(received.clone(), StatusCode::OK)))
+            .route("/broken", post(keep(received.clone(), StatusCode::INTERNAL_SERVER_ERROR)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{address}"), received)
+    }
+
+    fn due(url: String) -> Due {
+        Due {
+            id: 7,
+            event: "blog.published".to_string(),
+            payload: r#"{"event":"blog.published","data":{}}"#.to_string(),
+            attempts: 0,
+            url,
+            secret: "shh".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_receiver_can_check_the_signature() {
+        let (base, received) = receiver().await;
+        let delivery = due(format!("{base}/ok"));
+        assert_eq!(send(&reqwest::Client::new(), &delivery).await, Ok(200));
+
+        let received = received.lock().unwrap();
+        let (headers, body) = &received[0];
+        assert_eq!(body, &delivery.payload);
+        assert_eq!(headers["X-Webhook-Id"], "7");
+        assert_eq!(headers["X-Webhook-Event"], "blog.published");
+        let timestamp: u64 = headers["X-Webhook-Timestamp"].to_str().unwrap().parse().unwrap();
+        assert_eq!(
+            headers["X-Webhook-Signature"].to_str().unwrap(),
+            signature("shh", timestamp, body)
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_and_unreachable_receivers_fail_the_attempt() {
+        let (base, _) = receiver().await;
+        let client = reqwest::Client::new();
+        let broken = send(&client, &due(format!("{base}/broken"))).await;
+        assert!(matches!(broken, Err((Some(500), _))));
+
+        // bound and dropped again, so nothing listens there
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let unreachable = send(&client, &due(format!("http://{address}/ok"))).await;
+        assert!(matches!(unreachable, Err((None, _))));
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{blog::Text, comment::Comment};
+
+// one line of blogs.jsonl, the ids are the source database's and get new ones on restore
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BackupBlog {
+    pub id: i64,
+    pub image: Option<String>,
+    pub author: String,
+    pub date: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub likes: i64,
+    pub bookmarks: i32,
+    pub archived: bool,
+    pub tags: Vec<String>,
+}
+
+// one line of users.jsonl. the password hash goes along, a restored user signs in like before
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BackupUser {
+    pub id: i64,
+    pub username: String,
+    pub password_hash: String,
+    pub role: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+// one line of markdown_sources.jsonl, so importing the same directory after a restore still updates
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BackupSource {
+    pub source: String,
+    pub slug: String,
+    pub blog_id: i64,
+    pub checksum: String,
+}
+
+// everything a backup holds besides the media files, archived posts and comments in every moderation state included
+#[derive(Debug, Clone, Default)]
+pub struct Backup {
+    pub blogs: Vec<BackupBlog>,
+    pub texts: Vec<Text>,
+    pub comments: Vec<Comment>,
+    pub users: Vec<BackupUser>,
+    pub sources: Vec<BackupSource>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestoreReport {
+    pub blogs: usize,
+    pub texts: usize,
+    pub comments: usize,
+    pub tags: usize,
+    pub users: usize,
+    pub sources: usize,
+    // old id -> new id
+    pub blog_ids: BTreeMap<i64, i64>,
+}
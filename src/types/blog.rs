@@ -1,9 +1,11 @@
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
+use super::patch::Patch;
 use crate::error::Error;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(transparent)]
 pub struct BlogID(pub i64);
 
 #[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
@@ -12,9 +14,70 @@ pub struct Blog {
     pub image: Option<String>,
     pub author: String,
     pub date: NaiveDateTime,
+    // set by the server, clients don't have to send it back on PUT
+    #[serde(default)]
+    pub updated_at: NaiveDateTime,
     pub likes: i64,
     pub bookmarks: i32,
 }
+
+// a post readers can see, with what a static export needs and nothing else, see commands::export
+#[derive(Debug, Clone)]
+pub struct PublishedPost {
+    pub id: i64,
+    pub image: Option<String>,
+    pub author: String,
+    pub date: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+// PATCH /blogs/{id}, id and the dates belong to the server so they can't be patched
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlogPatch {
+    #[serde(default)]
+    pub image: Patch<String>,
+    #[serde(default)]
+    pub author: Patch<String>,
+    #[serde(default)]
+    pub likes: Patch<i64>,
+    #[serde(default)]
+    pub bookmarks: Patch<i32>,
+}
+
+// a checked BlogPatch, None means "leave the column as it is"
+#[derive(Debug, Clone, Default)]
+pub struct BlogChanges {
+    pub set_image: bool,
+    pub image: Option<String>,
+    pub author: Option<String>,
+    pub likes: Option<i64>,
+    pub bookmarks: Option<i32>,
+}
+
+impl BlogPatch {
+    pub fn into_changes(self) -> Result<BlogChanges, Error> {
+        let (set_image, image) = self.image.nullable();
+        Ok(BlogChanges {
+            set_image,
+            image,
+            author: self.author.required("author").map_err(Error::invalid_patch)?,
+            likes: self.likes.required("likes").map_err(Error::invalid_patch)?,
+            bookmarks: self
+                .bookmarks
+                .required("bookmarks")
+                .map_err(Error::invalid_patch)?,
+        })
+    }
+}
+
+impl BlogChanges {
+    pub fn is_empty(&self) -> bool {
+        !self.set_image && self.author.is_none() && self.likes.is_none() && self.bookmarks.is_none()
+    }
+}
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NewBlog {
     pub image: Option<String>,
@@ -22,12 +85,20 @@ pub struct NewBlog {
     pub text: String,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
 pub struct Text {
     pub blog_id: i64,
     pub text: String,
 }
 
+// PATCH /blogs/{id}/text
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextPatch {
+    #[serde(default)]
+    pub text: Patch<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Pagination {
     pub page: Option<i64>,
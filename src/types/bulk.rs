@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+
+use super::comment::Moderation;
+use crate::error::Error;
+
+// bulk endpoints take either explicit ids or a filter: {"ids": [1, 2]} or {"filter": {...}}
+#[derive(Debug, Clone, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum Selection<F> {
+    ids(Vec<i64>),
+    filter(F),
+}
+
+// an empty filter would select every row, we never want that by accident
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommentFilter {
+    pub blog_id: Option<i64>,
+    pub author: Option<String>,
+    pub contains: Option<String>,
+    pub moderation: Option<Moderation>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlogFilter {
+    pub author: Option<String>,
+    pub tag: Option<String>,
+}
+
+pub trait Filter {
+    fn is_empty(&self) -> bool;
+    // the first text field that's set but blank, "" would match everything just like no filter
+    fn blank_field(&self) -> Option<&'static str>;
+}
+
+fn is_blank(value: &Option<String>) -> bool {
+    value.as_ref().is_some_and(|value| value.trim().is_empty())
+}
+
+impl Filter for CommentFilter {
+    fn is_empty(&self) -> bool {
+        self.blog_id.is_none()
+            && self.author.is_none()
+            && self.contains.is_none()
+            && self.moderation.is_none()
+    }
+
+    fn blank_field(&self) -> Option<&'static str> {
+        [("author", &self.author), ("contains", &self.contains)]
+            .into_iter()
+            .find_map(|(name, value)| is_blank(value).then_some(name))
+    }
+}
+
+impl CommentFilter {
+    // the LIKE pattern for "contains", its own % and _ match literally (ESCAPE '\')
+    pub fn contains_pattern(&self) -> Option<String> {
+        self.contains.as_ref().map(|contains| {
+            let mut pattern = String::from("%");
+            for c in contains.chars() {
+                if matches!(c, '%' | '_' | '\\') {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+            }
+            pattern.push('%');
+            pattern
+        })
+    }
+}
+
+impl Filter for BlogFilter {
+    fn is_empty(&self) -> bool {
+        self.author.is_none() && self.tag.is_none()
+    }
+
+    fn blank_field(&self) -> Option<&'static str> {
+        [("author", &self.author), ("tag", &self.tag)]
+            .into_iter()
+            .find_map(|(name, value)| is_blank(value).then_some(name))
+    }
+}
+
+impl<F: Filter> Selection<F> {
+    pub fn validate(self) -> Result<Self, Error> {
+        match &self {
+            Selection::filter(filter) if filter.is_empty() => Err(Error::invalid_request(
+                "The filter needs at least one field".to_string(),
+            )),
+            Selection::filter(filter) => match filter.blank_field() {
+                Some(name) => Err(Error::invalid_request(format!(
+                    "The filter's \"{name}\" can't be empty"
+                ))),
+                None => Ok(self),
+            },
+            _ => Ok(self),
+        }
+    }
+}
+
+// POST /bulk/comments/{action}
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum CommentAction {
+    delete,
+    approve,
+    reject,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommentBulk {
+    #[serde(flatten)]
+    pub selection: Selection<CommentFilter>,
+}
+
+// POST /bulk/blogs/{action}
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum BlogAction {
+    tag,
+    untag,
+    archive,
+    unarchive,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlogBulk {
+    #[serde(flatten)]
+    pub selection: Selection<BlogFilter>,
+    pub tag: Option<String>,
+}
+
+// what the store actually runs, tag and untag carry the tag they need
+#[derive(Debug, Clone)]
+#[allow(non_camel_case_types)]
+pub enum BlogOperation {
+    tag(String),
+    untag(String),
+    archive,
+    unarchive,
+}
+
+impl BlogAction {
+    pub fn with_tag(self, tag: Option<String>) -> Result<BlogOperation, Error> {
+        let tag = tag.map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty());
+        match (self, tag) {
+            (BlogAction::tag, Some(tag)) => Ok(BlogOperation::tag(tag)),
+            (BlogAction::untag, Some(tag)) => Ok(BlogOperation::untag(tag)),
+            (BlogAction::tag | BlogAction::untag, None) => Err(Error::invalid_request(
+                "Tagging needs a non-empty \"tag\"".to_string(),
+            )),
+            (BlogAction::archive, _) => Ok(BlogOperation::archive),
+            (BlogAction::unarchive, _) => Ok(BlogOperation::unarchive),
+        }
+    }
+}
+
+// POST /bulk/blogs/import
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportBlog {
+    pub image: Option<String>,
+    pub author: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlogImport {
+    pub blogs: Vec<ImportBlog>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum BulkStatus {
+    deleted,
+    approved,
+    rejected,
+    tagged,
+    untagged,
+    archived,
+    unarchived,
+    imported,
+    unchanged,
+    not_found,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkItem {
+    pub id: i64,
+    // set for comments, it's what the events need once the comment itself is gone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blog_id: Option<i64>,
+    pub status: BulkStatus,
+}
+
+// one entry per selected item, everything in it happened in a single transaction
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BulkReport {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<BulkItem>,
+}
+
+impl BulkReport {
+    pub fn push(&mut self, id: i64, status: BulkStatus) {
+        self.push_comment(id, None, status);
+    }
+
+    pub fn push_comment(&mut self, id: i64, blog_id: Option<i64>, status: BulkStatus) {
+        match status {
+            BulkStatus::not_found => self.failed += 1,
+            _ => self.succeeded += 1,
+        }
+        self.results.push(BulkItem {
+            id,
+            blog_id,
+            status,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comments(contains: &str) -> Selection<CommentFilter> {
+        Selection::filter(CommentFilter {
+            contains: Some(contains.to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn blank_filters_are_refused() {
+        assert!(matches!(comments("").validate(), Err(Error::invalid_request(_))));
+        assert!(matches!(comments(" \t").validate(), Err(Error::invalid_request(_))));
+        assert!(comments("spam").validate().is_ok());
+        let tag = Selection::filter(BlogFilter {
+            tag: Some(String::new()),
+            ..Default::default()
+        });
+        assert!(matches!(tag.validate(), Err(Error::invalid_request(_))));
+    }
+
+    #[test]
+    fn contains_matches_its_wildcards_literally() {
+        let filter = CommentFilter {
+            contains: Some(r"100%_off\now".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(filter.contains_pattern().as_deref(), Some(r"%100\%\_off\\now%"));
+        assert_eq!(CommentFilter::default().contains_pattern(), None);
+    }
+}
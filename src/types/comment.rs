@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::Type;
 #[derive(Debug, Clone, Deserialize, Serialize, Type)]
@@ -9,7 +10,9 @@ pub struct Comment {
     pub author: String,
     pub text: String,
     pub likes: i32,
-    pub date: String,
+    pub date: NaiveDateTime,
+    // None is still waiting for a moderator, Some(false) was rejected
+    pub approved: Option<bool>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Type)]
@@ -22,3 +25,22 @@ pub struct NewComment {
     pub likes: i32,
     pub date: String,
 }
+
+// where a comment is in moderation, stored in the approved column as NULL, TRUE and FALSE
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum Moderation {
+    pending,
+    approved,
+    rejected,
+}
+
+impl Moderation {
+    pub fn as_approved(self) -> Option<bool> {
+        match self {
+            Moderation::pending => None,
+            Moderation::approved => Some(true),
+            Moderation::rejected => Some(false),
+        }
+    }
+}
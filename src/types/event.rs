@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+use super::{blog::Blog, comment::Comment};
+
+// what gets pushed to the sse streams, `type` is also used as the sse event name.
+// texts are announced and not sent along, clients fetch them when they need them
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[allow(non_camel_case_types)]
+pub enum BlogEvent {
+    blog_published { blog: Blog },
+    blog_updated { blog: Blog },
+    blog_deleted { blog_id: i64 },
+    comment_added { comment: Comment },
+    comment_deleted { blog_id: i64, comment_id: i64 },
+    likes_changed { blog_id: i64, likes: i64 },
+    text_updated { blog_id: i64 },
+}
+
+impl BlogEvent {
+    pub fn blog_id(&self) -> i64 {
+        match self {
+            BlogEvent::blog_published { blog } | BlogEvent::blog_updated { blog } => blog.id.0,
+            BlogEvent::comment_added { comment } => comment.blog_id,
+            BlogEvent::blog_deleted { blog_id }
+            | BlogEvent::comment_deleted { blog_id, .. }
+            | BlogEvent::likes_changed { blog_id, .. }
+            | BlogEvent::text_updated { blog_id } => *blog_id,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlogEvent::blog_published { .. } => "blog_published",
+            BlogEvent::blog_updated { .. } => "blog_updated",
+            BlogEvent::blog_deleted { .. } => "blog_deleted",
+            BlogEvent::comment_added { .. } => "comment_added",
+            BlogEvent::comment_deleted { .. } => "comment_deleted",
+            BlogEvent::likes_changed { .. } => "likes_changed",
+            BlogEvent::text_updated { .. } => "text_updated",
+        }
+    }
+
+    // the subset webhooks can subscribe to, see types::webhook::WEBHOOK_EVENTS
+    pub fn webhook_event(&self) -> Option<&'static str> {
+        match self {
+            BlogEvent::blog_published { .. } => Some("blog.published"),
+            BlogEvent::blog_updated { .. } | BlogEvent::text_updated { .. } => Some("blog.updated"),
+            BlogEvent::blog_deleted { .. } => Some("blog.deleted"),
+            BlogEvent::comment_added { .. } => Some("comment.created"),
+            BlogEvent::comment_deleted { .. } | BlogEvent::likes_changed { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sse_name_is_the_json_type() {
+        let events = [
+            BlogEvent::comment_deleted { blog_id: 1, comment_id: 2 },
+            BlogEvent::likes_changed { blog_id: 1, likes: 3 },
+            BlogEvent::text_updated { blog_id: 1 },
+        ];
+        for event in events {
+            let json = serde_json::to_value(&event).unwrap();
+            assert_eq!(json["type"], event.name());
+            assert_eq!(json["blog_id"], event.blog_id());
+            // the other instances read it back from the NOTIFY payload
+            let parsed: BlogEvent = serde_json::from_value(json).unwrap();
+            assert_eq!(parsed.name(), event.name());
+        }
+    }
+}
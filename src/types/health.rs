@@ -0,0 +1,48 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+}
+
+// what the backend reports about itself, getting one at all means the database answered
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseHealth {
+    pub backend: &'static str,
+    pub pool: Option<PoolStats>,
+    pub migration: Option<i64>,
+    pub latest_migration: Option<i64>,
+}
+
+impl DatabaseHealth {
+    pub fn migrated(&self) -> bool {
+        self.migration >= self.latest_migration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(migration: Option<i64>, latest_migration: Option<i64>) -> DatabaseHealth {
+        DatabaseHealth {
+            backend: "sqlite",
+            pool: None,
+            migration,
+            latest_migration,
+        }
+    }
+
+    #[test]
+    fn behind_the_latest_migration_isnt_migrated() {
+        assert!(at(Some(4), Some(4)).migrated());
+        // after rolling back the binary the schema is newer than it knows
+        assert!(at(Some(5), Some(4)).migrated());
+        assert!(!at(Some(3), Some(4)).migrated());
+        assert!(!at(None, Some(1)).migrated());
+        // the in-memory store has no migrations at all
+        assert!(at(None, None).migrated());
+    }
+}
@@ -0,0 +1,46 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub id: i64,
+    pub kind: String,
+    pub payload: String,
+    // queued, running, done or dead
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobSchedule {
+    pub name: String,
+    pub cron: String,
+    pub kind: String,
+    pub next_run_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobCount {
+    pub status: String,
+    pub count: i64,
+}
+
+// GET /admin/jobs
+#[derive(Debug, Clone, Serialize)]
+pub struct JobOverview {
+    pub counts: Vec<JobCount>,
+    pub schedules: Vec<JobSchedule>,
+    pub jobs: Vec<JobRecord>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobQuery {
+    pub status: Option<String>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+}
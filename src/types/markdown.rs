@@ -0,0 +1,42 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+// one markdown file, already parsed. the title is the first heading of the text, there's no column for it
+#[derive(Debug, Clone, Serialize)]
+pub struct MarkdownPost {
+    // the path relative to the imported directory, with / separators
+    pub source: String,
+    pub slug: String,
+    pub image: Option<String>,
+    pub author: String,
+    // None keeps the date of an existing post and means now for a new one
+    pub date: Option<NaiveDateTime>,
+    // drafts are imported as archived posts
+    pub archived: bool,
+    pub tags: Vec<String>,
+    pub text: String,
+}
+
+impl MarkdownPost {
+    // an unchanged file is skipped, it doesn't even bump updated_at
+    pub fn checksum(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        format!("{:x}", Sha256::digest(json))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum MarkdownStatus {
+    created,
+    updated,
+    unchanged,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MarkdownImported {
+    pub source: String,
+    pub blog_id: i64,
+    pub status: MarkdownStatus,
+}
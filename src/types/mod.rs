@@ -1,3 +1,13 @@
+pub mod backup;
 pub mod blog;
+pub mod bulk;
 pub mod comment;
 pub mod custom_time;
+pub mod event;
+pub mod health;
+pub mod job;
+pub mod markdown;
+pub mod operation;
+pub mod patch;
+pub mod user;
+pub mod webhook;
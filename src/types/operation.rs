@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+
+// an edit of the whole text in the ot.js format: a positive number keeps that many characters,
+// a negative one deletes them and a string is inserted, e.g. [5, "abc", -2, 10].
+// lengths are utf-16 code units like javascript strings, apply converts them to the rust string
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "Vec<RawStep>", into = "Vec<RawStep>")]
+pub struct TextOperation {
+    steps: Vec<Step>,
+    // in utf-16 code units, not chars or bytes, so editors in the browser count the same way
+    base_len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(non_camel_case_types)]
+enum Step {
+    retain(usize),
+    insert(String),
+    delete(usize),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+#[allow(non_camel_case_types)]
+enum RawStep {
+    count(i64),
+    text(String),
+}
+
+impl TryFrom<Vec<RawStep>> for TextOperation {
+    type Error = String;
+
+    fn try_from(raw: Vec<RawStep>) -> Result<Self, Self::Error> {
+        let mut operation = TextOperation::default();
+        for step in raw {
+            match step {
+                RawStep::count(0) => return Err("An operation can't contain a 0".to_string()),
+                RawStep::count(count) if count > 0 => operation.retain(count as usize)?,
+                RawStep::count(count) => operation.delete(count.unsigned_abs() as usize)?,
+                RawStep::text(text) => operation.insert(&text),
+            }
+        }
+        Ok(operation)
+    }
+}
+
+impl From<TextOperation> for Vec<RawStep> {
+    fn from(operation: TextOperation) -> Self {
+        operation
+            .steps
+            .into_iter()
+            .map(|step| match step {
+                Step::retain(count) => RawStep::count(count as i64),
+                Step::insert(text) => RawStep::text(text),
+                Step::delete(count) => RawStep::count(-(count as i64)),
+            })
+            .collect()
+    }
+}
+
+impl TextOperation {
+    pub fn base_len(&self) -> usize {
+        self.base_len
+    }
+
+    // the builders merge neighbouring steps of the same kind, transform relies on that
+    fn retain(&mut self, count: usize) -> Result<(), String> {
+        if count == 0 {
+            return Ok(());
+        }
+        self.base_len = add(self.base_len, count)?;
+        match self.steps.last_mut() {
+            Some(Step::retain(last)) => *last = add(*last, count)?,
+            _ => self.steps.push(Step::retain(count)),
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.steps.last_mut() {
+            Some(Step::insert(last)) => last.push_str(text),
+            _ => self.steps.push(Step::insert(text.to_string())),
+        }
+    }
+
+    fn delete(&mut self, count: usize) -> Result<(), String> {
+        if count == 0 {
+            return Ok(());
+        }
+        self.base_len = add(self.base_len, count)?;
+        match self.steps.last_mut() {
+            Some(Step::delete(last)) => *last = add(*last, count)?,
+            _ => self.steps.push(Step::delete(count)),
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, text: &str) -> Result<String, String> {
+        if utf16_len(text) != self.base_len {
+            return Err("The operation doesn't match the length of the text".to_string());
+        }
+        let mut chars = text.chars();
+        let mut result = String::with_capacity(text.len());
+        for step in &self.steps {
+            match step {
+                Step::retain(count) => take(&mut chars, *count, Some(&mut result))?,
+                Step::insert(inserted) => result.push_str(inserted),
+                Step::delete(count) => take(&mut chars, *count, None)?,
+            }
+        }
+        Ok(result)
+    }
+
+    // rewrites two edits of the same text so they can be applied one after the other:
+    // apply(apply(text, a), b') == apply(apply(text, b), a'). inserts of `a` win ties
+    pub fn transform(a: &TextOperation, b: &TextOperation) -> Result<(Self, Self), String> {
+        if a.base_len != b.base_len {
+            return Err("Both operations have to start from the same text".to_string());
+        }
+        let mut a_prime = TextOperation::default();
+        let mut b_prime = TextOperation::default();
+        let mut a_steps = a.steps.iter().cloned();
+        let mut b_steps = b.steps.iter().cloned();
+        let mut step_a = a_steps.next();
+        let mut step_b = b_steps.next();
+
+        loop {
+            match (step_a.take(), step_b.take()) {
+                (None, None) => break,
+                (Some(Step::insert(text)), other) => {
+                    a_prime.insert(&text);
+                    b_prime.retain(utf16_len(&text))?;
+                    step_a = a_steps.next();
+                    step_b = other;
+                }
+                (other, Some(Step::insert(text))) => {
+                    a_prime.retain(utf16_len(&text))?;
+                    b_prime.insert(&text);
+                    step_a = other;
+                    step_b = b_steps.next();
+                }
+                (Some(Step::retain(x)), Some(Step::retain(y))) => {
+                    let count = x.min(y);
+                    a_prime.retain(count)?;
+                    b_prime.retain(count)?;
+                    (step_a, step_b) = advance(
+                        Step::retain(x),
+                        Step::retain(y),
+                        &mut a_steps,
+                        &mut b_steps,
+                    );
+                }
+                // both deleted the same characters, nothing is left to do for either
+                (Some(Step::delete(x)), Some(Step::delete(y))) => {
+                    (step_a, step_b) = advance(
+                        Step::delete(x),
+                        Step::delete(y),
+                        &mut a_steps,
+                        &mut b_steps,
+                    );
+                }
+                (Some(Step::delete(x)), Some(Step::retain(y))) => {
+                    a_prime.delete(x.min(y))?;
+                    (step_a, step_b) = advance(
+                        Step::delete(x),
+                        Step::retain(y),
+                        &mut a_steps,
+                        &mut b_steps,
+                    );
+                }
+                (Some(Step::retain(x)), Some(Step::delete(y))) => {
+                    b_prime.delete(x.min(y))?;
+                    (step_a, step_b) = advance(
+                        Step::retain(x),
+                        Step::delete(y),
+                        &mut a_steps,
+                        &mut b_steps,
+                    );
+                }
+                _ => return Err("The operations don't cover the same text".to_string()),
+            }
+        }
+        Ok((a_prime, b_prime))
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+// the counts go back out as json numbers, so they also have to fit an i64
+fn add(a: usize, b: usize) -> Result<usize, String> {
+    a.checked_add(b)
+        .filter(|sum| *sum <= i64::MAX as usize)
+        .ok_or_else(|| "The operation is longer than any text can be".to_string())
+}
+
+// moves `count` utf-16 units ahead in the text, copying them into `kept` when there is one
+fn take(chars: &mut std::str::Chars, count: usize, mut kept: Option<&mut String>) -> Result<(), String> {
+    let mut left = count;
+    while left > 0 {
+        let Some(c) = chars.next() else {
+            return Err("The operation is longer than the text".to_string());
+        };
+        left = left
+            .checked_sub(c.len_utf16())
+            .ok_or_else(|| "The operation splits a character in two".to_string())?;
+        if let Some(kept) = kept.as_mut() {
+            kept.push(c);
+        }
+    }
+    Ok(())
+}
+
+fn count(step: &Step) -> usize {
+    match step {
+        Step::retain(count) | Step::delete(count) => *count,
+        Step::insert(text) => utf16_len(text),
+    }
+}
+
+fn shorten(step: Step, by: usize) -> Step {
+    match step {
+        Step::retain(count) => Step::retain(count - by),
+        Step::delete(count) => Step::delete(count - by),
+        Step::insert(text) => Step::insert(text),
+    }
+}
+
+// consumes the part both steps cover and keeps whatever is left of the longer one
+fn advance(
+    a: Step,
+    b: Step,
+    a_steps: &mut impl Iterator<Item = Step>,
+    b_steps: &mut impl Iterator<Item = Step>,
+) -> (Option<Step>, Option<Step>) {
+    let (x, y) = (count(&a), count(&b));
+    match x.cmp(&y) {
+        std::cmp::Ordering::Less => (a_steps.next(), Some(shorten(b, x))),
+        std::cmp::Ordering::Equal => (a_steps.next(), b_steps.next()),
+        std::cmp::Ordering::Greater => (Some(shorten(a, y)), b_steps.next()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(json: &str) -> Result<TextOperation, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn lengths_are_utf16_units() {
+        // the emoji is two units in javascript, é is one
+        let text = "a😀é";
+        let keep_emoji = operation(r#"[1, -2, "b", 1]"#).unwrap();
+        assert_eq!(keep_emoji.base_len(), 4);
+        assert_eq!(keep_emoji.apply(text).unwrap(), "abé");
+
+        let split = operation(r#"[2, -1, 1]"#).unwrap();
+        assert!(split.apply(text).is_err());
+    }
+
+    #[test]
+    fn transform_counts_inserts_in_utf16_units() {
+        let text = "ab";
+        let a = operation(r#"[1, "😀", 1]"#).unwrap();
+        let b = operation(r#"[2, "!"]"#).unwrap();
+        let (a_prime, b_prime) = TextOperation::transform(&a, &b).unwrap();
+        let left = b_prime.apply(&a.apply(text).unwrap()).unwrap();
+        let right = a_prime.apply(&b.apply(text).unwrap()).unwrap();
+        assert_eq!(left, "a😀b!");
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn overflowing_lengths_are_rejected() {
+        let max = i64::MAX;
+        assert!(operation(&format!("[{max}, {max}]")).is_err());
+        assert!(operation(&format!("[{max}, -1]")).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Deserializer};
+
+// json merge patch (RFC 7396) needs to tell a missing field apart from an explicit null:
+// missing leaves the column alone, null clears it, a value replaces it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Patch<T> {
+    #[default]
+    Missing,
+    Null,
+    Value(T),
+}
+
+impl<T> Patch<T> {
+    // for columns that can be cleared: (was it supplied, new value)
+    pub fn nullable(self) -> (bool, Option<T>) {
+        match self {
+            Patch::Missing => (false, None),
+            Patch::Null => (true, None),
+            Patch::Value(value) => (true, Some(value)),
+        }
+    }
+
+    // for NOT NULL columns, a null in the patch is a client error
+    pub fn required(self, field: &str) -> Result<Option<T>, String> {
+        match self {
+            Patch::Missing => Ok(None),
+            Patch::Null => Err(format!("{field} can't be null")),
+            Patch::Value(value) => Ok(Some(value)),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Patch<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Option::<T>::deserialize(deserializer)? {
+            Some(value) => Patch::Value(value),
+            None => Patch::Null,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, types::blog::BlogPatch};
+
+    fn patch(json: &str) -> Result<BlogPatch, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn missing_null_and_values_are_told_apart() {
+        let blog = patch(r#"{"image": null, "likes": 3}"#).unwrap();
+        assert_eq!(blog.image, Patch::Null);
+        assert_eq!(blog.author, Patch::Missing);
+        assert_eq!(blog.likes, Patch::Value(3));
+
+        let changes = blog.into_changes().unwrap();
+        assert!(changes.set_image && changes.image.is_none());
+        assert_eq!(changes.author, None);
+        assert_eq!(changes.likes, Some(3));
+        assert!(patch("{}").unwrap().into_changes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn required_columns_and_server_fields_are_refused() {
+        assert!(matches!(
+            patch(r#"{"author": null}"#).unwrap().into_changes(),
+            Err(Error::invalid_patch(_))
+        ));
+        assert!(patch(r#"{"id": 4}"#).is_err());
+        assert!(patch(r#"{"updated_at": "2024-01-01T00:00:00"}"#).is_err());
+    }
+}
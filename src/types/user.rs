@@ -0,0 +1,30 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+// authors write, editors also moderate and manage other people's posts, admins do everything
+pub const ROLES: &[&str] = &["author", "editor", "admin"];
+
+// the password hash stays in the database, it's never read back into one of these
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub role: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+// the password is already hashed, see commands::user
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub username: String,
+    pub password_hash: String,
+    pub role: String,
+}
+
+// what a sign in is checked against, it never leaves the server
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub role: String,
+    pub password_hash: String,
+}
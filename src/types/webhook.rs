@@ -0,0 +1,168 @@
+use std::net::IpAddr;
+
+use chrono::NaiveDateTime;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+pub const WEBHOOK_EVENTS: &[&str] = &[
+    "blog.published",
+    "blog.updated",
+    "blog.deleted",
+    "comment.created",
+];
+
+// the secret is write-only, it never shows up in a response
+#[derive(Debug, Clone, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: String,
+}
+
+impl NewWebhook {
+    pub fn validate(self) -> Result<Self, Error> {
+        if !(self.url.starts_with("http://") || self.url.starts_with("https://")) {
+            return Err(Error::invalid_request(
+                "The webhook url has to start with http:// or https://".to_string(),
+            ));
+        }
+        let host = Url::parse(&self.url).ok().and_then(|url| {
+            let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']');
+            Some(host.to_ascii_lowercase())
+        });
+        let Some(host) = host else {
+            return Err(Error::invalid_request(format!("{:?} isn't a valid url", self.url)));
+        };
+        let internal = match host.parse::<IpAddr>() {
+            Ok(ip) => is_internal(ip),
+            Err(_) => {
+                let domain = host.trim_end_matches('.');
+                domain == "localhost" || domain.ends_with(".localhost")
+            }
+        };
+        if internal {
+            return Err(Error::invalid_request(
+                "The webhook url can't point at a loopback or private address".to_string(),
+            ));
+        }
+        if self.secret.is_empty() {
+            return Err(Error::invalid_request(
+                "The webhook needs a secret to sign its deliveries".to_string(),
+            ));
+        }
+        if self.events.is_empty() {
+            return Err(Error::invalid_request(
+                "The webhook has to subscribe to at least one event".to_string(),
+            ));
+        }
+        match self
+            .events
+            .iter()
+            .find(|event| !WEBHOOK_EVENTS.contains(&event.as_str()))
+        {
+            Some(unknown) => Err(Error::invalid_request(format!(
+                "Unknown event {unknown:?}, expected one of {}",
+                WEBHOOK_EVENTS.join(", ")
+            ))),
+            None => Ok(self),
+        }
+    }
+}
+
+// loopback, private, link-local and the like: a webhook can't be pointed into our own network.
+// names are checked again once they're resolved, see store::webhooks
+pub fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // carrier-grade nat, 100.64.0.0/10
+                || (first == 100 && second & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local fc00::/7 and link-local fe80::/10
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    // pending, delivered or failed
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeliveryQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(url: &str) -> NewWebhook {
+        NewWebhook {
+            url: url.to_string(),
+            events: vec!["blog.published".to_string()],
+            secret: "shh".to_string(),
+        }
+    }
+
+    #[test]
+    fn internal_destinations_are_refused() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://2130706433/hook",
+            "http://localhost:8080/hook",
+            "http://api.localhost/hook",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.10/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "http://[fd00::1]/hook",
+            "http://0.0.0.0/hook",
+        ] {
+            assert!(webhook(url).validate().is_err(), "{url} was accepted");
+        }
+        assert!(webhook("https://hooks.example.com/blog").validate().is_ok());
+        assert!(webhook("https://93.184.216.34/blog").validate().is_ok());
+    }
+}
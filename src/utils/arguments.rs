@@ -1,4 +1,6 @@
-use clap::{command, value_parser, Arg, ArgMatches};
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
+
+use crate::types::user::ROLES;
 
 pub fn arguments() -> ArgMatches {
     command!().about("This is a web server for managing blog posts, text, and comments.")
@@ -9,21 +11,24 @@ pub fn arguments() -> ArgMatches {
           .short('d')
           .long("db-url")
           .aliases(["db", "url", "database", "psql", "dburl", "db_url"])
-          .help("a url that connects your postgres database to the server")
+          .help("a url that connects your database to the server - postgres://... or sqlite://blog.db")
+          .global(true)
   )
   .arg(
-      // -o or --open-port
-      Arg::new("set origin")
+      // -o or --origin, can be repeated
+      Arg::new("origins")
           .short('o')
-          .long("open-port")
-          .aliases(["origin", "open", "openport"])
-          .help("this argument exposes one port for the frontend to access the server (Default: 4446)")
-          .value_parser(value_parser!(u16))
+          .long("origin")
+          .aliases(["open-port", "open", "openport"])
+          .help("an origin the frontend is served from, like https://app.example.com or https://*.example.com. a bare port means http://localhost:<port> (Default: http://localhost:4446)")
+          .action(ArgAction::Append)
+          .global(true)
   )
   .arg(
       // --log
       Arg::new("log level").long("log")
       .help("assign the log level (Default: info) - options(debug, info, warn, error)")
+      .global(true)
   )
   .arg(
       // -p or --port or --server
@@ -33,13 +38,270 @@ pub fn arguments() -> ArgMatches {
           .alias("server")
           .help("expose a port for the server to listen to (Default: 4445)")
           .value_parser(value_parser!(u16))
+          .global(true)
+  )
+  .arg(
+    // --config-file
+    Arg::new("config file")
+      .long("config-file")
+      .help("the toml file to read the config from (Default: ./config/config.toml)")
+      .global(true)
   )
   .arg(
-    // --config
-    Arg::new("config")
-      .long("config")
-      .help("save your passing arguments into a toml file")
-      .value_parser(value_parser!(bool))
+    // --save-config
+    Arg::new("save config")
+      .long("save-config")
+      .help("write the effective config (file + environment + arguments) back into the config file")
+      .action(ArgAction::SetTrue)
+      .global(true)
+  )
+  .arg(
+    // --non-interactive
+    Arg::new("non interactive")
+      .long("non-interactive")
+      .help("never prompt, exit with an error code instead (the default when stdin isn't a terminal)")
+      .action(ArgAction::SetTrue)
+      .global(true)
+  )
+  .arg(
+    // --ephemeral
+    Arg::new("ephemeral")
+      .long("ephemeral")
+      .help("keep everything in memory instead of a database, nothing survives a restart")
+      .action(ArgAction::SetTrue)
+      .global(true)
+  )
+  .arg(
+    // --fixture
+    Arg::new("fixture")
+      .long("fixture")
+      .help("a json file to seed the in-memory store with (only with --ephemeral)")
+      .requires("ephemeral")
+      .global(true)
+  )
+  .subcommand(
+    Command::new("serve")
+      .about("run the web server, the same as passing no command at all")
+  )
+  .subcommand(
+    Command::new("migrate")
+      .about("manage the database schema")
+      .subcommand_required(true)
+      .subcommand(
+        Command::new("up")
+          .about("apply every migration that hasn't run yet")
+      )
+      .subcommand(
+        Command::new("down")
+          .about("revert the latest migration, or every one after --to")
+          .arg(
+            Arg::new("to")
+              .long("to")
+              .help("the version to go back to, 0 reverts everything")
+              .value_parser(value_parser!(i64))
+          )
+      )
+      .subcommand(
+        Command::new("status")
+          .about("show every migration and whether it's applied, pending or changed since")
+      )
+  )
+  .subcommand(
+    Command::new("post")
+      .about("manage blog posts")
+      .subcommand_required(true)
+      .subcommand(
+        Command::new("list")
+          .about("list the published posts, 10 per page")
+          .arg(
+            Arg::new("page")
+              .long("page")
+              .help("which page to show, starting at 1 (Default: all of them)")
+              .value_parser(value_parser!(i64))
+          )
+      )
+      .subcommand(
+        Command::new("publish")
+          .about("bring archived posts back")
+          .arg(post_ids())
+      )
+      .subcommand(
+        Command::new("unpublish")
+          .about("archive posts, they disappear from the listing but aren't deleted")
+          .arg(post_ids())
+      )
+      .subcommand(
+        Command::new("delete")
+          .about("delete posts together with their text and comments")
+          .arg(post_ids())
+      )
+  )
+  .subcommand(
+    Command::new("user")
+      .about("manage user accounts, the password is asked for (or read from stdin)")
+      .subcommand_required(true)
+      .subcommand(
+        Command::new("create")
+          .about("add a user")
+          .arg(username())
+          .arg(
+            Arg::new("role")
+              .long("role")
+              .help("what the user may do")
+              .value_parser(ROLES.to_vec())
+              .default_value("author")
+          )
+      )
+      .subcommand(
+        Command::new("reset-password")
+          .about("give a user a new password")
+          .arg(username())
+      )
+      .subcommand(
+        Command::new("grant")
+          .about("change a user's role")
+          .arg(username())
+          .arg(
+            Arg::new("role")
+              .help("the new role")
+              .required(true)
+              .value_parser(ROLES.to_vec())
+          )
+      )
+  )
+  .subcommand(
+    Command::new("comments")
+      .about("moderate comments")
+      .subcommand_required(true)
+      .subcommand(
+        Command::new("purge-spam")
+          .about("delete the comments a moderator rejected, pending comments are kept")
+          .arg(
+            Arg::new("blog")
+              .long("blog")
+              .help("only the comments on this post")
+              .value_parser(value_parser!(i64))
+          )
+          .arg(
+            Arg::new("author")
+              .long("author")
+              .help("only the comments by this author")
+          )
+          .arg(
+            Arg::new("contains")
+              .long("contains")
+              .help("only the comments containing this text (case insensitive)")
+          )
+      )
+  )
+  .subcommand(
+    Command::new("backup")
+      .about("write every post, text, comment, tag and user plus the media files into one .tar.gz")
+      .arg(
+        Arg::new("output")
+          .short('O')
+          .long("output")
+          .help("the archive to write (Default: backup-<date>-<time>.tar.gz)")
+      )
+      .arg(media_dir())
+  )
+  .subcommand(
+    Command::new("restore")
+      .about("load a backup into an empty database, the posts get new ids")
+      .arg(
+        Arg::new("archive")
+          .help("the .tar.gz written by backup")
+          .required(true)
+      )
+      .arg(media_dir())
+  )
+  .subcommand(
+    Command::new("import")
+      .about("bring posts in from elsewhere")
+      .subcommand_required(true)
+      .subcommand(
+        Command::new("markdown")
+          .about("import a directory of markdown files with yaml (---) or toml (+++) front matter, again updates them")
+          .arg(
+            Arg::new("dir")
+              .help("the directory, searched recursively for .md and .markdown files")
+              .required(true)
+          )
+          .arg(
+            Arg::new("author")
+              .long("author")
+              .help("the author of the files whose front matter doesn't name one")
+          )
+      )
+  )
+  .subcommand(
+    Command::new("export")
+      .about("write the published posts as a static site: markdown, html pages, tag pages and an atom feed")
+      .arg(
+        Arg::new("dir")
+          .help("where the site goes, it has to be empty or missing")
+          .required(true)
+      )
+      .arg(
+        Arg::new("title")
+          .long("title")
+          .help("the site title on every page and in the feed (Default: Blog)")
+      )
+      .arg(
+        Arg::new("base url")
+          .long("base-url")
+          .help("where the site will be hosted, like https://blog.example.com. without it the feed links are relative")
+      )
+      .arg(media_dir())
+      .arg(
+        Arg::new("force")
+          .long("force")
+          .help("write into a directory that isn't empty, files with the same name are overwritten")
+          .action(ArgAction::SetTrue)
+      )
+  )
+  .subcommand(
+    Command::new("config")
+      .about("inspect the configuration")
+      .subcommand_required(true)
+      .subcommand(
+        Command::new("check")
+          .about("validate the effective config and print it with the secrets hidden")
+      )
+      .subcommand(
+        Command::new("show")
+          .about("print the effective config (file + environment + arguments) with the secrets hidden")
+      )
+      .subcommand(
+        Command::new("init")
+          .about("write a config file with the defaults")
+          .arg(
+            Arg::new("force")
+              .long("force")
+              .help("overwrite the config file if there already is one")
+              .action(ArgAction::SetTrue)
+          )
+      )
   )
   .get_matches()
 }
+
+fn media_dir() -> Arg {
+  Arg::new("media dir")
+    .long("media-dir")
+    .help("where images that aren't urls live (Default: static/dist)")
+}
+
+fn post_ids() -> Arg {
+  Arg::new("ids")
+    .help("the ids of the posts")
+    .required(true)
+    .num_args(1..)
+    .value_parser(value_parser!(i64))
+}
+
+fn username() -> Arg {
+  Arg::new("username")
+    .help("the name the user signs in with")
+    .required(true)
+}
@@ -0,0 +1,115 @@
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{error::Error, store::Store};
+
+// the routes that change what the server does or sends out take http basic auth
+// from a user with the admin role, see `user create` and `user grant`
+pub async fn require_admin(
+    State(store): State<Store>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let (username, password) = basic_auth(request.headers()).ok_or(Error::unauthorized)?;
+    let Some(credentials) = store.credentials(&username).await? else {
+        return Err(Error::unauthorized);
+    };
+    // argon2 is slow on purpose, it doesn't get to hold up the other requests on this thread
+    let hash = credentials.password_hash;
+    let verified = tokio::task::spawn_blocking(move || verify(&password, &hash))
+        .await
+        .unwrap_or(false);
+    match (verified, credentials.role.as_str()) {
+        (false, _) => Err(Error::unauthorized),
+        (true, "admin") => Ok(next.run(request).await),
+        (true, _) => Err(Error::forbidden),
+    }
+}
+
+// "Authorization: Basic base64(username:password)"
+fn basic_auth(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+fn verify(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{
+        password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+        Argon2,
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{store::memory::MemoryStore, types::user::NewUser};
+
+    async fn app() -> Router {
+        let store = Store::from(MemoryStore::new());
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(b"correct horse", &salt)
+            .unwrap()
+            .to_string();
+        for (username, role) in [("root", "admin"), ("ada", "editor")] {
+            store
+                .create_user(NewUser {
+                    username: username.to_string(),
+                    password_hash: password_hash.clone(),
+                    role: role.to_string(),
+                })
+                .await
+                .unwrap();
+        }
+        Router::new()
+            .route("/admin", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(store.clone(), require_admin))
+            .with_state(store)
+    }
+
+    async fn status(credentials: Option<&str>) -> (StatusCode, bool) {
+        let mut request = Request::get("/admin");
+        if let Some(credentials) = credentials {
+            request = request.header(AUTHORIZATION, format!("Basic {}", STANDARD.encode(credentials)));
+        }
+        let response = app().await.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let challenged = response.headers().contains_key("www-authenticate");
+        (response.status(), challenged)
+    }
+
+    #[tokio::test]
+    async fn only_admins_get_through() {
+        assert_eq!(status(None).await, (StatusCode::UNAUTHORIZED, true));
+        assert_eq!(status(Some("root:wrong horse")).await, (StatusCode::UNAUTHORIZED, true));
+        assert_eq!(status(Some("nobody:correct horse")).await, (StatusCode::UNAUTHORIZED, true));
+        assert_eq!(status(Some("ada:correct horse")).await, (StatusCode::FORBIDDEN, false));
+        assert_eq!(status(Some("root:correct horse")).await, (StatusCode::OK, false));
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+
+use crate::{
+    error::Error,
+    store::Store,
+    types::{blog::Text, operation::TextOperation},
+};
+
+// how often an edited text is written back to the texts table while editors are connected.
+// these saves skip the events, readers and webhooks hear about a session once when it closes
+const PERSIST_EVERY: Duration = Duration::from_secs(5);
+
+// a client further behind than this has to reload the text
+const HISTORY: usize = 1000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Editor {
+    pub id: u64,
+    pub name: String,
+    pub cursor: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+#[allow(non_camel_case_types)]
+pub enum ClientMessage {
+    // `revision` is the last revision the client had seen when it made the edit
+    operation {
+        revision: u64,
+        operation: TextOperation,
+    },
+    cursor {
+        position: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+#[allow(non_camel_case_types)]
+pub enum ServerMessage {
+    snapshot {
+        you: u64,
+        revision: u64,
+        text: String,
+        editors: Vec<Editor>,
+    },
+    operation {
+        editor: u64,
+        revision: u64,
+        operation: TextOperation,
+    },
+    ack {
+        revision: u64,
+    },
+    joined {
+        editor: Editor,
+    },
+    left {
+        editor: u64,
+    },
+    cursor {
+        editor: u64,
+        position: Option<usize>,
+    },
+    error {
+        message: String,
+    },
+}
+
+#[derive(Debug)]
+struct Document {
+    text: String,
+    // the revision right before the oldest operation we still keep
+    history_start: u64,
+    history: VecDeque<TextOperation>,
+    editors: BTreeMap<u64, Editor>,
+    dirty: bool,
+    // saved quietly, but nobody was told yet
+    unannounced: bool,
+    has_text: bool,
+}
+
+impl Document {
+    fn revision(&self) -> u64 {
+        self.history_start + self.history.len() as u64
+    }
+}
+
+// one per blog that somebody is editing, the server's copy of the text is the source of truth
+#[derive(Debug)]
+pub struct EditSession {
+    blog_id: i64,
+    document: Mutex<Document>,
+    // every message carries the editor it came from, their own operation comes back as an ack
+    sender: broadcast::Sender<(u64, ServerMessage)>,
+}
+
+impl EditSession {
+    // brings an edit made at `revision` up to date with everything applied since and applies it
+    pub async fn receive(
+        &self,
+        editor: u64,
+        revision: u64,
+        operation: TextOperation,
+    ) -> Result<(), String> {
+        let mut document = self.document.lock().await;
+        if revision > document.revision() || revision < document.history_start {
+            return Err(format!(
+                "Revision {revision} is unknown, reload the text and try again"
+            ));
+        }
+
+        let mut operation = operation;
+        let skip = (revision - document.history_start) as usize;
+        for applied in document.history.iter().skip(skip) {
+            operation = TextOperation::transform(&operation, applied)?.0;
+        }
+        document.text = operation.apply(&document.text)?;
+        document.history.push_back(operation.clone());
+        if document.history.len() > HISTORY {
+            document.history.pop_front();
+            document.history_start += 1;
+        }
+        document.dirty = true;
+
+        // sent while holding the lock so every editor gets the operations in the same order
+        let revision = document.revision();
+        let _ = self.sender.send((
+            editor,
+            ServerMessage::operation {
+                editor,
+                revision,
+                operation,
+            },
+        ));
+        Ok(())
+    }
+
+    pub async fn cursor(&self, editor: u64, position: Option<usize>) {
+        let mut document = self.document.lock().await;
+        if let Some(stored) = document.editors.get_mut(&editor) {
+            stored.cursor = position;
+            let _ = self
+                .sender
+                .send((editor, ServerMessage::cursor { editor, position }));
+        }
+    }
+
+    // `announce` is the last save of a session, it goes through the evented store even when
+    // only the quiet saves before it changed anything
+    async fn persist(&self, store: &Store, announce: bool) {
+        let (text, has_text) = {
+            let mut document = self.document.lock().await;
+            let unannounced = announce && document.unannounced;
+            if !document.dirty && !unannounced {
+                return;
+            }
+            document.dirty = false;
+            (document.text.clone(), document.has_text)
+        };
+
+        let text = Text {
+            blog_id: self.blog_id,
+            text,
+        };
+        let saved = match has_text {
+            true => store.put_blog_text(text, self.blog_id, None).await,
+            false => store.post_blog_text(text, self.blog_id).await,
+        };
+
+        let mut document = self.document.lock().await;
+        match saved {
+            Ok(_) => {
+                document.has_text = true;
+                document.unannounced = !announce;
+            }
+            Err(_) => {
+                tracing::error!("Couldn't save the edited text of blog {}", self.blog_id);
+                document.dirty = true;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EditorHub {
+    sessions: Arc<Mutex<HashMap<i64, Arc<EditSession>>>>,
+    last_editor_id: Arc<AtomicU64>,
+    // the store without the events layer, for the periodic saves
+    quiet: Store,
+}
+
+pub struct Joined {
+    pub session: Arc<EditSession>,
+    pub editor: u64,
+    pub snapshot: ServerMessage,
+    pub updates: broadcast::Receiver<(u64, ServerMessage)>,
+}
+
+impl EditorHub {
+    pub fn new(quiet: Store) -> Self {
+        EditorHub {
+            sessions: Arc::default(),
+            last_editor_id: Arc::default(),
+            quiet,
+        }
+    }
+
+    // a rest write of the text would be overwritten by the next save of a session, so it's refused
+    // while one is open. the hub stays locked during the write so a session can't open halfway
+    pub async fn unless_editing<T>(
+        &self,
+        blog_id: i64,
+        write: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let sessions = self.sessions.lock().await;
+        if sessions.contains_key(&blog_id) {
+            return Err(Error::conflict(format!(
+                "The text of blog {blog_id} is being edited live, send the change through /blogs/{blog_id}/edit"
+            )));
+        }
+        write.await
+    }
+
+    pub async fn join(&self, store: &Store, blog_id: i64, name: String) -> Result<Joined, Error> {
+        let mut sessions = self.sessions.lock().await;
+        let session = match sessions.get(&blog_id) {
+            Some(session) => session.clone(),
+            None => {
+                let session = Arc::new(open_session(store, blog_id).await?);
+                tokio::spawn(persist_periodically(Arc::downgrade(&session), self.quiet.clone()));
+                sessions.insert(blog_id, session.clone());
+                session
+            }
+        };
+
+        let editor = Editor {
+            id: self.last_editor_id.fetch_add(1, Ordering::Relaxed) + 1,
+            name,
+            cursor: None,
+        };
+        let mut document = session.document.lock().await;
+        document.editors.insert(editor.id, editor.clone());
+        let updates = session.sender.subscribe();
+        let snapshot = ServerMessage::snapshot {
+            you: editor.id,
+            revision: document.revision(),
+            text: document.text.clone(),
+            editors: document.editors.values().cloned().collect(),
+        };
+        let _ = session
+            .sender
+            .send((editor.id, ServerMessage::joined { editor: editor.clone() }));
+        drop(document);
+
+        Ok(Joined {
+            session,
+            editor: editor.id,
+            snapshot,
+            updates,
+        })
+    }
+
+    // the last editor to leave closes the session, whatever is unsaved gets written right away
+    pub async fn leave(&self, store: &Store, session: &Arc<EditSession>, editor: u64) {
+        let mut sessions = self.sessions.lock().await;
+        let mut document = session.document.lock().await;
+        document.editors.remove(&editor);
+        let _ = session.sender.send((editor, ServerMessage::left { editor }));
+        let closed = document.editors.is_empty();
+        drop(document);
+
+        // still holding the hub so a new session can't load the text before it's saved
+        if closed {
+            sessions.remove(&session.blog_id);
+            session.persist(store, true).await;
+        }
+    }
+
+    // on shutdown, in case an editor's socket didn't get to leave on its own
+    pub async fn persist_all(&self, store: &Store) {
+        let sessions = self.sessions.lock().await;
+        for session in sessions.values() {
+            session.persist(store, true).await;
+        }
+    }
+}
+
+async fn open_session(store: &Store, blog_id: i64) -> Result<EditSession, Error> {
+    let (text, has_text) = match store.blog_text(blog_id).await {
+        Ok(text) => (text.text, true),
+        // a blog without a text yet starts empty, the first save creates it
+        Err(Error::db_query_error(sqlx::Error::RowNotFound)) => {
+            store.get_single_blog(blog_id).await?;
+            (String::new(), false)
+        }
+        Err(e) => return Err(e),
+    };
+    let (sender, _) = broadcast::channel(256);
+    Ok(EditSession {
+        blog_id,
+        document: Mutex::new(Document {
+            text,
+            history_start: 0,
+            history: VecDeque::new(),
+            editors: BTreeMap::new(),
+            dirty: false,
+            unannounced: false,
+            has_text,
+        }),
+        sender,
+    })
+}
+
+// stops on its own once the session is closed and dropped
+async fn persist_periodically(session: Weak<EditSession>, store: Store) {
+    loop {
+        tokio::time::sleep(PERSIST_EVERY).await;
+        let Some(session) = session.upgrade() else {
+            break;
+        };
+        session.persist(&store, false).await;
+    }
+}
@@ -0,0 +1,109 @@
+use axum::{
+    http::{
+        header::{ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDateTime};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+
+// the etag is a hash of the json we send, so it changes whenever any field of the response does
+pub fn etag<T: Serialize>(value: &T) -> String {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    let digest = format!("{:x}", Sha256::digest(&body));
+    format!("\"{}\"", &digest[..32])
+}
+
+// http dates have no sub-second part and our timestamps are stored in utc
+pub fn http_date(date: &NaiveDateTime) -> String {
+    date.and_utc().format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn candidates(header: &HeaderValue) -> Vec<&str> {
+    match header.to_str() {
+        Ok(header) => header.split(',').map(str::trim).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// If-None-Match compares weakly, W/"x" and "x" are the same version (RFC 9110 13.1.2)
+fn weak_match(header: &HeaderValue, etag: &str) -> bool {
+    candidates(header).into_iter().any(|candidate| {
+        candidate == "*" || candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+    })
+}
+
+// If-Match compares strongly, a weak tag never matches (RFC 9110 13.1.1)
+fn strong_match(header: &HeaderValue, etag: &str) -> bool {
+    candidates(header).into_iter().any(|candidate| {
+        candidate == "*" || (!candidate.starts_with("W/") && !etag.starts_with("W/") && candidate == etag)
+    })
+}
+
+// If-None-Match wins over If-Modified-Since when both are sent (RFC 9110 13.2.2)
+pub fn is_not_modified(
+    headers: &HeaderMap,
+    etag: &str,
+    last_modified: Option<&NaiveDateTime>,
+) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        return weak_match(if_none_match, etag);
+    }
+    match (headers.get(IF_MODIFIED_SINCE), last_modified) {
+        (Some(since), Some(last_modified)) => since
+            .to_str()
+            .ok()
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .is_some_and(|since| last_modified.and_utc().timestamp() <= since.timestamp()),
+        _ => false,
+    }
+}
+
+// the If-Match header of a write, the store checks it against the row it has locked for the write
+#[derive(Debug, Clone)]
+pub struct IfMatch(HeaderValue);
+
+impl IfMatch {
+    // no If-Match means the client doesn't care which version it overwrites
+    pub fn from_headers(headers: &HeaderMap) -> Option<IfMatch> {
+        headers.get(IF_MATCH).cloned().map(IfMatch)
+    }
+
+    // even * needs something to match, so a missing row fails the precondition instead of being a 404
+    pub fn check<T: Serialize>(&self, current: Option<&T>) -> Result<(), Error> {
+        match current {
+            Some(current) if strong_match(&self.0, &etag(current)) => Ok(()),
+            _ => Err(Error::precondition_failed),
+        }
+    }
+}
+
+// answers with 304 when the client already has this version, otherwise with the json and its validators
+pub fn conditional_json<T: Serialize>(
+    headers: &HeaderMap,
+    value: T,
+    last_modified: Option<&NaiveDateTime>,
+) -> Response {
+    let etag = etag(&value);
+    let mut response = if is_not_modified(headers, &etag, last_modified) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        Json(value).into_response()
+    };
+    with_validators(&mut response, &etag, last_modified);
+    response
+}
+
+pub fn with_validators(response: &mut Response, etag: &str, last_modified: Option<&NaiveDateTime>) {
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    if let Some(last_modified) = last_modified.and_then(|date| HeaderValue::from_str(&http_date(date)).ok()) {
+        response.headers_mut().insert(LAST_MODIFIED, last_modified);
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+
+use super::setting::{CorsConfig, CorsRoute};
+
+// "https://app.example.com" or "https://*.example.com", the wildcard stands for one or more subdomains
+#[derive(Debug, Clone)]
+#[allow(non_camel_case_types)]
+enum OriginPattern {
+    exact(HeaderValue),
+    subdomains { scheme: String, suffix: String },
+}
+
+impl OriginPattern {
+    fn parse(origin: &str) -> Result<Self, String> {
+        let invalid = || format!("\"{origin}\" isn't an origin like https://example.com");
+        let (scheme, host) = origin.split_once("://").ok_or_else(invalid)?;
+        if !matches!(scheme, "http" | "https") || host.is_empty() || host.contains('/') {
+            return Err(invalid());
+        }
+        match host.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() && !domain.contains('*') => {
+                Ok(OriginPattern::subdomains {
+                    scheme: format!("{scheme}://"),
+                    suffix: format!(".{domain}"),
+                })
+            }
+            Some(_) => Err(invalid()),
+            None if host.contains('*') => Err(format!(
+                "\"{origin}\" can only have a wildcard in front, like https://*.example.com"
+            )),
+            None => HeaderValue::from_str(origin)
+                .map(OriginPattern::exact)
+                .map_err(|_| invalid()),
+        }
+    }
+
+    fn matches(&self, origin: &HeaderValue) -> bool {
+        match self {
+            OriginPattern::exact(allowed) => allowed == origin,
+            OriginPattern::subdomains { scheme, suffix } => {
+                let Ok(origin) = origin.to_str() else {
+                    return false;
+                };
+                origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|host| host.strip_suffix(suffix.as_str()))
+                    .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains('/'))
+            }
+        }
+    }
+}
+
+// a route override only has the fields it changes, the rest comes from the [cors] section
+struct Policy<'c> {
+    origins: &'c [String],
+    methods: &'c [String],
+    headers: &'c [String],
+    expose_headers: &'c [String],
+    credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl<'c> Policy<'c> {
+    fn of(cors: &'c CorsConfig) -> Self {
+        Policy {
+            origins: &cors.origins,
+            methods: &cors.methods,
+            headers: &cors.headers,
+            expose_headers: &cors.expose_headers,
+            credentials: cors.credentials,
+            max_age: cors.max_age,
+        }
+    }
+
+    fn with(&self, route: &'c CorsRoute) -> Self {
+        Policy {
+            origins: route.origins.as_deref().unwrap_or(self.origins),
+            methods: route.methods.as_deref().unwrap_or(self.methods),
+            headers: route.headers.as_deref().unwrap_or(self.headers),
+            expose_headers: route.expose_headers.as_deref().unwrap_or(self.expose_headers),
+            credentials: route.credentials.unwrap_or(self.credentials),
+            max_age: route.max_age.or(self.max_age),
+        }
+    }
+
+    // collects every mistake instead of stopping at the first one
+    fn layer(&self, errors: &mut Vec<String>) -> CorsLayer {
+        let any = |values: &[String]| values.iter().any(|value| value == "*");
+        if self.credentials {
+            for (name, values) in [
+                ("origins", self.origins),
+                ("methods", self.methods),
+                ("headers", self.headers),
+                ("expose_headers", self.expose_headers),
+            ] {
+                if any(values) {
+                    errors.push(format!("\"*\" in {name} can't be used with credentials"));
+                }
+            }
+        }
+
+        let patterns = self
+            .origins
+            .iter()
+            .filter(|origin| *origin != "*")
+            .filter_map(|origin| OriginPattern::parse(origin).map_err(|e| errors.push(e)).ok())
+            .collect::<Vec<_>>();
+        let origins = match any(self.origins) {
+            true => AllowOrigin::any(),
+            false => AllowOrigin::predicate(move |origin, _| {
+                patterns.iter().any(|pattern| pattern.matches(origin))
+            }),
+        };
+        let methods = match any(self.methods) {
+            true => AllowMethods::any(),
+            false => AllowMethods::list(parse_all::<Method>(self.methods, "method", errors)),
+        };
+        let headers = match any(self.headers) {
+            true => AllowHeaders::any(),
+            false => AllowHeaders::list(parse_all::<HeaderName>(self.headers, "header", errors)),
+        };
+        let expose_headers = match any(self.expose_headers) {
+            true => ExposeHeaders::any(),
+            false => ExposeHeaders::list(parse_all::<HeaderName>(
+                self.expose_headers,
+                "header",
+                errors,
+            )),
+        };
+
+        let layer = CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(expose_headers)
+            .allow_credentials(self.credentials);
+        match self.max_age {
+            Some(seconds) => layer.max_age(Duration::from_secs(seconds)),
+            None => layer,
+        }
+    }
+}
+
+fn parse_all<T: FromStr>(values: &[String], kind: &str, errors: &mut Vec<String>) -> Vec<T> {
+    values
+        .iter()
+        .filter_map(|value| match value.parse::<T>() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                errors.push(format!("\"{value}\" isn't a valid {kind}"));
+                None
+            }
+        })
+        .collect()
+}
+
+// the [cors] policy plus one per route override, the longest matching path wins
+#[derive(Debug, Clone)]
+pub struct CorsPolicies {
+    default: CorsLayer,
+    routes: Vec<(String, CorsLayer)>,
+}
+
+impl CorsPolicies {
+    pub fn from_config(cors: &CorsConfig) -> Result<Arc<Self>, Vec<String>> {
+        let mut errors = Vec::new();
+        let policy = Policy::of(cors);
+        let default = policy.layer(&mut errors);
+
+        let mut routes = Vec::new();
+        for route in &cors.routes {
+            if !route.path.starts_with('/') {
+                errors.push(format!("the route \"{}\" has to start with /", route.path));
+                continue;
+            }
+            let mut route_errors = Vec::new();
+            let layer = policy.with(route).layer(&mut route_errors);
+            errors.extend(
+                route_errors
+                    .into_iter()
+                    .map(|e| format!("{e} (route {})", route.path)),
+            );
+            routes.push((route.path.trim_end_matches('/').to_string(), layer));
+        }
+        routes.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+        match errors.is_empty() {
+            true => Ok(Arc::new(CorsPolicies { default, routes })),
+            false => Err(errors),
+        }
+    }
+
+    // "/webhooks" covers "/webhooks" and "/webhooks/3", not "/webhooks-old"
+    fn for_path(&self, path: &str) -> &CorsLayer {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| match path.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            })
+            .map(|(_, layer)| layer)
+            .unwrap_or(&self.default)
+    }
+}
+
+// swapped as a whole when the config is reloaded, requests in flight keep the policies they started with
+#[derive(Debug, Clone)]
+pub struct LiveCors {
+    policies: Arc<RwLock<Arc<CorsPolicies>>>,
+}
+
+impl LiveCors {
+    pub fn new(policies: Arc<CorsPolicies>) -> Self {
+        LiveCors {
+            policies: Arc::new(RwLock::new(policies)),
+        }
+    }
+
+    pub fn swap(&self, policies: Arc<CorsPolicies>) {
+        *self.policies.write().unwrap_or_else(|e| e.into_inner()) = policies;
+    }
+
+    fn current(&self) -> Arc<CorsPolicies> {
+        self.policies
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+pub async fn apply_cors(State(cors): State<LiveCors>, request: Request, next: Next) -> Response {
+    let cors = cors.current().for_path(request.uri().path()).layer(next);
+    match cors.oneshot(request).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allows(pattern: &str, origin: &str) -> bool {
+        OriginPattern::parse(pattern)
+            .unwrap()
+            .matches(&HeaderValue::from_str(origin).unwrap())
+    }
+
+    #[test]
+    fn wildcards_only_cover_subdomains() {
+        assert!(allows("https://app.example.com", "https://app.example.com"));
+        assert!(!allows("https://app.example.com", "http://app.example.com"));
+        assert!(allows("https://*.example.com", "https://a.b.example.com"));
+        assert!(!allows("https://*.example.com", "https://example.com"));
+        assert!(!allows("https://*.example.com", "https://evilexample.com"));
+        assert!(!allows("https://*.example.com", "http://app.example.com"));
+
+        for bad in ["example.com", "ftp://example.com", "https://", "https://a.*.com", "https://*."] {
+            assert!(OriginPattern::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn every_mistake_is_reported() {
+        let cors: CorsConfig = toml::from_str(
+            r#"
+            origins = ["*"]
+            methods = ["GET", "NOT A METHOD"]
+            credentials = true
+
+            [[routes]]
+            path = "webhooks"
+            "#,
+        )
+        .unwrap();
+        let errors = CorsPolicies::from_config(&cors).unwrap_err();
+        assert_eq!(errors.len(), 3, "{errors:?}");
+    }
+
+    #[test]
+    fn the_longest_route_prefix_wins() {
+        let cors: CorsConfig = toml::from_str(
+            r#"
+            [[routes]]
+            path = "/webhooks"
+            origins = ["*"]
+
+            [[routes]]
+            path = "/webhooks/github/"
+            max_age = 60
+            "#,
+        )
+        .unwrap();
+        let policies = CorsPolicies::from_config(&cors).unwrap();
+        let policy = |path| format!("{:?}", policies.for_path(path));
+        assert_eq!(policy("/webhooks/3"), format!("{:?}", policies.routes[1].1));
+        assert_eq!(policy("/webhooks/github/push"), format!("{:?}", policies.routes[0].1));
+        assert_eq!(policy("/webhooks-old"), format!("{:?}", policies.default));
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+
+use super::shutdown::Shutdown;
+
+// shared between the probes and the shutdown signal, cloned into every request
+#[derive(Debug, Clone)]
+pub struct Health {
+    started: Instant,
+    pub started_at: DateTime<Local>,
+    shutdown: Shutdown,
+}
+
+impl Health {
+    pub fn start(shutdown: Shutdown) -> Self {
+        Health {
+            started: Instant::now(),
+            started_at: Local::now(),
+            shutdown,
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // from here on /readyz fails so the load balancer stops sending us traffic
+    pub fn shutting_down(&self) -> bool {
+        self.shutdown.is_triggered()
+    }
+}
+
+// a full disk or a read-only mount only shows up when we actually write
+pub fn log_writable() -> Result<(), String> {
+    let probe = format!("log/.readyz-{}", std::process::id());
+    fs::write(&probe, b"ok").map_err(|e| e.to_string())?;
+    fs::remove_file(&probe).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_clone_sees_the_shutdown() {
+        let shutdown = Shutdown::default();
+        let request = Health::start(shutdown.clone()).clone();
+        assert!(!request.shutting_down());
+        shutdown.trigger();
+        assert!(request.shutting_down());
+    }
+}
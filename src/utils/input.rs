@@ -1,8 +1,11 @@
 use dialoguer::Input;
 
-pub fn db_input() -> String {
+use crate::error::AppError;
+
+// only asked when someone is at the terminal, see --non-interactive
+pub fn db_input() -> Result<String, AppError> {
     Input::new()
     .with_prompt("We couldn't connect to the database\nYou can change the config file and restart the App or ENTER the URL here:")
     .interact_text()
-    .unwrap()
+    .map_err(|_| AppError::no_db_url)
 }
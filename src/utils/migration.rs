@@ -1,21 +1,47 @@
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::Path;
-use sqlx::{migrate::Migrator, Error as SqlxError};
-use crate::store::Store;
-
-pub async fn migrate(pool: &Store) -> Result<(), SqlxError> {
-    let migrations_dir = Path::new("./migrations");
-    if !migrations_dir.exists() {
-        fs::create_dir_all(migrations_dir)
-            .map_err(|e| SqlxError::Configuration(e.into()))?;
-    }
-    let initial_migration = migrations_dir.join("01__initial.sql");
-    if !initial_migration.exists() {
-        let mut file = File::create(initial_migration)
-            .map_err(|e| SqlxError::Configuration(e.into()))?;
+use std::{borrow::Cow, collections::HashMap, future::Future, pin::Pin};
+
+use serde::Serialize;
+use sqlx::{
+    error::BoxDynError,
+    migrate::{AppliedMigration, Migration, MigrationSource, MigrationType, Migrator},
+    Error as SqlxError,
+};
+
+use crate::store::{Backend, Store};
+
+// one version, applied with `up` and reverted with `down`
+struct Step {
+    version: i64,
+    description: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
 
-        let default_sql = r#"
+// keep these two in sync, every table change has to land in both of them.
+// never edit a step that has shipped, the checksum of `up` is checked against what was applied
+const POSTGRES_MIGRATIONS: &[Step] = &[
+    Step { version: 1, description: "initial", up: POSTGRES_INITIAL, down: POSTGRES_INITIAL_DOWN },
+    Step { version: 2, description: "comment ids", up: POSTGRES_COMMENT_IDS, down: POSTGRES_COMMENT_IDS_DOWN },
+    Step { version: 3, description: "updated at", up: POSTGRES_UPDATED_AT, down: POSTGRES_UPDATED_AT_DOWN },
+    Step { version: 4, description: "moderation tags", up: POSTGRES_MODERATION_TAGS, down: POSTGRES_MODERATION_TAGS_DOWN },
+    // webhooks only exist on postgres, sqlite has no counterpart
+    Step { version: 5, description: "webhooks", up: POSTGRES_WEBHOOKS, down: POSTGRES_WEBHOOKS_DOWN },
+    Step { version: 6, description: "jobs", up: POSTGRES_JOBS, down: POSTGRES_JOBS_DOWN },
+    Step { version: 7, description: "users", up: POSTGRES_USERS, down: POSTGRES_USERS_DOWN },
+    Step { version: 8, description: "markdown sources", up: POSTGRES_MARKDOWN_SOURCES, down: POSTGRES_MARKDOWN_SOURCES_DOWN },
+    Step { version: 9, description: "moderation state", up: POSTGRES_MODERATION_STATE, down: POSTGRES_MODERATION_STATE_DOWN },
+];
+
+const SQLITE_MIGRATIONS: &[Step] = &[
+    Step { version: 1, description: "initial", up: SQLITE_INITIAL, down: SQLITE_INITIAL_DOWN },
+    Step { version: 3, description: "updated at", up: SQLITE_UPDATED_AT, down: SQLITE_UPDATED_AT_DOWN },
+    Step { version: 4, description: "moderation tags", up: SQLITE_MODERATION_TAGS, down: SQLITE_MODERATION_TAGS_DOWN },
+    Step { version: 7, description: "users", up: SQLITE_USERS, down: SQLITE_USERS_DOWN },
+    Step { version: 8, description: "markdown sources", up: SQLITE_MARKDOWN_SOURCES, down: SQLITE_MARKDOWN_SOURCES_DOWN },
+    Step { version: 9, description: "moderation state", up: SQLITE_MODERATION_STATE, down: SQLITE_MODERATION_STATE_DOWN },
+];
+
+const POSTGRES_INITIAL: &str = r#"
             CREATE TABLE IF NOT EXISTS blogs (
                 id BIGSERIAL PRIMARY KEY,
                 image TEXT,
@@ -40,12 +66,491 @@ pub async fn migrate(pool: &Store) -> Result<(), SqlxError> {
             );
         "#;
 
-        file.write_all(default_sql.trim().as_bytes())
-            .map_err(|e| SqlxError::Configuration(e.into()))?;
+const POSTGRES_INITIAL_DOWN: &str = r#"
+            DROP TABLE IF EXISTS comments;
+            DROP TABLE IF EXISTS texts;
+            DROP TABLE IF EXISTS blogs;
+        "#;
+
+// comment ids are read as i64 everywhere, sqlite already stores them as 64 bit integers
+const POSTGRES_COMMENT_IDS: &str = r#"
+            ALTER SEQUENCE comments_id_seq AS BIGINT;
+            ALTER TABLE comments ALTER COLUMN id TYPE BIGINT;
+        "#;
+
+const POSTGRES_COMMENT_IDS_DOWN: &str = r#"
+            ALTER TABLE comments ALTER COLUMN id TYPE INT;
+            ALTER SEQUENCE comments_id_seq AS INT;
+        "#;
+
+// edits bump updated_at, date stays the original publication date
+const POSTGRES_UPDATED_AT: &str = r#"
+            ALTER TABLE blogs ADD COLUMN updated_at TIMESTAMP;
+            UPDATE blogs SET updated_at = date;
+            ALTER TABLE blogs ALTER COLUMN updated_at SET DEFAULT CURRENT_TIMESTAMP;
+            ALTER TABLE blogs ALTER COLUMN updated_at SET NOT NULL;
+        "#;
+
+const POSTGRES_UPDATED_AT_DOWN: &str = r#"
+            ALTER TABLE blogs DROP COLUMN updated_at;
+        "#;
+
+// comments that were already public count as approved, new ones wait for moderation
+const POSTGRES_MODERATION_TAGS: &str = r#"
+            ALTER TABLE comments ADD COLUMN approved BOOLEAN NOT NULL DEFAULT TRUE;
+            ALTER TABLE comments ALTER COLUMN approved SET DEFAULT FALSE;
+            ALTER TABLE blogs ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+
+            CREATE TABLE IF NOT EXISTS blog_tags (
+                blog_id BIGINT NOT NULL REFERENCES blogs(id) ON DELETE CASCADE,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (blog_id, tag)
+            );
+            CREATE INDEX IF NOT EXISTS blog_tags_tag ON blog_tags (tag);
+        "#;
+
+const POSTGRES_MODERATION_TAGS_DOWN: &str = r#"
+            DROP TABLE IF EXISTS blog_tags;
+            ALTER TABLE blogs DROP COLUMN archived;
+            ALTER TABLE comments DROP COLUMN approved;
+        "#;
+
+// a delivery stays pending until it's delivered or runs out of attempts, that makes it the queue and the log
+const POSTGRES_WEBHOOKS: &str = r#"
+            CREATE TABLE IF NOT EXISTS webhooks (
+                id BIGSERIAL PRIMARY KEY,
+                url TEXT NOT NULL,
+                events TEXT[] NOT NULL,
+                secret TEXT NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id BIGSERIAL PRIMARY KEY,
+                webhook_id BIGINT NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+                event TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempts INT NOT NULL DEFAULT 0,
+                next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                last_status_code INT,
+                last_error TEXT,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                delivered_at TIMESTAMP
+            );
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_due
+                ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook
+                ON webhook_deliveries (webhook_id, id);
+        "#;
+
+const POSTGRES_WEBHOOKS_DOWN: &str = r#"
+            DROP TABLE IF EXISTS webhook_deliveries;
+            DROP TABLE IF EXISTS webhooks;
+        "#;
+
+// jobs are queued -> running -> done, or dead once they run out of attempts.
+// times are utc because the cron schedules are computed in rust
+const POSTGRES_JOBS: &str = r#"
+            CREATE TABLE IF NOT EXISTS jobs (
+                id BIGSERIAL PRIMARY KEY,
+                kind TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'queued',
+                attempts INT NOT NULL DEFAULT 0,
+                max_attempts INT NOT NULL DEFAULT 5,
+                run_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+                locked_until TIMESTAMP,
+                last_error TEXT,
+                created_at TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+                finished_at TIMESTAMP
+            );
+            CREATE INDEX IF NOT EXISTS jobs_due ON jobs (run_at) WHERE status IN ('queued', 'running');
+            CREATE INDEX IF NOT EXISTS jobs_status ON jobs (status, id);
+
+            CREATE TABLE IF NOT EXISTS job_schedules (
+                name TEXT PRIMARY KEY,
+                cron TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                max_attempts INT NOT NULL,
+                next_run_at TIMESTAMP NOT NULL
+            );
+        "#;
+
+const POSTGRES_JOBS_DOWN: &str = r#"
+            DROP TABLE IF EXISTS job_schedules;
+            DROP TABLE IF EXISTS jobs;
+        "#;
+
+// password_hash is a PHC string ("$argon2id$..."), it carries its own salt and parameters
+const POSTGRES_USERS: &str = r#"
+            CREATE TABLE IF NOT EXISTS users (
+                id BIGSERIAL PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                password_hash TEXT NOT NULL,
+                role TEXT NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+        "#;
+
+const POSTGRES_USERS_DOWN: &str = r#"
+            DROP TABLE IF EXISTS users;
+        "#;
+
+// which file an imported post came from, so importing the same directory again updates instead of duplicating
+const POSTGRES_MARKDOWN_SOURCES: &str = r#"
+            CREATE TABLE IF NOT EXISTS markdown_sources (
+                source TEXT PRIMARY KEY,
+                slug TEXT NOT NULL UNIQUE,
+                blog_id BIGINT NOT NULL UNIQUE REFERENCES blogs(id) ON DELETE CASCADE,
+                checksum TEXT NOT NULL
+            );
+        "#;
+
+const POSTGRES_MARKDOWN_SOURCES_DOWN: &str = r#"
+            DROP TABLE IF EXISTS markdown_sources;
+        "#;
+
+// approved NULL is pending, FALSE is rejected. nobody could reject before this, so every FALSE was pending
+const POSTGRES_MODERATION_STATE: &str = r#"
+            ALTER TABLE comments ALTER COLUMN approved DROP NOT NULL;
+            ALTER TABLE comments ALTER COLUMN approved DROP DEFAULT;
+            UPDATE comments SET approved = NULL WHERE NOT approved;
+        "#;
+
+const POSTGRES_MODERATION_STATE_DOWN: &str = r#"
+            UPDATE comments SET approved = FALSE WHERE approved IS NULL;
+            ALTER TABLE comments ALTER COLUMN approved SET DEFAULT FALSE;
+            ALTER TABLE comments ALTER COLUMN approved SET NOT NULL;
+        "#;
+
+const SQLITE_INITIAL: &str = r#"
+            CREATE TABLE IF NOT EXISTS blogs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                image TEXT,
+                author TEXT NOT NULL,
+                date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                likes INTEGER NOT NULL DEFAULT 0,
+                bookmarks INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE TABLE IF NOT EXISTS texts (
+                blog_id INTEGER PRIMARY KEY REFERENCES blogs(id) ON DELETE CASCADE,
+                text TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS comments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                blog_id INTEGER NOT NULL REFERENCES blogs(id) ON DELETE CASCADE,
+                author TEXT NOT NULL,
+                text TEXT NOT NULL,
+                likes INTEGER NOT NULL DEFAULT 0,
+                date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+        "#;
+
+const SQLITE_INITIAL_DOWN: &str = r#"
+            DROP TABLE IF EXISTS comments;
+            DROP TABLE IF EXISTS texts;
+            DROP TABLE IF EXISTS blogs;
+        "#;
+
+// sqlite can't add a column with a CURRENT_TIMESTAMP default, so inserts set it themselves
+const SQLITE_UPDATED_AT: &str = r#"
+            ALTER TABLE blogs ADD COLUMN updated_at TIMESTAMP;
+            UPDATE blogs SET updated_at = date;
+        "#;
+
+const SQLITE_UPDATED_AT_DOWN: &str = r#"
+            ALTER TABLE blogs DROP COLUMN updated_at;
+        "#;
+
+// sqlite can't change a column default, new comments get approved = FALSE from the inserts
+const SQLITE_MODERATION_TAGS: &str = r#"
+            ALTER TABLE comments ADD COLUMN approved BOOLEAN NOT NULL DEFAULT TRUE;
+            ALTER TABLE blogs ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+
+            CREATE TABLE IF NOT EXISTS blog_tags (
+                blog_id INTEGER NOT NULL REFERENCES blogs(id) ON DELETE CASCADE,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (blog_id, tag)
+            );
+            CREATE INDEX IF NOT EXISTS blog_tags_tag ON blog_tags (tag);
+        "#;
+
+const SQLITE_MODERATION_TAGS_DOWN: &str = r#"
+            DROP TABLE IF EXISTS blog_tags;
+            ALTER TABLE blogs DROP COLUMN archived;
+            ALTER TABLE comments DROP COLUMN approved;
+        "#;
+
+const SQLITE_USERS: &str = r#"
+            CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL UNIQUE,
+                password_hash TEXT NOT NULL,
+                role TEXT NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+        "#;
+
+const SQLITE_USERS_DOWN: &str = r#"
+            DROP TABLE IF EXISTS users;
+        "#;
+
+const SQLITE_MARKDOWN_SOURCES: &str = r#"
+            CREATE TABLE IF NOT EXISTS markdown_sources (
+                source TEXT PRIMARY KEY,
+                slug TEXT NOT NULL UNIQUE,
+                blog_id INTEGER NOT NULL UNIQUE REFERENCES blogs(id) ON DELETE CASCADE,
+                checksum TEXT NOT NULL
+            );
+        "#;
+
+const SQLITE_MARKDOWN_SOURCES_DOWN: &str = r#"
+            DROP TABLE IF EXISTS markdown_sources;
+        "#;
+
+// sqlite can't drop a NOT NULL, so the table is rebuilt. the autoincrement counter moves over with it
+const SQLITE_MODERATION_STATE: &str = r#"
+            ALTER TABLE comments RENAME TO comments_old;
+            CREATE TABLE comments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                blog_id INTEGER NOT NULL REFERENCES blogs(id) ON DELETE CASCADE,
+                author TEXT NOT NULL,
+                text TEXT NOT NULL,
+                likes INTEGER NOT NULL DEFAULT 0,
+                date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                approved BOOLEAN
+            );
+            INSERT INTO comments (id, blog_id, author, text, likes, date, approved)
+                SELECT id, blog_id, author, text, likes, date, CASE WHEN approved THEN TRUE END FROM comments_old;
+            DELETE FROM sqlite_sequence WHERE name = 'comments';
+            UPDATE sqlite_sequence SET name = 'comments' WHERE name = 'comments_old';
+            DROP TABLE comments_old;
+        "#;
+
+const SQLITE_MODERATION_STATE_DOWN: &str = r#"
+            ALTER TABLE comments RENAME TO comments_old;
+            CREATE TABLE comments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                blog_id INTEGER NOT NULL REFERENCES blogs(id) ON DELETE CASCADE,
+                author TEXT NOT NULL,
+                text TEXT NOT NULL,
+                likes INTEGER NOT NULL DEFAULT 0,
+                date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                approved BOOLEAN NOT NULL DEFAULT TRUE
+            );
+            INSERT INTO comments (id, blog_id, author, text, likes, date, approved)
+                SELECT id, blog_id, author, text, likes, date, COALESCE(approved, FALSE) FROM comments_old;
+            DELETE FROM sqlite_sequence WHERE name = 'comments';
+            UPDATE sqlite_sequence SET name = 'comments' WHERE name = 'comments_old';
+            DROP TABLE comments_old;
+        "#;
+
+pub async fn migrate(store: &Store) -> Result<(), SqlxError> {
+    store.migrate().await
+}
+
+fn steps(backend: Backend) -> &'static [Step] {
+    match backend {
+        Backend::Postgres => POSTGRES_MIGRATIONS,
+        Backend::Sqlite => SQLITE_MIGRATIONS,
+    }
+}
+
+pub fn latest_version(backend: Backend) -> i64 {
+    steps(backend)
+        .iter()
+        .map(|step| step.version)
+        .max()
+        .unwrap_or_default()
+}
+
+// the migrations are compiled in, ./migrations isn't read or written anymore
+#[derive(Debug)]
+struct Embedded(Backend);
+
+impl MigrationSource<'static> for Embedded {
+    fn resolve(
+        self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Migration>, BoxDynError>> + Send + 'static>> {
+        // trimmed like the files they used to be written to, so the recorded checksums still match
+        let migrations = steps(self.0)
+            .iter()
+            .flat_map(|step| {
+                [
+                    (MigrationType::ReversibleUp, step.up),
+                    (MigrationType::ReversibleDown, step.down),
+                ]
+                .map(|(kind, sql)| {
+                    Migration::new(
+                        step.version,
+                        Cow::Borrowed(step.description),
+                        kind,
+                        Cow::Borrowed(sql.trim()),
+                        false,
+                    )
+                })
+            })
+            .collect();
+        Box::pin(async move { Ok(migrations) })
     }
+}
+
+pub async fn migrator(backend: Backend) -> Result<Migrator, SqlxError> {
+    Ok(Migrator::new(Embedded(backend)).await?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum MigrationState {
+    applied,
+    pending,
+    // applied, but the sql in this binary isn't what ran back then
+    drifted,
+    // applied by a newer binary, this one doesn't know it
+    unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+// every migration this binary knows about plus the ones the database knows and it doesn't
+pub fn migration_status(backend: Backend, applied: &[AppliedMigration]) -> Vec<MigrationStatus> {
+    let mut applied = applied
+        .iter()
+        .map(|migration| (migration.version, migration))
+        .collect::<HashMap<_, _>>();
+
+    let mut status = steps(backend)
+        .iter()
+        .map(|step| {
+            let checksum = Migration::new(
+                step.version,
+                Cow::Borrowed(step.description),
+                MigrationType::ReversibleUp,
+                Cow::Borrowed(step.up.trim()),
+                false,
+            )
+            .checksum;
+            let state = match applied.remove(&step.version) {
+                Some(migration) if migration.checksum == checksum => MigrationState::applied,
+                Some(_) => MigrationState::drifted,
+                None => MigrationState::pending,
+            };
+            MigrationStatus {
+                version: step.version,
+                description: step.description.to_string(),
+                state,
+            }
+        })
+        .collect::<Vec<_>>();
 
-    let migrator = Migrator::new(migrations_dir).await?;
-    migrator.run(&pool.connection).await?;
+    status.extend(applied.into_keys().map(|version| MigrationStatus {
+        version,
+        description: String::new(),
+        state: MigrationState::unknown,
+    }));
+    status.sort_by_key(|migration| migration.version);
+    status
+}
 
-    Ok(())
-}
\ No newline at end of file
+// what keeps the schema from matching this binary, empty when it does
+pub fn mismatches(status: &[MigrationStatus]) -> Vec<String> {
+    let versions = |state: MigrationState| {
+        status
+            .iter()
+            .filter(|migration| migration.state == state)
+            .map(|migration| migration.version.to_string())
+            .collect::<Vec<_>>()
+    };
+    [
+        (MigrationState::pending, "pending"),
+        (MigrationState::drifted, "changed after they were applied"),
+        (MigrationState::unknown, "applied by a newer version"),
+    ]
+    .into_iter()
+    .filter_map(|(state, what)| {
+        let versions = versions(state);
+        (!versions.is_empty()).then(|| format!("{what}: {}", versions.join(", ")))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(step: &Step, up: &str) -> AppliedMigration {
+        let checksum = Migration::new(
+            step.version,
+            Cow::Borrowed(step.description),
+            MigrationType::ReversibleUp,
+            Cow::Owned(up.trim().to_string()),
+            false,
+        )
+        .checksum;
+        AppliedMigration {
+            version: step.version,
+            checksum,
+        }
+    }
+
+    #[test]
+    fn changed_and_unknown_migrations_are_told_apart() {
+        let [initial, updated_at, ..] = SQLITE_MIGRATIONS else {
+            unreachable!()
+        };
+        let applied = [
+            applied(initial, initial.up),
+            applied(updated_at, "ALTER TABLE blogs ADD COLUMN edited_at TIMESTAMP;"),
+            AppliedMigration {
+                version: 99,
+                checksum: Cow::Borrowed(&[]),
+            },
+        ];
+        let status = migration_status(Backend::Sqlite, &applied);
+        let states = status
+            .iter()
+            .map(|migration| (migration.version, migration.state))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            [
+                (1, MigrationState::applied),
+                (3, MigrationState::drifted),
+                (4, MigrationState::pending),
+                (7, MigrationState::pending),
+                (8, MigrationState::pending),
+                (9, MigrationState::pending),
+                (99, MigrationState::unknown),
+            ]
+        );
+        assert_eq!(
+            mismatches(&status),
+            [
+                "pending: 4, 7, 8, 9",
+                "changed after they were applied: 3",
+                "applied by a newer version: 99",
+            ]
+        );
+    }
+
+    #[test]
+    fn an_up_to_date_schema_has_no_mismatches() {
+        let applied = POSTGRES_MIGRATIONS
+            .iter()
+            .map(|step| applied(step, step.up))
+            .collect::<Vec<_>>();
+        assert!(mismatches(&migration_status(Backend::Postgres, &applied)).is_empty());
+        assert_eq!(latest_version(Backend::Postgres), 9);
+    }
+}
@@ -1,5 +1,14 @@
 pub mod arguments;
+pub mod auth;
+pub mod collab;
+pub mod conditional;
+pub mod cors;
+pub mod health;
 pub mod migration;
 pub mod setting;
 pub mod input;
+pub mod metrics;
 pub mod panics;
+pub mod reload;
+pub mod shutdown;
+pub mod tls;
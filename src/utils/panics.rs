@@ -1,7 +1,8 @@
 
 
 
-fn handle_panics(error: String) {
+#[allow(dead_code)]
+fn handle_panics(_error: String) {
   // instead of logging the error - create a file and write the cause of the error that made
   // your app panic inside the file 
 }
\ No newline at end of file
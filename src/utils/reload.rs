@@ -0,0 +1,240 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use owo_colors::OwoColorize;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use super::{
+    cors::{CorsPolicies, LiveCors},
+    setting::{config_builder, log_filter, CliOverrides, ServerConfig, DEFAULT_CONFIG_FILE},
+    shutdown::Shutdown,
+};
+
+// how often the config file is checked for changes
+const WATCH_EVERY: Duration = Duration::from_secs(5);
+
+pub type LogReload = reload::Handle<EnvFilter, Registry>;
+
+// the config the server is running with right now, it changes when the file does
+#[derive(Clone)]
+pub struct LiveConfig {
+    config: Arc<RwLock<ServerConfig>>,
+}
+
+impl LiveConfig {
+    pub fn new(config: ServerConfig) -> Self {
+        LiveConfig {
+            config: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    pub fn current(&self) -> ServerConfig {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn replace(&self, config: ServerConfig) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+}
+
+// rebuilds the config the same way startup did (same file, same arguments) and applies what it can
+pub struct Reloader {
+    pub config_file: Option<String>,
+    pub cli: CliOverrides,
+    pub live: LiveConfig,
+    pub cors: LiveCors,
+    pub log: LogReload,
+}
+
+impl Reloader {
+    // on a change of the config file or on SIGHUP
+    pub fn spawn(self, shutdown: Shutdown) {
+        tokio::spawn(async move {
+            let path = self
+                .config_file
+                .clone()
+                .unwrap_or_else(|| DEFAULT_CONFIG_FILE.to_string());
+            let mut loaded = modified(&path);
+
+            #[cfg(unix)]
+            let mut sighup =
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(sighup) => Some(sighup),
+                    Err(e) => {
+                        tracing::error!("Couldn't listen for SIGHUP: {}", e);
+                        None
+                    }
+                };
+
+            loop {
+                #[cfg(unix)]
+                let hangup = async {
+                    match sighup.as_mut() {
+                        Some(sighup) => {
+                            sighup.recv().await;
+                        }
+                        None => std::future::pending().await,
+                    }
+                };
+                #[cfg(not(unix))]
+                let hangup = std::future::pending::<()>();
+
+                tokio::select! {
+                    _ = tokio::time::sleep(WATCH_EVERY) => {
+                        let current = modified(&path);
+                        if current == loaded {
+                            continue;
+                        }
+                        loaded = current;
+                    }
+                    _ = hangup => {
+                        loaded = modified(&path);
+                    }
+                    _ = shutdown.triggered() => break,
+                }
+                self.reload();
+            }
+        });
+    }
+
+    fn reload(&self) {
+        let config = match config_builder(self.config_file.as_deref(), self.cli.clone()) {
+            Ok(config) => config,
+            Err(e) => return rejected(vec![format!("{e:#}")]),
+        };
+        // nothing is applied unless everything is valid
+        let cors = match CorsPolicies::from_config(&config.cors) {
+            Ok(cors) => cors,
+            Err(errors) => {
+                return rejected(errors.into_iter().map(|e| format!("cors: {e}")).collect())
+            }
+        };
+
+        let old = self.live.current();
+        if let Err(e) = self.log.reload(EnvFilter::new(log_filter(&config.log_level))) {
+            return rejected(vec![format!("log_level: {e}")]);
+        }
+        self.cors.swap(cors);
+
+        let restart = needs_restart(&old, &config);
+        self.live.replace(config);
+
+        tracing::info!("Reloaded the config");
+        println!("{}", "Reloaded the config".bright_green());
+        if !restart.is_empty() {
+            tracing::warn!("Changed settings that need a restart: {}", restart.join(", "));
+            println!(
+                "{} {}",
+                "These only take effect after a restart:".bright_yellow(),
+                restart.join(", ")
+            );
+        }
+    }
+}
+
+fn rejected(errors: Vec<String>) {
+    tracing::error!("Kept the old config, the new one is invalid: {}", errors.join("; "));
+    println!("{}", "The new config is invalid, keeping the old one:".bright_red());
+    for error in errors {
+        println!("  {error}");
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(Path::new(path))
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+// log_level, cors and shutdown_timeout are picked up live, everything else is read once at startup
+fn needs_restart(old: &ServerConfig, new: &ServerConfig) -> Vec<&'static str> {
+    let mut restart = Vec::new();
+    if old.db_url != new.db_url {
+        restart.push("db_url");
+    }
+    if old.server_port != new.server_port {
+        restart.push("server_port");
+    }
+    if old.cache_ttl != new.cache_ttl {
+        restart.push("cache_ttl");
+    }
+    if old.cache_size != new.cache_size {
+        restart.push("cache_size");
+    }
+    if old.auto_migrate != new.auto_migrate {
+        restart.push("auto_migrate");
+    }
+    if old.connect != new.connect {
+        restart.push("connect");
+    }
+    if old.db_url_file != new.db_url_file {
+        restart.push("db_url_file");
+    }
+    if old.tls != new.tls {
+        restart.push("tls");
+    }
+    restart
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::setting::{LogLevel, TlsConfig};
+
+    #[test]
+    fn an_invalid_config_keeps_the_old_one() {
+        let folder = std::env::temp_dir().join(format!("blog-reload-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let config_file = folder.join("config.toml");
+        fs::write(&config_file, "cache_ttl = 5\n").unwrap();
+
+        let config = ServerConfig::default();
+        let (_filter, log) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let reloader = Reloader {
+            config_file: config_file.to_str().map(String::from),
+            cli: CliOverrides::default(),
+            live: LiveConfig::new(config.clone()),
+            cors: LiveCors::new(CorsPolicies::from_config(&config.cors).unwrap()),
+            log,
+        };
+
+        reloader.reload();
+        assert_eq!(reloader.live.current().cache_ttl, 5);
+
+        fs::write(&config_file, "cache_ttl = 7\n[cors]\norigins = [\"not an origin\"]\n").unwrap();
+        reloader.reload();
+        assert_eq!(reloader.live.current().cache_ttl, 5);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn only_startup_settings_need_a_restart() {
+        let old = ServerConfig::default();
+        let mut new = old.clone();
+        new.log_level = LogLevel::debug;
+        new.shutdown_timeout += 5;
+        assert!(needs_restart(&old, &new).is_empty());
+
+        new.server_port = "4556".to_string();
+        new.tls = Some(TlsConfig {
+            cert_path: "cert.pem".to_string(),
+            key_path: "key.pem".to_string(),
+            min_version: Default::default(),
+            redirect_port: None,
+            hsts_max_age: 0,
+        });
+        assert_eq!(needs_restart(&old, &new), vec!["server_port", "tls"]);
+
+        // the same tls settings again aren't a change
+        let old = new.clone();
+        assert!(needs_restart(&old, &new).is_empty());
+    }
+}
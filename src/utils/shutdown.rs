@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+// flipped once when we get SIGINT/SIGTERM, everything long-running watches it to wind down
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    stopping: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (stopping, _) = watch::channel(false);
+        Shutdown {
+            stopping: Arc::new(stopping),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.stopping.subscribe()
+    }
+
+    // resolves right away when we're already shutting down
+    pub async fn triggered(&self) {
+        let mut stopping = self.stopping.subscribe();
+        let _ = stopping.wait_for(|stopping| *stopping).await;
+    }
+}
+
+// ctrl-c in a terminal, SIGTERM from docker/systemd/kubernetes
+pub async fn wait_for_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Couldn't listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Couldn't listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn waiters_wake_up_and_late_ones_dont_wait() {
+        let shutdown = Shutdown::default();
+        let mut stopping = shutdown.subscribe();
+        let waiting = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.triggered().await }
+        });
+        assert!(!shutdown.is_triggered());
+
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stopping.has_changed().unwrap() && *stopping.borrow_and_update());
+
+        // whatever starts after the signal shouldn't hang around waiting for another one
+        tokio::time::timeout(Duration::from_secs(1), shutdown.triggered())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    http::{header, uri::Authority, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::Redirect,
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use owo_colors::OwoColorize;
+use rustls::{
+    crypto::ring,
+    version::{TLS12, TLS13},
+    ServerConfig, SupportedProtocolVersion,
+};
+
+use super::{
+    setting::{TlsConfig, TlsVersion},
+    shutdown::Shutdown,
+};
+
+// how often the certificate files are checked for changes
+const WATCH_EVERY: Duration = Duration::from_secs(10);
+
+pub fn server_config(tls: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let cert_file = File::open(&tls.cert_path)
+        .with_context(|| format!("couldn't open the certificate {}", tls.cert_path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("couldn't read the certificate {}", tls.cert_path))?;
+    if certs.is_empty() {
+        bail!("there's no certificate in {}", tls.cert_path);
+    }
+
+    let key_file = File::open(&tls.key_path)
+        .with_context(|| format!("couldn't open the private key {}", tls.key_path))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .with_context(|| format!("couldn't read the private key {}", tls.key_path))?
+        .ok_or_else(|| anyhow!("there's no private key in {}", tls.key_path))?;
+
+    let versions: &[&SupportedProtocolVersion] = match tls.min_version {
+        TlsVersion::tls1_2 => &[&TLS13, &TLS12],
+        TlsVersion::tls1_3 => &[&TLS13],
+    };
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(versions)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("the certificate and the private key don't belong together")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn modified(tls: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert = fs::metadata(&tls.cert_path).and_then(|meta| meta.modified());
+    let key = fs::metadata(&tls.key_path).and_then(|meta| meta.modified());
+    cert.ok().zip(key.ok())
+}
+
+// polls instead of watching, renewals usually swap symlinks and that's what survives it.
+// new connections get the new certificate, open ones keep theirs
+pub fn watch_certificates(tls: TlsConfig, rustls: RustlsConfig, shutdown: Shutdown) {
+    tokio::spawn(async move {
+        let mut loaded = modified(&tls);
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(WATCH_EVERY) => {}
+                _ = shutdown.triggered() => break,
+            }
+            let current = modified(&tls);
+            if current == loaded {
+                continue;
+            }
+            // the cert and the key are written one after the other, a mismatch is retried next round
+            match server_config(&tls) {
+                Ok(config) => {
+                    rustls.reload_from_config(config);
+                    loaded = current;
+                    tracing::info!("Reloaded the TLS certificate");
+                    println!("{}", "Reloaded the TLS certificate".bright_green());
+                }
+                Err(e) => tracing::error!("Couldn't reload the TLS certificate: {:#}", e),
+            }
+        }
+    });
+}
+
+// Strict-Transport-Security for every https response
+pub fn hsts(tls: &TlsConfig) -> Option<HeaderValue> {
+    if tls.hsts_max_age == 0 {
+        return None;
+    }
+    HeaderValue::from_str(&format!("max-age={}; includeSubDomains", tls.hsts_max_age)).ok()
+}
+
+// everything that comes in over plain http is sent to the same path on https
+pub fn redirect_app(https_port: String) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        to_https(&headers, &uri, &https_port)
+    })
+}
+
+fn to_https(headers: &HeaderMap, uri: &Uri, https_port: &str) -> Result<Redirect, StatusCode> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let authority = match https_port {
+        "443" => host.host().to_string(),
+        port => format!("{}:{}", host.host(), port),
+    };
+    let path = uri.path_and_query().map(|path| path.as_str()).unwrap_or("/");
+    Ok(Redirect::permanent(&format!("https://{authority}{path}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    fn location(host: &str, uri: &str, https_port: &str) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_str(host).unwrap());
+        let response = to_https(&headers, &uri.parse().unwrap(), https_port)
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        response.headers()[header::LOCATION].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn redirects_keep_the_path_and_swap_the_port() {
+        assert_eq!(location("blog.example:80", "/blogs?page=2", "443"), "https://blog.example/blogs?page=2");
+        assert_eq!(location("localhost:3000", "/", "3443"), "https://localhost:3443/");
+        // without a host there is nowhere to redirect to
+        let missing = to_https(&HeaderMap::new(), &"/".parse().unwrap(), "443");
+        assert_eq!(missing.err(), Some(StatusCode::BAD_REQUEST));
+    }
+}